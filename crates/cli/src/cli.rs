@@ -1,34 +1,397 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    num::NonZeroU64,
+    path::PathBuf,
+};
+
+use recordin_common::{
+    config::{
+        Backpressure,
+        ColorMatrix,
+        ColorRange,
+        GraphicsSystem,
+        SegmentLimit,
+        SinkConfig,
+        SoundSystem,
+        StartDate,
+        TimeHook,
+        ToneMap,
+    },
+    geometry::{
+        Fit,
+        Rect,
+        ScaleAlgorithm,
+        Size,
+    },
+    images::{
+        FrameRange,
+        ImageFormat,
+    },
+    options::{
+        self,
+        OptionsError,
+    },
+    profile::Profile,
+};
+
 #[derive(Debug, Clone, clap::Parser)]
-#[command(version, about, long_about = None)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[clap(
+        short = 'c',
+        long,
+        help = "Path of profile file, defaults to recordin.toml if present"
+    )]
+    pub config: Option<PathBuf>,
+    #[clap(short = 'p', long, help = "Name of profile to use")]
+    pub profile: Option<String>,
     #[clap(short = 'r', long, help = "Desired FPS for program")]
-    pub fps: f64,
+    pub fps: Option<f64>,
     #[clap(flatten)]
     pub graphics: Graphics,
     #[clap(flatten)]
     pub sound: Sound,
     #[clap(alias = "venc", long, help = "Video encoder FFmpeg uses")]
     pub video_encoder: Option<String>,
-    #[clap(alias = "vopt", long, help = "Video encoder options")]
-    pub video_option: Option<String>,
+    #[clap(
+        alias = "vopt",
+        long,
+        help = "Video encoder options as key=value;key=value, quote or \\-escape special characters"
+    )]
+    pub video_option: Vec<String>,
+    #[clap(
+        alias = "pix-fmt",
+        long,
+        help = "Pixel format to encode in, e.g. yuv420p, yuv444p, yuv420p10, nv12, bgr0"
+    )]
+    pub pixel_format: Option<String>,
+    #[clap(long, help = "YUV matrix, bt601 or bt709 (default)")]
+    pub color_matrix: Option<ColorMatrix>,
+    #[clap(long, help = "YUV range, limited (default) or full")]
+    pub color_range: Option<ColorRange>,
+    #[clap(long, help = "Only encode this region of the frame, as WxH+X+Y")]
+    pub crop: Option<Rect>,
+    #[clap(long, help = "Scale video to a fixed size, as WxH")]
+    pub scale: Option<Size>,
+    #[clap(
+        long,
+        help = "How to fit frames of another aspect ratio into --scale, letterbox (default) or stretch"
+    )]
+    pub fit: Option<Fit>,
+    #[clap(
+        long,
+        help = "Scaling algorithm: neighbor, bilinear, bicubic (default), area, lanczos or spline"
+    )]
+    pub scale_algorithm: Option<ScaleAlgorithm>,
+    #[clap(
+        long,
+        overrides_with = "no_continuous",
+        help = "Keep one video file when the game resizes or recreates its swap chain"
+    )]
+    pub continuous: bool,
+    #[clap(
+        long,
+        overrides_with = "continuous",
+        help = "Undo continuous of a profile"
+    )]
+    pub no_continuous: bool,
+    #[clap(
+        long,
+        help = "Start a new video file every so many frames (1800f), virtual time (10min) or bytes (2GB)"
+    )]
+    pub segment: Option<SegmentLimit>,
     #[clap(short = 'v', long, help = "Path of video output file")]
     pub video_output: Option<String>,
+    #[clap(
+        long,
+        help = "Container of video output (mp4, mov, mkv, webm, nut, raw or any FFmpeg muxer), \
+            guessed from the file extension by default"
+    )]
+    pub container: Option<String>,
+    #[clap(
+        alias = "copt",
+        long,
+        help = "Video container options as key=value;key=value"
+    )]
+    pub container_option: Vec<String>,
+    #[clap(
+        alias = "aenc",
+        long,
+        help = "Audio encoder FFmpeg uses, defaults to wavpack"
+    )]
+    pub audio_encoder: Option<String>,
+    #[clap(
+        alias = "aopt",
+        long,
+        help = "Audio encoder options as key=value;key=value, quote or \\-escape special characters"
+    )]
+    pub audio_option: Vec<String>,
+    #[clap(
+        long,
+        help = "Audio sample rate in Hz, defaults to 48000 if the encoder supports it"
+    )]
+    pub audio_sample_rate: Option<u32>,
+    #[clap(
+        long,
+        value_parser = parse_bitrate,
+        help = "Audio bitrate in bits per second, k and M suffixes allowed"
+    )]
+    pub audio_bitrate: Option<u64>,
     #[clap(short = 'a', long, help = "Path of audio output file")]
     pub audio_output: Option<String>,
-    #[clap(short = 'A', long, help = "Merge audio to video")]
+    #[clap(
+        long,
+        help = "Container of audio output, guessed from the file extension by default"
+    )]
+    pub audio_container: Option<String>,
+    #[clap(
+        alias = "acopt",
+        long,
+        help = "Audio container options as key=value;key=value"
+    )]
+    pub audio_container_option: Vec<String>,
+    #[clap(
+        short = 'A',
+        long,
+        overrides_with = "no_merge_audio",
+        help = "Merge audio to video"
+    )]
     pub merge_audio: bool,
+    #[clap(
+        long,
+        overrides_with = "merge_audio",
+        help = "Undo merge-audio of a profile"
+    )]
+    pub no_merge_audio: bool,
+    #[clap(
+        long = "images",
+        help = "Write frames as images named by this pattern, e.g. frames/%06d.png"
+    )]
+    pub images_output: Option<String>,
+    #[clap(
+        long,
+        help = "Image format: png, exr, tga or raw, guessed from the extension by default"
+    )]
+    pub image_format: Option<ImageFormat>,
+    #[clap(
+        long,
+        help = "Only write images of these virtual frames, e.g. 600-1200"
+    )]
+    pub image_frames: Option<FrameRange>,
+    #[clap(long, help = "Only write every nth image")]
+    pub image_every: Option<NonZeroU64>,
+    #[clap(
+        long,
+        overrides_with = "no_recoverable",
+        help = "Write MP4 and MOV as fragments, so that a file cut short by a crash stays playable"
+    )]
+    pub recoverable: bool,
+    #[clap(
+        long,
+        overrides_with = "recoverable",
+        help = "Undo recoverable of a profile"
+    )]
+    pub no_recoverable: bool,
+    #[clap(
+        long,
+        overrides_with = "no_flip_vertical",
+        help = "Turn captured images upside down, for surfaces stored bottom row first"
+    )]
+    pub flip_vertical: bool,
+    #[clap(
+        long,
+        overrides_with = "flip_vertical",
+        help = "Undo flip-vertical of a profile"
+    )]
+    pub no_flip_vertical: bool,
+    #[clap(
+        long,
+        help = "What HDR games are recorded as: off to keep HDR10 for a 10-bit pixel format, \
+            or tone mapped to SDR by clip, reinhard or hable (default)"
+    )]
+    pub tone_map: Option<ToneMap>,
+    #[clap(
+        long,
+        help = "UTC date the game's clock starts at, as YYYY-MM-DD or YYYY-MM-DDTHH:MM:SS, \
+            so that recordings are reproducible; the real time when not given"
+    )]
+    pub start_date: Option<StartDate>,
+    #[clap(
+        long = "no-time-hook",
+        help = "Leave a time source in real time: interrupt-time, unbiased-interrupt-time \
+            or time-get-system-time, may be repeated, replacing those of the profile. \
+            QueryPerformanceCounter, GetTickCount and timeGetTime always follow virtual time"
+    )]
+    pub disabled_time_hooks: Vec<TimeHook>,
+    #[clap(
+        long,
+        help = "When the video output falls behind: block the game (default), drop frames, \
+            or grow=SIZE to buffer up to SIZE of frames"
+    )]
+    pub backpressure: Option<Backpressure>,
+    #[clap(
+        long = "sink",
+        help = "Also feed raw-video=PATH, raw-audio=PATH, video-pipe=TARGET, rawvideo-pipe=TARGET, \
+            audio-pipe=TARGET or null, may be repeated, replacing those of the profile. \
+            TARGET is a named pipe, exec:COMMAND, tcp:HOST:PORT or unix:PATH"
+    )]
+    pub sinks: Vec<SinkConfig>,
     #[clap(short = 'R', long, help = "")]
     pub target_regex: Option<String>,
-    #[clap(short = 'I', long = "aggressive")]
+    #[clap(short = 'I', long = "aggressive", overrides_with = "no_aggressive")]
     pub aggressive_infect: bool,
+    #[clap(
+        long,
+        overrides_with = "aggressive_infect",
+        help = "Undo aggressive of a profile"
+    )]
+    pub no_aggressive: bool,
     #[clap(short = 'T', long = "force-tick")]
     pub force_tick_threshold: Option<u64>,
-    #[clap(help = "Path of executable to start")]
-    pub executable: String,
+    #[clap(required = true, help = "Path of executable to start")]
+    pub executable: Option<String>,
     #[clap(last = true, help = "Arguments passed to executable")]
     pub exec_args: Vec<String>,
 }
 
+#[derive(Debug, Clone, clap::Subcommand)]
+pub enum Command {
+    #[clap(about = "List encoders linked into the loader")]
+    ListEncoders {
+        #[clap(long, conflicts_with = "audio", help = "Only list video encoders")]
+        video: bool,
+        #[clap(long, help = "Only list audio encoders")]
+        audio: bool,
+        #[clap(short = 'd', long, help = "Show private options of each encoder")]
+        details: bool,
+        #[clap(help = "Only list encoders whose name contains this")]
+        filter: Option<String>,
+    },
+    #[clap(about = "List output formats linked into the loader")]
+    ListFormats {
+        #[clap(help = "Only list formats whose name or extension contains this")]
+        filter: Option<String>,
+    },
+}
+
+impl Cli {
+    /// Settings given as flags, to be layered on top of the selected profile.
+    pub fn to_profile(&self) -> Result<Profile, FlagError> {
+        let graphics = if self.graphics.vulkan {
+            Some(GraphicsSystem::Vulkan)
+        } else if self.graphics.d3d11 {
+            Some(GraphicsSystem::D3D11)
+        } else {
+            None
+        };
+        let sound = self.sound.wasapi.then_some(SoundSystem::Wasapi);
+        Ok(Profile {
+            fps: self.fps,
+            graphics,
+            sound,
+            video_encoder: self.video_encoder.clone(),
+            video_options: parse_options("video-option", &self.video_option)?,
+            pixel_format: self.pixel_format.clone(),
+            color_matrix: self.color_matrix,
+            color_range: self.color_range,
+            crop: self.crop,
+            scale: self.scale,
+            fit: self.fit,
+            scale_algorithm: self.scale_algorithm,
+            continuous: switch(self.continuous, self.no_continuous),
+            segment: self.segment,
+            video_output: self.video_output.clone(),
+            video_container: self.container.clone(),
+            video_container_options: parse_options("container-option", &self.container_option)?,
+            audio_encoder: self.audio_encoder.clone(),
+            audio_options: parse_options("audio-option", &self.audio_option)?,
+            audio_sample_rate: self.audio_sample_rate,
+            audio_bitrate: self.audio_bitrate,
+            audio_output: self.audio_output.clone(),
+            audio_container: self.audio_container.clone(),
+            audio_container_options: parse_options(
+                "audio-container-option",
+                &self.audio_container_option,
+            )?,
+            merge_audio: switch(self.merge_audio, self.no_merge_audio),
+            images_output: self.images_output.clone(),
+            image_format: self.image_format,
+            image_frames: self.image_frames,
+            image_every: self.image_every,
+            recoverable: switch(self.recoverable, self.no_recoverable),
+            flip_vertical: switch(self.flip_vertical, self.no_flip_vertical),
+            tone_map: self.tone_map,
+            start_date: self.start_date,
+            disabled_time_hooks: self.disabled_time_hooks.clone(),
+            backpressure: self.backpressure,
+            sinks: self.sinks.clone(),
+            target_regex: self.target_regex.clone(),
+            aggressive: switch(self.aggressive_infect, self.no_aggressive),
+            force_tick: self.force_tick_threshold,
+        })
+    }
+}
+
+/// A `--flag` and its `--no-flag`, of which clap keeps only the last given.
+fn switch(on: bool, off: bool) -> Option<bool> {
+    if on {
+        Some(true)
+    } else if off {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+fn parse_options(
+    flag: &'static str,
+    values: &[String],
+) -> Result<BTreeMap<String, String>, FlagError> {
+    let mut parsed = BTreeMap::new();
+    for s in values {
+        options::parse_into(&mut parsed, s).map_err(|error| FlagError { flag, error })?;
+    }
+    Ok(parsed)
+}
+
+fn parse_bitrate(s: &str) -> Result<u64, String> {
+    let (number, scale) = match s.strip_suffix(['k', 'K']) {
+        Some(n) => (n, 1e3),
+        None => match s.strip_suffix('M') {
+            Some(n) => (n, 1e6),
+            None => (s, 1.),
+        },
+    };
+    match number.parse::<f64>() {
+        Ok(n) if n > 0. => Ok((n * scale).round() as u64),
+        _ => Err(format!("`{s}` is not a bitrate like 128000, 128k or 1.5M")),
+    }
+}
+
+/// An option string given to `--{flag}` that does not parse.
+#[derive(Debug, Clone)]
+pub struct FlagError {
+    pub flag: &'static str,
+    pub error: OptionsError,
+}
+
+impl fmt::Display for FlagError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid --{}: {}", self.flag, self.error)
+    }
+}
+
+impl std::error::Error for FlagError {}
+
 #[derive(Debug, Clone, clap::Args)]
 #[group(required = false, multiple = false)]
 pub struct Graphics {
@@ -0,0 +1,66 @@
+use recordin_common::abi::{
+    EncoderInfo,
+    Inventory,
+    MediaKind,
+};
+
+pub fn print_encoders(
+    inventory: &Inventory,
+    kind: Option<MediaKind>,
+    details: bool,
+    filter: Option<&str>,
+) {
+    for (k, title) in [
+        (MediaKind::Video, "Video encoders:"),
+        (MediaKind::Audio, "Audio encoders:"),
+    ] {
+        if kind.is_some_and(|kind| kind != k) {
+            continue;
+        }
+        println!("{title}");
+        let encoders = inventory
+            .encoders
+            .iter()
+            .filter(|e| e.kind == k)
+            .filter(|e| filter.is_none_or(|f| e.name.contains(f)));
+        for e in encoders {
+            print_encoder(e, details);
+        }
+        println!();
+    }
+}
+
+fn print_encoder(e: &EncoderInfo, details: bool) {
+    println!("  {:<24}{}", e.name, e.long_name);
+    if !e.pixel_formats.is_empty() {
+        println!("  {:<24}pixel formats: {}", "", e.pixel_formats.join(", "));
+    }
+    if !e.sample_formats.is_empty() {
+        println!(
+            "  {:<24}sample formats: {}",
+            "",
+            e.sample_formats.join(", ")
+        );
+    }
+    if details {
+        for o in &e.options {
+            println!("  {:<24}  {:<20}{}", "", o.name, o.help);
+            if !o.values.is_empty() {
+                println!("  {:<24}  {:<20}values: {}", "", "", o.values.join(", "));
+            }
+        }
+    }
+}
+
+pub fn print_formats(inventory: &Inventory, filter: Option<&str>) {
+    println!("Output formats:");
+    let muxers = inventory.muxers.iter().filter(|m| {
+        filter.is_none_or(|f| m.name.contains(f) || m.extensions.iter().any(|e| e.contains(f)))
+    });
+    for m in muxers {
+        println!("  {:<24}{}", m.name, m.long_name);
+        if !m.extensions.is_empty() {
+            println!("  {:<24}extensions: {}", "", m.extensions.join(", "));
+        }
+    }
+}
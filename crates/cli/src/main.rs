@@ -1,6 +1,9 @@
 use std::{
-    path::Path,
-    process::Command,
+    path::{
+        Path,
+        PathBuf,
+    },
+    process,
 };
 
 use libloading::{
@@ -8,22 +11,42 @@ use libloading::{
     Symbol,
 };
 use recordin_common::{
-    ENV_KEY_AGGRESSIVE,
-    ENV_KEY_AUDIO_OUTPUT,
-    ENV_KEY_FORCE_TICK_THRESHOLD,
-    ENV_KEY_FPS_F64_HEX,
-    ENV_KEY_GRAPHICS_SYSTEM,
+    ENV_KEY_CONFIG,
     ENV_KEY_IS_CLI,
-    ENV_KEY_SOUND_SYSTEM,
     ENV_KEY_TARGET_REGEX,
-    ENV_KEY_VIDEO_ARGS,
-    ENV_KEY_VIDEO_ENCODER,
-    ENV_KEY_VIDEO_OUTPUT,
+    abi::{
+        self,
+        Inventory,
+        InventoryFn,
+        MediaKind,
+        ProbeFn,
+        SYMBOL_INVENTORY,
+        SYMBOL_PROBE,
+    },
+    config::{
+        AudioConfig,
+        CONFIG_VERSION,
+        ImagesConfig,
+        RecordinConfig,
+        VideoConfig,
+    },
+    images::ImageFormat,
+    profile::{
+        DEFAULT_PROFILE_FILE,
+        Profile,
+        ProfileFile,
+    },
 };
 
-use crate::cli::Cli;
+use crate::cli::{
+    Cli,
+    Command,
+};
 
 mod cli;
+mod list;
+
+const DEFAULT_AUDIO_ENCODER: &str = "wavpack";
 
 fn main() -> color_eyre::Result<()> {
     color_eyre::install()?;
@@ -31,30 +54,70 @@ fn main() -> color_eyre::Result<()> {
         std::env::set_var(ENV_KEY_IS_CLI, "1");
     }
     let cli: Cli = clap::Parser::parse();
-    let executable_filename = AsRef::<Path>::as_ref(&cli.executable)
-        .file_name()
-        .ok_or(color_eyre::eyre::eyre!("Probably invalid executable path"))?
-        .to_string_lossy();
-    let executable_regex = regex::escape(&executable_filename);
-    let target_regex_v = cli.target_regex.as_deref().unwrap_or(&executable_regex);
-    if std::env::var_os(ENV_KEY_TARGET_REGEX).is_none() {
-        unsafe {
-            std::env::set_var(ENV_KEY_TARGET_REGEX, target_regex_v);
+    match &cli.command {
+        Some(Command::ListEncoders {
+            video,
+            audio,
+            details,
+            filter,
+        }) => {
+            let kind = if *video {
+                Some(MediaKind::Video)
+            } else if *audio {
+                Some(MediaKind::Audio)
+            } else {
+                None
+            };
+            let inventory = inventory(&load_loader()?)?;
+            list::print_encoders(&inventory, kind, *details, filter.as_deref());
+            return Ok(());
         }
-    }
-    if cli.aggressive_infect {
-        unsafe {
-            std::env::set_var(ENV_KEY_AGGRESSIVE, "1");
+        Some(Command::ListFormats { filter }) => {
+            let inventory = inventory(&load_loader()?)?;
+            list::print_formats(&inventory, filter.as_deref());
+            return Ok(());
         }
+        None => {}
     }
-    let mut fps = cli.fps;
-    if !(0.5..=3000.0).contains(&fps) {
-        fps = 60.0;
+    let executable = cli
+        .executable
+        .as_deref()
+        .ok_or(color_eyre::eyre::eyre!("Executable not given"))?;
+    let cli_profile = cli.to_profile()?;
+    let profile = load_profile(&cli)?.merge(cli_profile);
+    let config = build_config(executable, profile)?;
+    if let Some(graphics) = config.graphics {
+        println!("{} enabled", graphics.as_str());
     }
-    let fps_str = format!("{:x}", fps.to_bits());
+    if let Some(sound) = config.sound {
+        println!("{} enabled", sound.as_str());
+    }
+    let encoded = config.encode()?;
     unsafe {
-        std::env::set_var(ENV_KEY_FPS_F64_HEX, fps_str);
+        std::env::set_var(ENV_KEY_CONFIG, &encoded);
+    }
+    let loader_module = load_loader()?;
+    let probe: Symbol<ProbeFn> = unsafe { loader_module.get(SYMBOL_PROBE) }?;
+    let mut problem = String::new();
+    let ok = unsafe {
+        probe(
+            encoded.as_ptr(),
+            encoded.len(),
+            abi::write_to_string,
+            (&raw mut problem).cast(),
+        )
+    };
+    if !ok {
+        Err(color_eyre::eyre::eyre!("{problem}"))?
     }
+    process::Command::new(executable)
+        .args(&cli.exec_args)
+        .env_remove(ENV_KEY_IS_CLI)
+        .spawn()?;
+    Ok(())
+}
+
+fn load_loader() -> color_eyre::Result<Library> {
     let loader_module = unsafe { Library::new("recordin_loader") }?;
     let magic_symbol: Symbol<*const u64> = unsafe { loader_module.get("__MAGIC__") }?;
     assert_eq!(
@@ -62,47 +125,133 @@ fn main() -> color_eyre::Result<()> {
         1145141919810,
         "unexpected DLL loaded"
     );
-    if let Some(ffmpeg_encoder) = &cli.video_encoder {
-        unsafe {
-            std::env::set_var(ENV_KEY_VIDEO_ENCODER, ffmpeg_encoder);
-            if let Some(ffmpeg_args) = &cli.video_option {
-                std::env::set_var(ENV_KEY_VIDEO_ARGS, ffmpeg_args);
-            }
-            if let Some(ffmpeg_output) = &cli.video_output {
-                std::env::set_var(ENV_KEY_VIDEO_OUTPUT, ffmpeg_output);
-            }
-        }
+    let version_symbol: Symbol<*const u32> = unsafe { loader_module.get("__CONFIG_VERSION__") }?;
+    let loader_version = unsafe { **version_symbol };
+    if loader_version != CONFIG_VERSION {
+        Err(color_eyre::eyre::eyre!(
+            "Loader expects config version {loader_version} but CLI provides {CONFIG_VERSION}, \
+                please use CLI and loader from the same build"
+        ))?
     }
-    if let Some(audio_output) = &cli.audio_output {
-        unsafe {
-            std::env::set_var(ENV_KEY_AUDIO_OUTPUT, audio_output);
-        }
+    Ok(loader_module)
+}
+
+fn inventory(loader_module: &Library) -> color_eyre::Result<Inventory> {
+    let inventory: Symbol<InventoryFn> = unsafe { loader_module.get(SYMBOL_INVENTORY) }?;
+    let mut encoded = String::new();
+    unsafe {
+        inventory(abi::write_to_string, (&raw mut encoded).cast());
     }
-    if let Some(v) = cli.force_tick_threshold {
-        unsafe {
-            std::env::set_var(ENV_KEY_FORCE_TICK_THRESHOLD, format!("{v:X}"));
+    Inventory::decode(&encoded).map_err(|e| color_eyre::eyre::eyre!("Invalid inventory: {e}"))
+}
+
+fn load_profile(cli: &Cli) -> color_eyre::Result<Profile> {
+    let file = match &cli.config {
+        Some(path) => ProfileFile::load(path)?,
+        None if Path::new(DEFAULT_PROFILE_FILE).is_file() => {
+            ProfileFile::load(DEFAULT_PROFILE_FILE)?
         }
+        None => ProfileFile::default(),
+    };
+    Ok(file.select(cli.profile.as_deref())?)
+}
+
+fn build_config(executable: &str, profile: Profile) -> color_eyre::Result<RecordinConfig> {
+    let mut fps = profile.fps.ok_or(color_eyre::eyre::eyre!(
+        "FPS must be given by --fps or profile"
+    ))?;
+    if !(0.5..=3000.0).contains(&fps) {
+        fps = 60.0;
     }
-    if cli.graphics.vulkan {
-        println!("Vulkan enabled");
-        unsafe {
-            std::env::set_var(ENV_KEY_GRAPHICS_SYSTEM, "Vulkan");
-        }
-    } else if cli.graphics.d3d11 {
-        println!("D3D11 enabled");
-        unsafe {
-            std::env::set_var(ENV_KEY_GRAPHICS_SYSTEM, "D3D11");
-        }
+    let executable_filename = AsRef::<Path>::as_ref(executable)
+        .file_name()
+        .ok_or(color_eyre::eyre::eyre!("Probably invalid executable path"))?
+        .to_string_lossy();
+    let target_regex = match std::env::var(ENV_KEY_TARGET_REGEX) {
+        Ok(target_regex) => target_regex,
+        Err(_) => profile
+            .target_regex
+            .unwrap_or_else(|| regex::escape(&executable_filename)),
+    };
+    let video = match (profile.video_encoder, profile.video_output) {
+        (Some(encoder), Some(output)) => Some(VideoConfig {
+            encoder,
+            options: profile.video_options,
+            pixel_format: profile.pixel_format,
+            matrix: profile.color_matrix,
+            range: profile.color_range,
+            crop: profile.crop,
+            scale: profile.scale,
+            fit: profile.fit.unwrap_or_default(),
+            scale_algorithm: profile.scale_algorithm.unwrap_or_default(),
+            continuous: profile.continuous.unwrap_or_default(),
+            segment: profile.segment,
+            output: output.into(),
+            container: profile.video_container,
+            container_options: profile.video_container_options,
+        }),
+        (None, Some(_)) => Err(color_eyre::eyre::eyre!(
+            "Video output requires a video encoder"
+        ))?,
+        (_, None) => None,
+    };
+    let merge_audio = profile.merge_audio.unwrap_or_default();
+    if merge_audio && video.is_none() {
+        Err(color_eyre::eyre::eyre!(
+            "Merging audio requires a video output"
+        ))?
     }
-    if cli.sound.wasapi {
-        println!("WASAPI enabled");
-        unsafe {
-            std::env::set_var(ENV_KEY_SOUND_SYSTEM, "WASAPI");
-        }
+    if merge_audio && profile.audio_output.is_some() {
+        Err(color_eyre::eyre::eyre!(
+            "Merged audio is written into the video output, audio output must not be given"
+        ))?
     }
-    Command::new(&cli.executable)
-        .args(&cli.exec_args)
-        .env_remove(ENV_KEY_IS_CLI)
-        .spawn()?;
-    Ok(())
+    let audio = (merge_audio || profile.audio_output.is_some()).then(|| AudioConfig {
+        encoder: profile
+            .audio_encoder
+            .unwrap_or_else(|| DEFAULT_AUDIO_ENCODER.to_owned()),
+        options: profile.audio_options,
+        sample_rate: profile.audio_sample_rate,
+        bitrate: profile.audio_bitrate,
+        output: profile.audio_output.map(Into::into),
+        container: profile.audio_container,
+        container_options: profile.audio_container_options,
+    });
+    let images = profile
+        .images_output
+        .map(|output| {
+            let output = PathBuf::from(output);
+            let format = match profile.image_format {
+                Some(format) => format,
+                None => ImageFormat::from_path(&output).ok_or(color_eyre::eyre::eyre!(
+                    "Image format of {output:?} unknown, give it with --image-format"
+                ))?,
+            };
+            color_eyre::Result::<_>::Ok(ImagesConfig {
+                output,
+                format,
+                frames: profile.image_frames,
+                every: profile.image_every,
+            })
+        })
+        .transpose()?;
+    Ok(RecordinConfig {
+        target_regex: Some(target_regex),
+        aggressive: profile.aggressive.unwrap_or_default(),
+        force_tick_threshold: profile.force_tick,
+        graphics: profile.graphics,
+        sound: profile.sound,
+        merge_audio,
+        recoverable: profile.recoverable.unwrap_or_default(),
+        flip_vertical: profile.flip_vertical.unwrap_or_default(),
+        tone_map: profile.tone_map.unwrap_or_default(),
+        start_date: profile.start_date,
+        disabled_time_hooks: profile.disabled_time_hooks,
+        backpressure: profile.backpressure.unwrap_or_default(),
+        video,
+        audio,
+        images,
+        sinks: profile.sinks,
+        ..RecordinConfig::new(fps)
+    })
 }
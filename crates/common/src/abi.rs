@@ -0,0 +1,130 @@
+//! Functions exported by the loader DLL for the CLI to call before launching
+//! the target, so that both sides agree on names and signatures.
+
+use std::ffi::c_void;
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+/// Receives a chunk of UTF-8 text produced by the loader.
+pub type WriteFn = unsafe extern "C" fn(ctx: *mut c_void, data: *const u8, len: usize);
+
+/// Checks an encoded [`RecordinConfig`](crate::config::RecordinConfig) against
+/// the loader's ffmpeg build, writing a description of any problem. Returns
+/// `true` if recording with the config is expected to work.
+pub type ProbeFn = unsafe extern "C" fn(
+    config: *const u8,
+    config_len: usize,
+    write: WriteFn,
+    ctx: *mut c_void,
+) -> bool;
+
+pub const SYMBOL_PROBE: &str = "recordin_probe";
+
+/// Writes an encoded [`Inventory`] of the loader's ffmpeg build.
+pub type InventoryFn = unsafe extern "C" fn(write: WriteFn, ctx: *mut c_void);
+
+pub const SYMBOL_INVENTORY: &str = "recordin_inventory";
+
+/// Appends to the `String` behind `ctx`.
+///
+/// # Safety
+///
+/// `ctx` must point to a `String` and `data` to `len` bytes of UTF-8.
+pub unsafe extern "C" fn write_to_string(ctx: *mut c_void, data: *const u8, len: usize) {
+    unsafe {
+        let s = &mut *ctx.cast::<String>();
+        let bytes = std::slice::from_raw_parts(data, len);
+        s.push_str(&String::from_utf8_lossy(bytes));
+    }
+}
+
+/// Encoders and muxers available in the loader's ffmpeg build.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Inventory {
+    pub encoders: Vec<EncoderInfo>,
+    pub muxers: Vec<MuxerInfo>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Video,
+    Audio,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct EncoderInfo {
+    pub name: String,
+    pub long_name: String,
+    pub kind: MediaKind,
+    #[serde(default)]
+    pub pixel_formats: Vec<String>,
+    #[serde(default)]
+    pub sample_formats: Vec<String>,
+    #[serde(default)]
+    pub options: Vec<OptionInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct OptionInfo {
+    pub name: String,
+    pub help: String,
+    /// Named constants the option accepts, if any.
+    #[serde(default)]
+    pub values: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct MuxerInfo {
+    pub name: String,
+    pub long_name: String,
+    #[serde(default)]
+    pub extensions: Vec<String>,
+}
+
+impl Inventory {
+    pub fn encode(&self) -> Result<String, String> {
+        toml::to_string(self).map_err(|e| e.to_string())
+    }
+
+    pub fn decode(s: &str) -> Result<Self, String> {
+        toml::from_str(s).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inventory_round_trip() {
+        let inventory = Inventory {
+            encoders: vec![EncoderInfo {
+                name: "libx264".to_owned(),
+                long_name: "libx264 H.264 / AVC".to_owned(),
+                kind: MediaKind::Video,
+                pixel_formats: vec!["yuv420p".to_owned(), "nv12".to_owned()],
+                sample_formats: vec![],
+                options: vec![OptionInfo {
+                    name: "preset".to_owned(),
+                    help: "Set the encoding preset".to_owned(),
+                    values: vec![],
+                }],
+            }],
+            muxers: vec![MuxerInfo {
+                name: "matroska".to_owned(),
+                long_name: "Matroska".to_owned(),
+                extensions: vec!["mkv".to_owned()],
+            }],
+        };
+        let s = inventory.encode().unwrap();
+        assert_eq!(Inventory::decode(&s).unwrap(), inventory);
+    }
+}
@@ -0,0 +1,503 @@
+//! Surface formats of captured images and their conversion to packed RGB,
+//! tone mapping HDR content to SDR when asked to.
+
+use crate::{
+    config::ToneMap,
+    pixels::{
+        self,
+        Shuffle,
+    },
+};
+
+/// Pixel layout of a captured surface, sRGB variants included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SurfaceFormat {
+    Bgra8,
+    Rgba8,
+    /// 10 bits per channel in a little-endian `u32`, blue in the low bits.
+    Bgr10A2,
+    /// 10 bits per channel in a little-endian `u32`, red in the low bits.
+    Rgb10A2,
+    /// Half floats, red first.
+    Rgba16F,
+}
+
+impl SurfaceFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            SurfaceFormat::Rgba16F => 8,
+            _ => 4,
+        }
+    }
+
+    /// Whether it holds more than 8 bits per channel.
+    pub fn deep(self) -> bool {
+        !matches!(self, SurfaceFormat::Bgra8 | SurfaceFormat::Rgba8)
+    }
+}
+
+/// How the values of a surface relate to light.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Transfer {
+    /// sRGB encoded, the SDR everything assumes.
+    #[default]
+    Srgb,
+    /// Linear BT.709 with 1.0 at 80 nits and HDR beyond, scRGB.
+    Linear,
+    /// SMPTE ST 2084 encoded BT.2020, HDR10.
+    Pq,
+}
+
+/// Bits per channel of converted pixels, which are packed RGB, 16-bit
+/// channels little-endian.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Depth {
+    #[default]
+    Eight,
+    Sixteen,
+}
+
+impl Depth {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            Depth::Eight => 3,
+            Depth::Sixteen => 6,
+        }
+    }
+}
+
+/// Luminance tone mapping puts at SDR white, after BT.2408, for HDR10 and
+/// scRGB alike.
+const SDR_WHITE_NITS: f32 = 203.;
+
+/// Luminance scRGB 1.0 stands for.
+const SCRGB_UNIT_NITS: f32 = 80.;
+
+/// Peak luminance tone mapping fits into SDR white, as most HDR displays
+/// and the games targeting them top out around it.
+const PEAK_NITS: f32 = 1000.;
+
+const BT709_TO_BT2020: [[f32; 3]; 3] = [
+    [0.627_404, 0.329_283, 0.043_313],
+    [0.069_097, 0.919_541, 0.011_362],
+    [0.016_391, 0.088_013, 0.895_595],
+];
+
+const BT2020_TO_BT709: [[f32; 3]; 3] = [
+    [1.660_491, -0.587_641, -0.072_850],
+    [-0.124_551, 1.132_9, -0.008_349],
+    [-0.018_151, -0.100_579, 1.118_73],
+];
+
+/// Converts rows of a surface to packed RGB: 8-bit surfaces to 8 bits,
+/// deeper ones to 16 bits, HDR either tone mapped to sRGB or kept as
+/// HDR10.
+#[derive(Debug, Clone)]
+pub struct Converter {
+    format: SurfaceFormat,
+    kind: Kind,
+    transfer: Transfer,
+}
+
+#[derive(Debug, Clone)]
+enum Kind {
+    Shuffle(Shuffle),
+    /// 10-bit values widened as they are.
+    Widen,
+    /// Signal values through `decode` to linear light, then mixed by
+    /// `matrix`, tone mapped and encoded by `encode`.
+    Map {
+        decode: Decode,
+        scale: f32,
+        matrix: Option<[[f32; 3]; 3]>,
+        tone_map: ToneMap,
+        encode: Encode,
+    },
+}
+
+impl Converter {
+    pub fn new(format: SurfaceFormat, transfer: Transfer, tone_map: ToneMap) -> Self {
+        let (kind, transfer) = match (format, transfer) {
+            (SurfaceFormat::Bgra8, _) => (Kind::Shuffle(Shuffle::REVERSE), Transfer::Srgb),
+            (SurfaceFormat::Rgba8, _) => (Kind::Shuffle(Shuffle::DROP_LAST), Transfer::Srgb),
+            (SurfaceFormat::Bgr10A2 | SurfaceFormat::Rgb10A2, Transfer::Pq)
+                if tone_map == ToneMap::Off =>
+            {
+                (Kind::Widen, Transfer::Pq)
+            }
+            (SurfaceFormat::Bgr10A2 | SurfaceFormat::Rgb10A2, Transfer::Pq) => {
+                let kind = Kind::Map {
+                    decode: Decode::new(format, pq_to_linear),
+                    scale: 10000. / SDR_WHITE_NITS,
+                    matrix: Some(BT2020_TO_BT709),
+                    tone_map,
+                    encode: Encode::new(srgb_from_linear),
+                };
+                (kind, Transfer::Srgb)
+            }
+            (SurfaceFormat::Rgba16F, Transfer::Linear) if tone_map == ToneMap::Off => {
+                let kind = Kind::Map {
+                    decode: Decode::new(format, |v| v),
+                    scale: SCRGB_UNIT_NITS / 10000.,
+                    matrix: Some(BT709_TO_BT2020),
+                    tone_map: ToneMap::Clip,
+                    encode: Encode::new(linear_to_pq),
+                };
+                (kind, Transfer::Pq)
+            }
+            (SurfaceFormat::Rgba16F, Transfer::Linear) => {
+                let kind = Kind::Map {
+                    decode: Decode::new(format, |v| v),
+                    scale: SCRGB_UNIT_NITS / SDR_WHITE_NITS,
+                    matrix: None,
+                    tone_map,
+                    encode: Encode::new(srgb_from_linear),
+                };
+                (kind, Transfer::Srgb)
+            }
+            (SurfaceFormat::Rgba16F, _) => {
+                let kind = Kind::Map {
+                    decode: Decode::new(format, |v| v),
+                    scale: 1.,
+                    matrix: None,
+                    tone_map: ToneMap::Clip,
+                    encode: Encode::new(|v| v),
+                };
+                (kind, Transfer::Srgb)
+            }
+            // A linear 10-bit surface has too little precision to be
+            // anything but sRGB in practice.
+            _ => (Kind::Widen, Transfer::Srgb),
+        };
+        Self {
+            format,
+            kind,
+            transfer,
+        }
+    }
+
+    pub fn format(&self) -> SurfaceFormat {
+        self.format
+    }
+
+    pub fn depth(&self) -> Depth {
+        match self.kind {
+            Kind::Shuffle(_) => Depth::Eight,
+            _ => Depth::Sixteen,
+        }
+    }
+
+    /// Transfer of the converted pixels, sRGB unless HDR is kept as HDR10.
+    pub fn transfer(&self) -> Transfer {
+        self.transfer
+    }
+
+    /// Converts as many pixels as both `src` and `dst` hold.
+    pub fn convert_row(&self, src: &[u8], dst: &mut [u8]) {
+        let (src_px, _) = src.as_chunks::<4>();
+        let (dst_ch, _) = dst.as_chunks_mut::<2>();
+        match &self.kind {
+            Kind::Shuffle(shuffle) => pixels::pack_row(src, dst, *shuffle),
+            Kind::Widen => {
+                for (px, out) in src_px.iter().zip(dst_ch.chunks_exact_mut(3)) {
+                    let rgb = unpack_10(self.format, u32::from_le_bytes(*px));
+                    for (out, v) in out.iter_mut().zip(rgb) {
+                        // Repeats the top bits, so 1023 becomes 65535.
+                        *out = ((v << 6) | (v >> 4)).to_le_bytes();
+                    }
+                }
+            }
+            Kind::Map {
+                decode,
+                scale,
+                matrix,
+                tone_map,
+                encode,
+            } => {
+                let pixel = self.format.bytes_per_pixel() / 4;
+                for (px, out) in src_px.chunks_exact(pixel).zip(dst_ch.chunks_exact_mut(3)) {
+                    let mut rgb = decode.pixel(self.format, px).map(|v| v * scale);
+                    if let Some(m) = matrix {
+                        rgb = mul(m, rgb);
+                    }
+                    for (out, v) in out.iter_mut().zip(rgb) {
+                        *out = encode.get(tone_map.apply(v)).to_le_bytes();
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl ToneMap {
+    /// Maps linear light relative to SDR white into `0..=1`.
+    pub fn apply(self, v: f32) -> f32 {
+        let v = v.max(0.);
+        let white = PEAK_NITS / SDR_WHITE_NITS;
+        match self {
+            ToneMap::Off | ToneMap::Clip => v.min(1.),
+            // The extended form, reaching 1 at the peak.
+            ToneMap::Reinhard => (v * (1. + v / (white * white)) / (1. + v)).min(1.),
+            ToneMap::Hable => (hable(v) / hable(white)).min(1.),
+        }
+    }
+}
+
+/// John Hable's filmic curve from Uncharted 2.
+fn hable(v: f32) -> f32 {
+    let (a, b, c, d, e, f) = (0.15, 0.5, 0.1, 0.2, 0.02, 0.3);
+    (v * (a * v + c * b) + d * e) / (v * (a * v + b) + d * f) - e / f
+}
+
+fn mul(m: &[[f32; 3]; 3], v: [f32; 3]) -> [f32; 3] {
+    m.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+/// Red, green and blue of a 10-bit pixel.
+fn unpack_10(format: SurfaceFormat, px: u32) -> [u16; 3] {
+    let [low, mid, high] = [0, 10, 20].map(|shift| ((px >> shift) & 0x3ff) as u16);
+    match format {
+        SurfaceFormat::Bgr10A2 => [high, mid, low],
+        _ => [low, mid, high],
+    }
+}
+
+/// Signal values of a surface to linear light, looked up for 10-bit ones.
+#[derive(Debug, Clone)]
+enum Decode {
+    Table(Box<[f32; 1024]>),
+    Half(fn(f32) -> f32),
+}
+
+impl Decode {
+    fn new(format: SurfaceFormat, f: fn(f32) -> f32) -> Self {
+        match format {
+            SurfaceFormat::Rgba16F => Decode::Half(f),
+            _ => Decode::Table(Box::new(std::array::from_fn(|i| f(i as f32 / 1023.)))),
+        }
+    }
+
+    fn pixel(&self, format: SurfaceFormat, px: &[[u8; 4]]) -> [f32; 3] {
+        match self {
+            Decode::Table(table) => {
+                unpack_10(format, u32::from_le_bytes(px[0])).map(|v| table[v as usize])
+            }
+            Decode::Half(f) => {
+                let [r0, r1, g0, g1] = px[0];
+                let [b0, b1, ..] = px[1];
+                [[r0, r1], [g0, g1], [b0, b1]].map(|h| f(half_to_f32(u16::from_le_bytes(h))))
+            }
+        }
+    }
+}
+
+/// Linear light in `0..=1` to 16-bit signal values, looked up by the square
+/// root so that the darks, where both curves are steep, get most entries.
+#[derive(Debug, Clone)]
+struct Encode(Box<[u16]>);
+
+impl Encode {
+    const STEPS: usize = 1 << 14;
+
+    fn new(f: fn(f32) -> f32) -> Self {
+        let table = (0..=Self::STEPS)
+            .map(|i| {
+                let root = i as f32 / Self::STEPS as f32;
+                (f(root * root).clamp(0., 1.) * 65535.).round() as u16
+            })
+            .collect();
+        Self(table)
+    }
+
+    fn get(&self, v: f32) -> u16 {
+        let root = v.clamp(0., 1.).sqrt();
+        self.0[(root * Self::STEPS as f32).round() as usize]
+    }
+}
+
+pub fn half_to_f32(h: u16) -> f32 {
+    let sign = if h & 0x8000 != 0 { -1. } else { 1. };
+    let exp = ((h >> 10) & 0x1f) as i32;
+    let man = (h & 0x3ff) as f32;
+    match exp {
+        0 => sign * man * 2f32.powi(-24),
+        31 if man == 0. => sign * f32::INFINITY,
+        31 => f32::NAN,
+        _ => sign * (1. + man / 1024.) * 2f32.powi(exp - 15),
+    }
+}
+
+pub fn srgb_from_linear(v: f32) -> f32 {
+    if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1. / 2.4) - 0.055
+    }
+}
+
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.040_45 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+const PQ_M1: f32 = 2610. / 16384.;
+const PQ_M2: f32 = 2523. / 4096. * 128.;
+const PQ_C1: f32 = 3424. / 4096.;
+const PQ_C2: f32 = 2413. / 4096. * 32.;
+const PQ_C3: f32 = 2392. / 4096. * 32.;
+
+/// ST 2084 signal to linear light, 1.0 being 10000 nits.
+pub fn pq_to_linear(v: f32) -> f32 {
+    let p = v.max(0.).powf(1. / PQ_M2);
+    ((p - PQ_C1).max(0.) / (PQ_C2 - PQ_C3 * p)).powf(1. / PQ_M1)
+}
+
+/// Linear light, 1.0 being 10000 nits, to ST 2084 signal.
+pub fn linear_to_pq(v: f32) -> f32 {
+    let y = v.max(0.).powf(PQ_M1);
+    ((PQ_C1 + PQ_C2 * y) / (1. + PQ_C3 * y)).powf(PQ_M2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(converter: &Converter, src: &[u8]) -> Vec<u16> {
+        let pixels = src.len() / converter.format().bytes_per_pixel();
+        let mut dst = vec![0; pixels * converter.depth().bytes_per_pixel()];
+        converter.convert_row(src, &mut dst);
+        match converter.depth() {
+            Depth::Eight => dst.into_iter().map(u16::from).collect(),
+            Depth::Sixteen => {
+                let (dst, _) = dst.as_chunks::<2>();
+                dst.iter().map(|&b| u16::from_le_bytes(b)).collect()
+            }
+        }
+    }
+
+    fn half(v: f32) -> [u8; 2] {
+        // Exact for the values used here.
+        let exp = v.log2().floor() as i32;
+        let man = ((v / 2f32.powi(exp) - 1.) * 1024.) as u16;
+        ((((exp + 15) as u16) << 10) | man).to_le_bytes()
+    }
+
+    #[test]
+    fn eight_bit_channel_order() {
+        let src = [1, 2, 3, 4, 5, 6, 7, 8];
+        let bgra = Converter::new(SurfaceFormat::Bgra8, Transfer::Srgb, ToneMap::Hable);
+        assert_eq!(convert(&bgra, &src), [3, 2, 1, 7, 6, 5]);
+        let rgba = Converter::new(SurfaceFormat::Rgba8, Transfer::Pq, ToneMap::Off);
+        assert_eq!(convert(&rgba, &src), [1, 2, 3, 5, 6, 7]);
+        assert_eq!(rgba.transfer(), Transfer::Srgb);
+    }
+
+    #[test]
+    fn ten_bit_widened() {
+        let px = (1023u32 | 512 << 10 | 1 << 20 | 3 << 30).to_le_bytes();
+        let rgb = Converter::new(SurfaceFormat::Rgb10A2, Transfer::Srgb, ToneMap::Hable);
+        assert_eq!(rgb.depth(), Depth::Sixteen);
+        assert_eq!(convert(&rgb, &px), [65535, 0x8020, 0x40]);
+        let bgr = Converter::new(SurfaceFormat::Bgr10A2, Transfer::Pq, ToneMap::Off);
+        assert_eq!(convert(&bgr, &px), [0x40, 0x8020, 65535]);
+        assert_eq!(bgr.transfer(), Transfer::Pq);
+    }
+
+    #[test]
+    fn half_floats() {
+        assert_eq!(half_to_f32(0x3c00), 1.);
+        assert_eq!(half_to_f32(0xc000), -2.);
+        assert_eq!(half_to_f32(0x3555), 0.333_251_95);
+        assert_eq!(half_to_f32(0x0001), 2f32.powi(-24));
+        assert_eq!(half_to_f32(0x7c00), f32::INFINITY);
+        assert!(half_to_f32(0x7e00).is_nan());
+        assert_eq!(half_to_f32(u16::from_le_bytes(half(12.5))), 12.5);
+    }
+
+    #[test]
+    fn pq_curve() {
+        // 100 nits is about half the signal range.
+        assert!((linear_to_pq(0.01) - 0.508).abs() < 1e-3);
+        for v in [0., 1e-4, 0.01, 0.1, 0.5, 1.] {
+            assert!((pq_to_linear(linear_to_pq(v)) - v).abs() < 1e-4 * v.max(1e-2));
+        }
+    }
+
+    #[test]
+    fn srgb_curve() {
+        for v in [0., 0.002, 0.2, 0.5, 1.] {
+            assert!((srgb_to_linear(srgb_from_linear(v)) - v).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn tone_maps_reach_white_at_peak() {
+        let peak = PEAK_NITS / SDR_WHITE_NITS;
+        for tone_map in [ToneMap::Clip, ToneMap::Reinhard, ToneMap::Hable] {
+            assert!(tone_map.apply(0.).abs() < 1e-6, "{tone_map:?}");
+            assert!(tone_map.apply(-1.).abs() < 1e-6, "{tone_map:?}");
+            assert!((tone_map.apply(peak) - 1.).abs() < 1e-5, "{tone_map:?}");
+            assert_eq!(tone_map.apply(peak * 2.), 1.);
+            let mut last = 0.;
+            for i in 1..100 {
+                let v = tone_map.apply(i as f32 / 20.);
+                assert!(v >= last, "{tone_map:?} not monotonic");
+                last = v;
+            }
+        }
+    }
+
+    #[test]
+    fn scrgb_white() {
+        let white = [half(1.), half(1.), half(1.), half(1.)].concat();
+        let sdr = Converter::new(SurfaceFormat::Rgba16F, Transfer::Linear, ToneMap::Clip);
+        assert_eq!(sdr.transfer(), Transfer::Srgb);
+        // 80 nits is well below the SDR white of HDR content.
+        let want = (srgb_from_linear(SCRGB_UNIT_NITS / SDR_WHITE_NITS) * 65535.).round();
+        for v in convert(&sdr, &white) {
+            assert!((v as f32 - want).abs() <= 64., "{v} {want}");
+        }
+        let bright = [half(4.), half(4.), half(4.), half(1.)].concat();
+        assert_eq!(convert(&sdr, &bright), [65535; 3]);
+        // Kept as HDR10, SDR white lands at 80 nits.
+        let hdr = Converter::new(SurfaceFormat::Rgba16F, Transfer::Linear, ToneMap::Off);
+        assert_eq!(hdr.transfer(), Transfer::Pq);
+        let want = (linear_to_pq(0.008) * 65535.).round();
+        for v in convert(&hdr, &white) {
+            assert!((v as f32 - want).abs() <= 64., "{v} {want}");
+        }
+    }
+
+    #[test]
+    fn hdr10_tone_mapped() {
+        let white = linear_to_pq(SDR_WHITE_NITS / 10000.);
+        let v = (white * 1023.).round() as u32;
+        let px = (v | v << 10 | v << 20).to_le_bytes();
+        let clip = Converter::new(SurfaceFormat::Rgb10A2, Transfer::Pq, ToneMap::Clip);
+        assert_eq!(clip.transfer(), Transfer::Srgb);
+        for v in convert(&clip, &px) {
+            assert!(v > 65000, "{v}");
+        }
+        let black = Converter::new(SurfaceFormat::Rgb10A2, Transfer::Pq, ToneMap::Hable);
+        assert_eq!(convert(&black, &[0; 4]), [0; 3]);
+    }
+
+    #[test]
+    fn hdr10_and_scrgb_share_white() {
+        // 200 nits in either encoding comes out the same.
+        let v = (linear_to_pq(0.02) * 1023.).round() as u32;
+        let hdr10 = (v | v << 10 | v << 20).to_le_bytes();
+        let scrgb = [half(2.5), half(2.5), half(2.5), half(1.)].concat();
+        for tone_map in [ToneMap::Clip, ToneMap::Reinhard, ToneMap::Hable] {
+            let pq = Converter::new(SurfaceFormat::Rgb10A2, Transfer::Pq, tone_map);
+            let linear = Converter::new(SurfaceFormat::Rgba16F, Transfer::Linear, tone_map);
+            let (pq, linear) = (convert(&pq, &hdr10), convert(&linear, &scrgb));
+            for (a, b) in pq.into_iter().zip(linear) {
+                assert!(a.abs_diff(b) < 500, "{tone_map:?} {a} {b}");
+            }
+        }
+    }
+}
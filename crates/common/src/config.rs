@@ -0,0 +1,1021 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    num::NonZeroU64,
+    path::{
+        Path,
+        PathBuf,
+    },
+    str::FromStr,
+};
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    ENV_KEY_CONFIG,
+    ENV_KEY_CONFIG_FILE,
+    geometry::{
+        Fit,
+        Rect,
+        ScaleAlgorithm,
+        Size,
+    },
+    images::{
+        FrameRange,
+        ImageFormat,
+    },
+};
+
+/// Bumped whenever [`RecordinConfig`] changes incompatibly, so a CLI and a
+/// loader built from different sources refuse to talk to each other.
+pub const CONFIG_VERSION: u32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GraphicsSystem {
+    Vulkan,
+    D3D11,
+}
+
+impl GraphicsSystem {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GraphicsSystem::Vulkan => "Vulkan",
+            GraphicsSystem::D3D11 => "D3D11",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SoundSystem {
+    Wasapi,
+}
+
+impl SoundSystem {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SoundSystem::Wasapi => "WASAPI",
+        }
+    }
+}
+
+/// YUV matrix used when converting the captured RGB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorMatrix {
+    Bt601,
+    Bt709,
+}
+
+impl ColorMatrix {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ColorMatrix::Bt601 => "bt601",
+            ColorMatrix::Bt709 => "bt709",
+        }
+    }
+}
+
+impl FromStr for ColorMatrix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "bt601" | "601" => Ok(ColorMatrix::Bt601),
+            "bt709" | "709" => Ok(ColorMatrix::Bt709),
+            _ => Err(format!(
+                "unknown color matrix `{s}`, expected bt601 or bt709"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorRange {
+    /// 16-235, what players expect from YUV video.
+    Limited,
+    Full,
+}
+
+impl ColorRange {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ColorRange::Limited => "limited",
+            ColorRange::Full => "full",
+        }
+    }
+}
+
+impl FromStr for ColorRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "limited" | "tv" => Ok(ColorRange::Limited),
+            "full" | "pc" => Ok(ColorRange::Full),
+            _ => Err(format!(
+                "unknown color range `{s}`, expected limited or full"
+            )),
+        }
+    }
+}
+
+/// What HDR captures, scRGB or HDR10, become.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToneMap {
+    /// Kept as HDR10, for a 10-bit encoder.
+    Off,
+    /// Cut off at SDR white.
+    Clip,
+    Reinhard,
+    #[default]
+    Hable,
+}
+
+impl ToneMap {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ToneMap::Off => "off",
+            ToneMap::Clip => "clip",
+            ToneMap::Reinhard => "reinhard",
+            ToneMap::Hable => "hable",
+        }
+    }
+}
+
+impl FromStr for ToneMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" | "hdr10" => Ok(ToneMap::Off),
+            "clip" => Ok(ToneMap::Clip),
+            "reinhard" => Ok(ToneMap::Reinhard),
+            "hable" | "filmic" => Ok(ToneMap::Hable),
+            _ => Err(format!(
+                "unknown tone mapping `{s}`, expected off, clip, reinhard or hable"
+            )),
+        }
+    }
+}
+
+/// When a segmented video output moves on to its next file.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum SegmentLimit {
+    /// `1800f`
+    Frames(u64),
+    /// Seconds of virtual time, `90s`, `10min` or `2h`.
+    Duration(f64),
+    /// Bytes, `700MB`, `2GiB`; a segment ends after the frame exceeding it.
+    Size(u64),
+}
+
+impl fmt::Display for SegmentLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SegmentLimit::Frames(n) => write!(f, "{n}f"),
+            SegmentLimit::Duration(secs) => write!(f, "{secs}s"),
+            SegmentLimit::Size(bytes) => write!(f, "{bytes}B"),
+        }
+    }
+}
+
+impl FromStr for SegmentLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("`{s}` is not a segment length like 1800f, 10min or 2GB");
+        let (n, unit) = split_unit(s).ok_or_else(err)?;
+        let limit = match unit {
+            "f" | "frames" if n.fract() == 0. => SegmentLimit::Frames(n as u64),
+            "s" | "sec" => SegmentLimit::Duration(n),
+            "min" => SegmentLimit::Duration(n * 60.),
+            "h" => SegmentLimit::Duration(n * 3600.),
+            _ => SegmentLimit::Size((n * byte_scale(unit).ok_or_else(err)?) as u64),
+        };
+        Ok(limit)
+    }
+}
+
+/// Splits `2.5GB` into a positive number and its unit.
+fn split_unit(s: &str) -> Option<(f64, &str)> {
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (n, unit) = s.split_at(split);
+    let n: f64 = n.parse().ok()?;
+    (n > 0.).then_some((n, unit.trim()))
+}
+
+fn byte_scale(unit: &str) -> Option<f64> {
+    let scale = match unit {
+        "B" => 1.,
+        "k" | "K" | "kB" | "KB" => 1e3,
+        "M" | "MB" => 1e6,
+        "G" | "GB" => 1e9,
+        "KiB" => 1024.,
+        "MiB" => 1024. * 1024.,
+        "GiB" => 1024. * 1024. * 1024.,
+        _ => return None,
+    };
+    Some(scale)
+}
+
+impl TryFrom<String> for SegmentLimit {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<SegmentLimit> for String {
+    fn from(limit: SegmentLimit) -> Self {
+        limit.to_string()
+    }
+}
+
+/// UTC date and time the virtual wall clock starts at, written
+/// `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM:SS` with an optional `Z`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct StartDate {
+    /// Seconds since 1970-01-01T00:00:00Z.
+    pub unix_seconds: i64,
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The inverse of [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+impl fmt::Display for StartDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (days, secs) = (
+            self.unix_seconds.div_euclid(86400),
+            self.unix_seconds.rem_euclid(86400),
+        );
+        let (year, month, day) = civil_from_days(days);
+        write!(
+            f,
+            "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        )
+    }
+}
+
+impl FromStr for StartDate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("invalid date `{s}`, expected YYYY-MM-DD or YYYY-MM-DDTHH:MM:SS");
+        let trimmed = s.strip_suffix(['Z', 'z']).unwrap_or(s);
+        let (date, time) = trimmed
+            .split_once(['T', 't', ' '])
+            .unwrap_or((trimmed, "00:00:00"));
+        let fields = |s: &str, sep: char| -> Option<[i64; 3]> {
+            let mut fields = s.split(sep).map(|f| {
+                let digits = !f.is_empty() && f.bytes().all(|b| b.is_ascii_digit());
+                digits.then(|| f.parse().ok()).flatten()
+            });
+            let parsed = [fields.next()??, fields.next()??, fields.next()??];
+            fields.next().is_none().then_some(parsed)
+        };
+        let [year, month, day] = fields(date, '-').ok_or_else(err)?;
+        let [hour, minute, second] = fields(time, ':').ok_or_else(err)?;
+        let days = days_from_civil(year, month, day);
+        // Out of range fields would roll over into the next ones.
+        let valid = (1601..=9999).contains(&year)
+            && civil_from_days(days) == (year, month, day)
+            && hour < 24
+            && minute < 60
+            && second < 60;
+        if !valid {
+            return Err(err());
+        }
+        Ok(StartDate {
+            unix_seconds: days * 86400 + hour * 3600 + minute * 60 + second,
+        })
+    }
+}
+
+impl TryFrom<String> for StartDate {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<StartDate> for String {
+    fn from(date: StartDate) -> Self {
+        date.to_string()
+    }
+}
+
+/// Time sources beyond the performance counter, `GetTickCount` and
+/// `timeGetTime` that can be left alone, to find out which one a game trips
+/// over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TimeHook {
+    /// `QueryInterruptTime` and `QueryInterruptTimePrecise`.
+    InterruptTime,
+    /// `QueryUnbiasedInterruptTime` and `QueryUnbiasedInterruptTimePrecise`.
+    UnbiasedInterruptTime,
+    /// `timeGetSystemTime`.
+    TimeGetSystemTime,
+}
+
+impl TimeHook {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TimeHook::InterruptTime => "interrupt-time",
+            TimeHook::UnbiasedInterruptTime => "unbiased-interrupt-time",
+            TimeHook::TimeGetSystemTime => "time-get-system-time",
+        }
+    }
+}
+
+impl FromStr for TimeHook {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "interrupt-time" => Ok(TimeHook::InterruptTime),
+            "unbiased-interrupt-time" => Ok(TimeHook::UnbiasedInterruptTime),
+            "time-get-system-time" => Ok(TimeHook::TimeGetSystemTime),
+            _ => Err(format!(
+                "unknown time hook `{s}`, expected interrupt-time, unbiased-interrupt-time \
+                    or time-get-system-time"
+            )),
+        }
+    }
+}
+
+/// What capturing does when the video output thread falls behind and no
+/// free frame buffer is left.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Backpressure {
+    /// Wait for one, stalling the game.
+    #[default]
+    Block,
+    /// Skip the frame and count it.
+    Drop,
+    /// Allocate another buffer while all of them take at most this many
+    /// bytes, `grow=2GB`, then wait.
+    Grow(u64),
+}
+
+impl fmt::Display for Backpressure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backpressure::Block => write!(f, "block"),
+            Backpressure::Drop => write!(f, "drop"),
+            Backpressure::Grow(bytes) => write!(f, "grow={bytes}B"),
+        }
+    }
+}
+
+impl FromStr for Backpressure {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            None if s == "block" => Ok(Backpressure::Block),
+            None if s == "drop" => Ok(Backpressure::Drop),
+            Some(("grow", cap)) => {
+                let err = || format!("`{cap}` is not a memory size like 512MB or 2GiB");
+                let (n, unit) = split_unit(cap).ok_or_else(err)?;
+                Ok(Backpressure::Grow(
+                    (n * byte_scale(unit).ok_or_else(err)?) as u64,
+                ))
+            }
+            None if s == "grow" => Err("grow needs a memory cap, like grow=2GB".to_owned()),
+            _ => Err(format!(
+                "unknown backpressure policy `{s}`, expected block, drop or grow=SIZE"
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for Backpressure {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Backpressure> for String {
+    fn from(policy: Backpressure) -> Self {
+        policy.to_string()
+    }
+}
+
+/// An output fed next to the encoded video, audio and images, written as
+/// `kind` or `kind=value`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum SinkConfig {
+    /// Captured frames, packed 8-bit RGB, one after another in one file.
+    RawVideo(PathBuf),
+    /// Rendered audio, interleaved stereo `f32` at 48 kHz.
+    RawAudio(PathBuf),
+    /// Captured frames, each after a header giving its size, format and
+    /// virtual time.
+    VideoPipe(PipeTarget),
+    /// Captured frames without headers, what `ffmpeg -f rawvideo` reads.
+    RawVideoPipe(PipeTarget),
+    /// Rendered audio like [`SinkConfig::RawAudio`], each chunk after a
+    /// header giving its format, length and virtual time.
+    AudioPipe(PipeTarget),
+    /// Captures frames and audio and throws them away, to measure what
+    /// capturing costs.
+    Null,
+}
+
+/// Where a pipe sink streams to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PipeTarget {
+    /// `exec:COMMAND`, the standard input of a command started for it.
+    Command(String),
+    /// `tcp:HOST:PORT`
+    Tcp(String),
+    /// `unix:PATH`, a Unix domain socket.
+    Unix(PathBuf),
+    /// A named pipe like `\\.\pipe\frames` that is already listening.
+    Path(PathBuf),
+}
+
+impl SinkConfig {
+    pub fn takes_video(&self) -> bool {
+        matches!(
+            self,
+            SinkConfig::RawVideo(_)
+                | SinkConfig::VideoPipe(_)
+                | SinkConfig::RawVideoPipe(_)
+                | SinkConfig::Null
+        )
+    }
+
+    pub fn takes_audio(&self) -> bool {
+        matches!(
+            self,
+            SinkConfig::RawAudio(_) | SinkConfig::AudioPipe(_) | SinkConfig::Null
+        )
+    }
+}
+
+impl fmt::Display for SinkConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SinkConfig::RawVideo(path) => write!(f, "raw-video={}", path.display()),
+            SinkConfig::RawAudio(path) => write!(f, "raw-audio={}", path.display()),
+            SinkConfig::VideoPipe(target) => write!(f, "video-pipe={target}"),
+            SinkConfig::RawVideoPipe(target) => write!(f, "rawvideo-pipe={target}"),
+            SinkConfig::AudioPipe(target) => write!(f, "audio-pipe={target}"),
+            SinkConfig::Null => write!(f, "null"),
+        }
+    }
+}
+
+impl FromStr for SinkConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = match s.split_once('=') {
+            Some((kind, value)) => (kind, Some(value)),
+            None => (s, None),
+        };
+        let value = || value.ok_or(format!("sink `{kind}` needs a value, like {kind}=out"));
+        match kind {
+            "raw-video" => Ok(SinkConfig::RawVideo(value()?.into())),
+            "raw-audio" => Ok(SinkConfig::RawAudio(value()?.into())),
+            "video-pipe" => Ok(SinkConfig::VideoPipe(value()?.parse()?)),
+            "rawvideo-pipe" => Ok(SinkConfig::RawVideoPipe(value()?.parse()?)),
+            "audio-pipe" => Ok(SinkConfig::AudioPipe(value()?.parse()?)),
+            "null" => Ok(SinkConfig::Null),
+            _ => Err(format!(
+                "unknown sink `{kind}`, expected raw-video, raw-audio, video-pipe, \
+                    rawvideo-pipe, audio-pipe or null"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PipeTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PipeTarget::Command(command) => write!(f, "exec:{command}"),
+            PipeTarget::Tcp(address) => write!(f, "tcp:{address}"),
+            PipeTarget::Unix(path) => write!(f, "unix:{}", path.display()),
+            PipeTarget::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl FromStr for PipeTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let target = if let Some(command) = s.strip_prefix("exec:") {
+            PipeTarget::Command(command.to_owned())
+        } else if let Some(address) = s.strip_prefix("tcp:") {
+            if !address.contains(':') {
+                Err(format!("`{address}` is not an address like 127.0.0.1:9000"))?
+            }
+            PipeTarget::Tcp(address.to_owned())
+        } else if let Some(path) = s.strip_prefix("unix:") {
+            PipeTarget::Unix(path.into())
+        } else {
+            PipeTarget::Path(s.into())
+        };
+        match &target {
+            PipeTarget::Command(s) if s.trim().is_empty() => Err("empty command".to_owned()),
+            PipeTarget::Unix(p) | PipeTarget::Path(p) if p.as_os_str().is_empty() => {
+                Err("empty path".to_owned())
+            }
+            _ => Ok(target),
+        }
+    }
+}
+
+impl TryFrom<String> for SinkConfig {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<SinkConfig> for String {
+    fn from(sink: SinkConfig) -> Self {
+        sink.to_string()
+    }
+}
+
+/// Everything the CLI hands over to the injected loader.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct RecordinConfig {
+    pub version: u32,
+    pub fps: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_regex: Option<String>,
+    #[serde(default)]
+    pub aggressive: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub force_tick_threshold: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub graphics: Option<GraphicsSystem>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sound: Option<SoundSystem>,
+    #[serde(default)]
+    pub merge_audio: bool,
+    /// Prefer container settings that leave a playable file even without a
+    /// trailer, like fragmented MP4.
+    #[serde(default)]
+    pub recoverable: bool,
+    /// Turn captured images upside down, for surfaces stored bottom row
+    /// first.
+    #[serde(default)]
+    pub flip_vertical: bool,
+    #[serde(default)]
+    pub tone_map: ToneMap,
+    /// Where the virtual wall clock starts, the real time at launch when
+    /// not given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_date: Option<StartDate>,
+    /// Time sources that keep running in real time.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disabled_time_hooks: Vec<TimeHook>,
+    #[serde(default)]
+    pub backpressure: Backpressure,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub video: Option<VideoConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio: Option<AudioConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub images: Option<ImagesConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sinks: Vec<SinkConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct VideoConfig {
+    pub encoder: String,
+    #[serde(default)]
+    pub options: BTreeMap<String, String>,
+    /// FFmpeg pixel format name, yuv420p when not given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pixel_format: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matrix: Option<ColorMatrix>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub range: Option<ColorRange>,
+    /// Region of the captured frame to encode.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crop: Option<Rect>,
+    /// Fixed output size, the (cropped) swap chain size when not given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale: Option<Size>,
+    #[serde(default)]
+    pub fit: Fit,
+    #[serde(default)]
+    pub scale_algorithm: ScaleAlgorithm,
+    /// Keep writing one output when the swap chain is resized or recreated,
+    /// fitting later frames into the size of the first.
+    #[serde(default)]
+    pub continuous: bool,
+    /// Split the output into independently playable files, listed in a
+    /// `.segments.csv` manifest next to them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub segment: Option<SegmentLimit>,
+    pub output: PathBuf,
+    /// Muxer name, guessed from the extension of `output` when not given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+    #[serde(default)]
+    pub container_options: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct AudioConfig {
+    pub encoder: String,
+    #[serde(default)]
+    pub options: BTreeMap<String, String>,
+    /// Hz, the encoder's rate closest to 48 kHz when not given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample_rate: Option<u32>,
+    /// Bits per second.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bitrate: Option<u64>,
+    /// Not set when audio is merged into the video output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+    #[serde(default)]
+    pub container_options: BTreeMap<String, String>,
+}
+
+/// Every captured frame, or a selection of them, as an image file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ImagesConfig {
+    /// File name pattern, see [`frame_path`](crate::images::frame_path).
+    pub output: PathBuf,
+    pub format: ImageFormat,
+    /// Virtual frames to write, all of them when not given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frames: Option<FrameRange>,
+    /// Only write every nth frame of the range.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub every: Option<NonZeroU64>,
+}
+
+impl RecordinConfig {
+    pub fn new(fps: f64) -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            fps,
+            target_regex: None,
+            aggressive: false,
+            force_tick_threshold: None,
+            graphics: None,
+            sound: None,
+            merge_audio: false,
+            recoverable: false,
+            flip_vertical: false,
+            tone_map: ToneMap::Hable,
+            start_date: None,
+            disabled_time_hooks: Vec::new(),
+            backpressure: Backpressure::Block,
+            video: None,
+            audio: None,
+            images: None,
+            sinks: Vec::new(),
+        }
+    }
+
+    pub fn encode(&self) -> Result<String, ConfigError> {
+        toml::to_string(self).map_err(|e| ConfigError::Parse(e.to_string()))
+    }
+
+    /// Parses a serialized config, checking the version before anything else
+    /// so that a mismatch is not reported as some unrelated field error.
+    pub fn decode(s: &str) -> Result<Self, ConfigError> {
+        #[derive(Deserialize)]
+        struct Versioned {
+            version: u32,
+        }
+        let Versioned { version } = toml::from_str::<Versioned>(s)
+            .map_err(|_| ConfigError::Parse("missing version field".to_owned()))?;
+        if version != CONFIG_VERSION {
+            Err(ConfigError::Version {
+                expected: CONFIG_VERSION,
+                found: version,
+            })?
+        }
+        toml::from_str(s).map_err(|e| ConfigError::Parse(e.to_string()))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let s = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+        Self::decode(&s)
+    }
+
+    /// Reads the config from [`ENV_KEY_CONFIG`], or from the file named by
+    /// [`ENV_KEY_CONFIG_FILE`].
+    pub fn from_env() -> Result<Self, ConfigError> {
+        if let Some(s) = std::env::var_os(ENV_KEY_CONFIG) {
+            Self::decode(&s.to_string_lossy())
+        } else if let Some(path) = std::env::var_os(ENV_KEY_CONFIG_FILE) {
+            Self::load(path)
+        } else {
+            Err(ConfigError::Missing)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Missing,
+    Io(std::io::Error),
+    Parse(String),
+    Version { expected: u32, found: u32 },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Missing => write!(
+                f,
+                "neither {ENV_KEY_CONFIG} nor {ENV_KEY_CONFIG_FILE} is set"
+            ),
+            ConfigError::Io(e) => write!(f, "unable to read config file: {e}"),
+            ConfigError::Parse(e) => write!(f, "invalid config: {e}"),
+            ConfigError::Version { expected, found } => write!(
+                f,
+                "config version {found} does not match expected version {expected}, \
+                    CLI and loader are probably from different builds"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn full() -> RecordinConfig {
+        RecordinConfig {
+            target_regex: Some("game\\.exe".to_owned()),
+            aggressive: true,
+            force_tick_threshold: Some(0x10000),
+            graphics: Some(GraphicsSystem::D3D11),
+            sound: Some(SoundSystem::Wasapi),
+            merge_audio: true,
+            recoverable: true,
+            flip_vertical: true,
+            tone_map: ToneMap::Off,
+            start_date: Some("2000-01-01".parse().unwrap()),
+            disabled_time_hooks: vec![TimeHook::InterruptTime, TimeHook::TimeGetSystemTime],
+            backpressure: Backpressure::Grow(1 << 30),
+            video: Some(VideoConfig {
+                encoder: "libx264".to_owned(),
+                options: [
+                    ("crf".to_owned(), "18".to_owned()),
+                    ("x264-params".to_owned(), "keyint=60:bframes=0".to_owned()),
+                ]
+                .into(),
+                pixel_format: Some("yuv420p10".to_owned()),
+                matrix: Some(ColorMatrix::Bt709),
+                range: Some(ColorRange::Full),
+                crop: Some("1280x720+8+31".parse().unwrap()),
+                scale: Some(Size::new(1920, 1080)),
+                fit: Fit::Stretch,
+                scale_algorithm: ScaleAlgorithm::Lanczos,
+                continuous: true,
+                segment: Some(SegmentLimit::Duration(1.5)),
+                output: "C:\\videos\\out;1.mp4".into(),
+                container: Some("mov".to_owned()),
+                container_options: [("movflags".to_owned(), "+faststart".to_owned())].into(),
+            }),
+            audio: Some(AudioConfig {
+                encoder: "libopus".to_owned(),
+                options: [("application".to_owned(), "audio".to_owned())].into(),
+                sample_rate: Some(48000),
+                bitrate: Some(128_000),
+                output: Some("out.mka".into()),
+                container: None,
+                container_options: BTreeMap::new(),
+            }),
+            images: Some(ImagesConfig {
+                output: "frames/%06d.exr".into(),
+                format: ImageFormat::Exr,
+                frames: Some("600-".parse().unwrap()),
+                every: NonZeroU64::new(2),
+            }),
+            sinks: vec![
+                SinkConfig::RawAudio("dump/audio.f32".into()),
+                SinkConfig::VideoPipe(PipeTarget::Unix("frames.sock".into())),
+                SinkConfig::Null,
+            ],
+            ..RecordinConfig::new(59.94)
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for config in [RecordinConfig::new(60.), full()] {
+            let s = config.encode().unwrap();
+            assert_eq!(RecordinConfig::decode(&s).unwrap(), config);
+        }
+    }
+
+    #[test]
+    fn round_trip_fps_exactly() {
+        let config = RecordinConfig::new(1. / 3.);
+        let s = config.encode().unwrap();
+        assert_eq!(
+            RecordinConfig::decode(&s).unwrap().fps.to_bits(),
+            (1f64 / 3.).to_bits()
+        );
+    }
+
+    #[test]
+    fn reject_version_mismatch() {
+        let mut config = full();
+        config.version = CONFIG_VERSION + 1;
+        let s = config.encode().unwrap();
+        let err = RecordinConfig::decode(&s).unwrap_err();
+        assert!(matches!(
+            err,
+            ConfigError::Version { expected: CONFIG_VERSION, found } if found == CONFIG_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn parse_color() {
+        assert_eq!("BT709".parse(), Ok(ColorMatrix::Bt709));
+        assert_eq!("601".parse(), Ok(ColorMatrix::Bt601));
+        assert_eq!("tv".parse(), Ok(ColorRange::Limited));
+        assert_eq!("full".parse(), Ok(ColorRange::Full));
+        assert!("bt2020".parse::<ColorMatrix>().is_err());
+        assert_eq!("HDR10".parse(), Ok(ToneMap::Off));
+        assert_eq!("reinhard".parse(), Ok(ToneMap::Reinhard));
+        assert!("aces".parse::<ToneMap>().is_err());
+    }
+
+    #[test]
+    fn parse_time_hook() {
+        for hook in [
+            TimeHook::InterruptTime,
+            TimeHook::UnbiasedInterruptTime,
+            TimeHook::TimeGetSystemTime,
+        ] {
+            assert_eq!(hook.as_str().parse(), Ok(hook));
+        }
+        assert_eq!("Interrupt-Time".parse(), Ok(TimeHook::InterruptTime));
+        assert!("qpc".parse::<TimeHook>().is_err());
+    }
+
+    #[test]
+    fn parse_segment_limit() {
+        assert_eq!("1800f".parse(), Ok(SegmentLimit::Frames(1800)));
+        assert_eq!("10min".parse(), Ok(SegmentLimit::Duration(600.)));
+        assert_eq!("1.5h".parse(), Ok(SegmentLimit::Duration(5400.)));
+        assert_eq!("2GB".parse(), Ok(SegmentLimit::Size(2_000_000_000)));
+        assert_eq!("1MiB".parse(), Ok(SegmentLimit::Size(1 << 20)));
+        assert!("1.5f".parse::<SegmentLimit>().is_err());
+        assert!("0s".parse::<SegmentLimit>().is_err());
+        assert!("10".parse::<SegmentLimit>().is_err());
+        for limit in ["25f", "0.5s", "700B"] {
+            let parsed: SegmentLimit = limit.parse().unwrap();
+            assert_eq!(parsed.to_string(), limit);
+        }
+    }
+
+    #[test]
+    fn parse_start_date() {
+        let date = |s: &str| s.parse::<StartDate>().map(|d| d.unix_seconds);
+        assert_eq!(date("1970-01-01"), Ok(0));
+        assert_eq!(date("2000-03-01T12:34:56Z"), Ok(951914096));
+        assert_eq!(date("1601-01-01 00:00:00"), Ok(-11644473600));
+        assert_eq!(date("2024-02-29T23:59:59"), Ok(1709251199));
+        for bad in [
+            "2023-02-29",
+            "2000-13-01",
+            "2000-01-01T24:00:00",
+            "1600-12-31",
+            "2000-1-1x",
+            "2000-01-01T12:00",
+            "+2000-01-01",
+            "yesterday",
+        ] {
+            assert!(bad.parse::<StartDate>().is_err(), "{bad}");
+        }
+        for s in ["2000-01-01T00:00:00Z", "2038-01-19T03:14:08Z"] {
+            assert_eq!(s.parse::<StartDate>().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn parse_backpressure() {
+        assert_eq!("block".parse(), Ok(Backpressure::Block));
+        assert_eq!("drop".parse(), Ok(Backpressure::Drop));
+        assert_eq!("grow=512MB".parse(), Ok(Backpressure::Grow(512_000_000)));
+        assert_eq!("grow=2GiB".parse(), Ok(Backpressure::Grow(2 << 30)));
+        assert!("grow".parse::<Backpressure>().is_err());
+        assert!("grow=2f".parse::<Backpressure>().is_err());
+        assert!("wait".parse::<Backpressure>().is_err());
+        assert_eq!(
+            Backpressure::Grow(1000).to_string().parse(),
+            Ok(Backpressure::Grow(1000))
+        );
+    }
+
+    #[test]
+    fn parse_sink() {
+        assert_eq!(
+            "raw-video=C:\\out=1.rgb".parse(),
+            Ok(SinkConfig::RawVideo("C:\\out=1.rgb".into()))
+        );
+        assert_eq!("null".parse(), Ok(SinkConfig::Null));
+        assert_eq!(
+            "rawvideo-pipe=exec:ffmpeg -i - out.mkv".parse(),
+            Ok(SinkConfig::RawVideoPipe(PipeTarget::Command(
+                "ffmpeg -i - out.mkv".to_owned()
+            )))
+        );
+        assert_eq!(
+            "video-pipe=tcp:127.0.0.1:9000".parse(),
+            Ok(SinkConfig::VideoPipe(PipeTarget::Tcp(
+                "127.0.0.1:9000".to_owned()
+            )))
+        );
+        assert_eq!(
+            "audio-pipe=\\\\.\\pipe\\pcm".parse(),
+            Ok(SinkConfig::AudioPipe(PipeTarget::Path(
+                "\\\\.\\pipe\\pcm".into()
+            )))
+        );
+        assert!("video-pipe=tcp:9000".parse::<SinkConfig>().is_err());
+        assert!("audio-pipe=exec:".parse::<SinkConfig>().is_err());
+        assert!("raw-audio".parse::<SinkConfig>().is_err());
+        assert!("tee=a".parse::<SinkConfig>().is_err());
+        assert_eq!(
+            SinkConfig::RawAudio("a.f32".into()).to_string(),
+            "raw-audio=a.f32"
+        );
+    }
+
+    #[test]
+    fn reject_missing_version() {
+        let err = RecordinConfig::decode("fps = 60.0").unwrap_err();
+        assert!(matches!(err, ConfigError::Parse(_)));
+    }
+}
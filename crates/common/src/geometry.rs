@@ -0,0 +1,338 @@
+//! Output size, cropping and fitting of captured frames.
+
+use std::{
+    fmt,
+    str::FromStr,
+};
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+/// `WIDTHxHEIGHT`, e.g. `1920x1080`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Size {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// `WIDTHxHEIGHT+X+Y`, a region of the captured frame; the offset defaults
+/// to the top left corner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// How a frame is brought to the `--scale` size when aspect ratios differ.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    /// Keep the aspect ratio and pad with black bars.
+    #[default]
+    Letterbox,
+    Stretch,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScaleAlgorithm {
+    Neighbor,
+    Bilinear,
+    #[default]
+    Bicubic,
+    Area,
+    Lanczos,
+    Spline,
+}
+
+/// Where a frame ends up: `source` is cut out of the captured frame and
+/// scaled into `placement` of an output frame of `output` size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub source: Rect,
+    pub output: Size,
+    pub placement: Rect,
+}
+
+impl Size {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self { width, height }
+    }
+}
+
+impl Rect {
+    pub fn size(&self) -> Size {
+        Size::new(self.width, self.height)
+    }
+}
+
+impl Layout {
+    /// Lays out a `captured` frame, `None` if it is smaller than 2x2. Without
+    /// `scale` the output is as large as the (cropped) frame, less an odd
+    /// last row or column. Output and placement offsets and sizes are kept
+    /// even so that chroma subsampled formats line up.
+    pub fn new(captured: Size, crop: Option<Rect>, scale: Option<Size>, fit: Fit) -> Option<Self> {
+        if captured.width < 2 || captured.height < 2 {
+            return None;
+        }
+        let full = Rect {
+            x: 0,
+            y: 0,
+            width: captured.width,
+            height: captured.height,
+        };
+        let mut source = crop.map_or(full, |c| {
+            let x = c.x.min(captured.width - 2);
+            let y = c.y.min(captured.height - 2);
+            Rect {
+                x,
+                y,
+                width: c.width.clamp(2, captured.width - x),
+                height: c.height.clamp(2, captured.height - y),
+            }
+        });
+        if scale.is_none() {
+            // Dropping a row or column keeps the aspect ratio, scaling it
+            // away would not.
+            source.width = even(source.width);
+            source.height = even(source.height);
+        }
+        let output = scale.unwrap_or(source.size());
+        let output = Size::new(even(output.width).max(2), even(output.height).max(2));
+        let (width, height) = match fit {
+            Fit::Stretch => (output.width, output.height),
+            Fit::Letterbox => {
+                // compare aspect ratios without rounding
+                let wide = source.width as u64 * output.height as u64
+                    >= output.width as u64 * source.height as u64;
+                if wide {
+                    let h = nearest_even(
+                        output.width as u64 * source.height as u64,
+                        source.width as u64,
+                    );
+                    (output.width, h.clamp(2, output.height))
+                } else {
+                    let w = nearest_even(
+                        output.height as u64 * source.width as u64,
+                        source.height as u64,
+                    );
+                    (w.clamp(2, output.width), output.height)
+                }
+            }
+        };
+        let placement = Rect {
+            x: even((output.width - width) / 2),
+            y: even((output.height - height) / 2),
+            width,
+            height,
+        };
+        Some(Self {
+            source,
+            output,
+            placement,
+        })
+    }
+}
+
+fn even(n: u32) -> u32 {
+    n & !1
+}
+
+/// The even number closest to `num / den`.
+fn nearest_even(num: u64, den: u64) -> u32 {
+    ((num + den) / (2 * den) * 2) as u32
+}
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+impl fmt::Display for Rect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}+{}+{}", self.width, self.height, self.x, self.y)
+    }
+}
+
+impl FromStr for Size {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("`{s}` is not a size like 1920x1080");
+        let (w, h) = s.split_once(['x', 'X']).ok_or_else(err)?;
+        let width: u32 = w.trim().parse().map_err(|_| err())?;
+        let height: u32 = h.trim().parse().map_err(|_| err())?;
+        if width == 0 || height == 0 {
+            Err(err())?
+        }
+        Ok(Size { width, height })
+    }
+}
+
+impl FromStr for Rect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("`{s}` is not a region like 1280x720 or 1280x720+320+180");
+        let mut parts = s.split('+');
+        let size: Size = parts
+            .next()
+            .unwrap_or_default()
+            .parse()
+            .map_err(|_| err())?;
+        let mut offset = || match parts.next() {
+            Some(n) => n.trim().parse().map_err(|_| err()),
+            None => Ok(0),
+        };
+        let (x, y) = (offset()?, offset()?);
+        if parts.next().is_some() {
+            Err(err())?
+        }
+        Ok(Rect {
+            x,
+            y,
+            width: size.width,
+            height: size.height,
+        })
+    }
+}
+
+impl TryFrom<String> for Size {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Size> for String {
+    fn from(size: Size) -> Self {
+        size.to_string()
+    }
+}
+
+impl TryFrom<String> for Rect {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Rect> for String {
+    fn from(rect: Rect) -> Self {
+        rect.to_string()
+    }
+}
+
+impl FromStr for Fit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "letterbox" | "pad" => Ok(Fit::Letterbox),
+            "stretch" => Ok(Fit::Stretch),
+            _ => Err(format!("unknown fit `{s}`, expected letterbox or stretch")),
+        }
+    }
+}
+
+impl FromStr for ScaleAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "neighbor" | "nearest" | "point" => Ok(ScaleAlgorithm::Neighbor),
+            "bilinear" => Ok(ScaleAlgorithm::Bilinear),
+            "bicubic" => Ok(ScaleAlgorithm::Bicubic),
+            "area" => Ok(ScaleAlgorithm::Area),
+            "lanczos" => Ok(ScaleAlgorithm::Lanczos),
+            "spline" => Ok(ScaleAlgorithm::Spline),
+            _ => Err(format!(
+                "unknown scaling algorithm `{s}`, expected one of \
+                    neighbor, bilinear, bicubic, area, lanczos, spline"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn parse_geometry() {
+        assert_eq!("1920x1080".parse(), Ok(Size::new(1920, 1080)));
+        assert!("1920".parse::<Size>().is_err());
+        assert!("0x1080".parse::<Size>().is_err());
+        assert_eq!("640x480+10+20".parse(), Ok(rect(10, 20, 640, 480)));
+        assert_eq!("640x480".parse(), Ok(rect(0, 0, 640, 480)));
+        assert!("640x480+1+2+3".parse::<Rect>().is_err());
+        assert_eq!("letterbox".parse(), Ok(Fit::Letterbox));
+        assert_eq!("Lanczos".parse(), Ok(ScaleAlgorithm::Lanczos));
+    }
+
+    #[test]
+    fn layout_unscaled() {
+        let layout = Layout::new(Size::new(800, 600), None, None, Fit::Letterbox).unwrap();
+        assert_eq!(layout.output, Size::new(800, 600));
+        assert_eq!(layout.placement, rect(0, 0, 800, 600));
+        let crop = Some(rect(700, 100, 400, 400));
+        let layout = Layout::new(Size::new(800, 600), crop, None, Fit::Letterbox).unwrap();
+        assert_eq!(layout.source, rect(700, 100, 100, 400));
+        assert_eq!(layout.output, Size::new(100, 400));
+    }
+
+    #[test]
+    fn layout_odd_and_empty() {
+        let layout = Layout::new(Size::new(801, 601), None, None, Fit::Letterbox).unwrap();
+        assert_eq!(layout.source, rect(0, 0, 800, 600));
+        assert_eq!(layout.placement, rect(0, 0, 800, 600));
+        let scale = Some(Size::new(1281, 721));
+        let layout = Layout::new(Size::new(801, 601), None, scale, Fit::Stretch).unwrap();
+        assert_eq!(layout.source, rect(0, 0, 801, 601));
+        assert_eq!(layout.output, Size::new(1280, 720));
+        let crop = Some(rect(900, 700, 1, 1));
+        let layout = Layout::new(Size::new(800, 600), crop, None, Fit::Letterbox).unwrap();
+        assert_eq!(layout.source, rect(798, 598, 2, 2));
+        assert_eq!(
+            Layout::new(Size::new(0, 600), None, None, Fit::Letterbox),
+            None
+        );
+        assert_eq!(
+            Layout::new(Size::new(800, 1), None, scale, Fit::Stretch),
+            None
+        );
+    }
+
+    #[test]
+    fn layout_letterbox() {
+        let scale = Some(Size::new(1920, 1080));
+        let layout = Layout::new(Size::new(800, 600), None, scale, Fit::Letterbox).unwrap();
+        assert_eq!(layout.output, Size::new(1920, 1080));
+        assert_eq!(layout.placement, rect(240, 0, 1440, 1080));
+        let layout = Layout::new(Size::new(2560, 1080), None, scale, Fit::Letterbox).unwrap();
+        assert_eq!(layout.placement, rect(0, 134, 1920, 810));
+        // 1437.5 wide rounds to 1438 rather than down to 1436
+        let layout = Layout::new(Size::new(2875, 2160), None, scale, Fit::Letterbox).unwrap();
+        assert_eq!(layout.placement, rect(240, 0, 1438, 1080));
+        let layout = Layout::new(Size::new(800, 600), None, scale, Fit::Stretch).unwrap();
+        assert_eq!(layout.placement, rect(0, 0, 1920, 1080));
+    }
+}
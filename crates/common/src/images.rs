@@ -0,0 +1,185 @@
+//! Naming and selection of image sequence frames.
+
+use std::{
+    fmt,
+    path::{
+        Path,
+        PathBuf,
+    },
+    str::FromStr,
+};
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    Png,
+    /// OpenEXR with 32-bit float channels.
+    Exr,
+    Tga,
+    /// Packed 8-bit RGB without any header.
+    Raw,
+}
+
+/// Inclusive range of virtual frames, `100-200`, `100-` or `-200`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct FrameRange {
+    pub first: Option<u64>,
+    pub last: Option<u64>,
+}
+
+impl ImageFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Exr => "exr",
+            ImageFormat::Tga => "tga",
+            ImageFormat::Raw => "raw",
+        }
+    }
+
+    /// Guesses the format from the extension of `path`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "png" => Ok(ImageFormat::Png),
+            "exr" => Ok(ImageFormat::Exr),
+            "tga" | "targa" => Ok(ImageFormat::Tga),
+            "raw" | "rgb" => Ok(ImageFormat::Raw),
+            _ => Err(format!(
+                "unknown image format `{s}`, expected png, exr, tga or raw"
+            )),
+        }
+    }
+}
+
+impl FrameRange {
+    pub fn contains(&self, frame: u64) -> bool {
+        self.first.is_none_or(|first| frame >= first) && self.last.is_none_or(|last| frame <= last)
+    }
+}
+
+impl fmt::Display for FrameRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(first) = self.first {
+            write!(f, "{first}")?;
+        }
+        write!(f, "-")?;
+        if let Some(last) = self.last {
+            write!(f, "{last}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for FrameRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("`{s}` is not a frame range like 100-200, 100- or -200");
+        let (first, last) = s.split_once('-').ok_or_else(err)?;
+        let bound = |n: &str| match n.trim() {
+            "" => Ok(None),
+            n => n.parse().map(Some).map_err(|_| err()),
+        };
+        let range = FrameRange {
+            first: bound(first)?,
+            last: bound(last)?,
+        };
+        if let (Some(first), Some(last)) = (range.first, range.last)
+            && first > last
+        {
+            Err(err())?
+        }
+        Ok(range)
+    }
+}
+
+impl TryFrom<String> for FrameRange {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<FrameRange> for String {
+    fn from(range: FrameRange) -> Self {
+        range.to_string()
+    }
+}
+
+/// Path of `frame` in a sequence named by `pattern`, whose `%d` or `%06d`
+/// is replaced by the frame number. Without a placeholder the number is
+/// appended to the file stem.
+pub fn frame_path(pattern: &Path, frame: u64) -> PathBuf {
+    let s = pattern.to_string_lossy();
+    if let Some(start) = s.find('%') {
+        let spec = &s[start + 1..];
+        if let Some(end) = spec.find('d')
+            && spec[..end].bytes().all(|b| b.is_ascii_digit())
+        {
+            let width = spec[..end].parse().unwrap_or(0);
+            let rest = &spec[end + 1..];
+            return format!("{}{frame:0width$}{rest}", &s[..start]).into();
+        }
+    }
+    let mut stem = pattern.file_stem().unwrap_or_default().to_owned();
+    stem.push(format!("-{frame:06}"));
+    let mut path = pattern.with_file_name(stem);
+    if let Some(ext) = pattern.extension() {
+        path.set_extension(ext);
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_frame_range() {
+        let range: FrameRange = "100-200".parse().unwrap();
+        assert!(!range.contains(99));
+        assert!(range.contains(100) && range.contains(200));
+        assert!(!range.contains(201));
+        let open: FrameRange = "-5".parse().unwrap();
+        assert_eq!(open.first, None);
+        assert!(open.contains(0) && !open.contains(6));
+        assert_eq!("7-".parse::<FrameRange>().unwrap().to_string(), "7-");
+        assert!("200-100".parse::<FrameRange>().is_err());
+        assert!("100".parse::<FrameRange>().is_err());
+    }
+
+    #[test]
+    fn name_frames() {
+        assert_eq!(
+            frame_path(Path::new("shots/f%06d.png"), 42),
+            Path::new("shots/f000042.png")
+        );
+        assert_eq!(
+            frame_path(Path::new("%d.exr"), 1234567),
+            Path::new("1234567.exr")
+        );
+        assert_eq!(
+            frame_path(Path::new("out/shot.tga"), 7),
+            Path::new("out/shot-000007.tga")
+        );
+        assert_eq!(
+            ImageFormat::from_path(Path::new("a.TGA")),
+            Some(ImageFormat::Tga)
+        );
+    }
+}
@@ -0,0 +1,256 @@
+//! Encoder option strings such as `preset=slow; x264-params="keyint=60:bframes=0"`.
+//!
+//! Options are `key=value` pairs separated by `;`. Whitespace around keys and
+//! values is ignored. A value (or key) may contain `;`, `=` or whitespace if it
+//! is quoted with `"..."` or `'...'`, or if the character is escaped with `\`.
+//! Inside double quotes `\` still escapes the next character, single quotes
+//! take everything literally.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    iter::Peekable,
+    str::CharIndices,
+};
+
+pub fn parse(s: &str) -> Result<BTreeMap<String, String>, OptionsError> {
+    let mut options = BTreeMap::new();
+    parse_into(&mut options, s)?;
+    Ok(options)
+}
+
+/// Like [`parse`], adding to `options`. A key already in `options` is a
+/// duplicate just like one repeated within `s`.
+pub fn parse_into(options: &mut BTreeMap<String, String>, s: &str) -> Result<(), OptionsError> {
+    let mut chars = s.char_indices().peekable();
+    loop {
+        while chars
+            .next_if(|(_, c)| c.is_whitespace() || *c == ';')
+            .is_some()
+        {}
+        let Some(&(key_pos, _)) = chars.peek() else {
+            break;
+        };
+        let key = token(&mut chars, &['=', ';'])?;
+        if key.is_empty() {
+            Err(OptionsError::new(key_pos, ErrorKind::EmptyKey))?
+        }
+        if chars.next_if(|(_, c)| *c == '=').is_none() {
+            return Err(OptionsError::new(key_pos, ErrorKind::MissingValue(key)));
+        }
+        let value = token(&mut chars, &[';'])?;
+        if options.contains_key(&key) {
+            return Err(OptionsError::new(key_pos, ErrorKind::Duplicate(key)));
+        }
+        options.insert(key, value);
+    }
+    Ok(())
+}
+
+/// Inverse of [`parse`], quoting values where necessary.
+pub fn format(options: &BTreeMap<String, String>) -> String {
+    options
+        .iter()
+        .map(|(k, v)| format!("{}={}", quote(k), quote(v)))
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+fn quote(s: &str) -> String {
+    let plain = !s.is_empty()
+        && !s.starts_with(char::is_whitespace)
+        && !s.ends_with(char::is_whitespace)
+        && !s.contains(['=', ';', '"', '\'', '\\']);
+    if plain {
+        return s.to_owned();
+    }
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        if matches!(c, '"' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Reads a key or value up to an unquoted, unescaped delimiter, which is left
+/// in `chars`.
+fn token(chars: &mut Peekable<CharIndices>, delimiters: &[char]) -> Result<String, OptionsError> {
+    let mut out = String::new();
+    // length of `out` without trailing unquoted whitespace
+    let mut kept = 0;
+    while let Some(&(pos, c)) = chars.peek() {
+        if delimiters.contains(&c) {
+            break;
+        }
+        chars.next();
+        match c {
+            '\\' => {
+                let (_, e) = chars
+                    .next()
+                    .ok_or(OptionsError::new(pos, ErrorKind::TrailingEscape))?;
+                out.push(e);
+            }
+            '"' | '\'' => loop {
+                let (_, q) = chars
+                    .next()
+                    .ok_or(OptionsError::new(pos, ErrorKind::UnterminatedQuote))?;
+                match q {
+                    _ if q == c => break,
+                    '\\' if c == '"' => {
+                        let (_, e) = chars
+                            .next()
+                            .ok_or(OptionsError::new(pos, ErrorKind::UnterminatedQuote))?;
+                        out.push(e);
+                    }
+                    _ => out.push(q),
+                }
+            },
+            _ if c.is_whitespace() => {
+                if !out.is_empty() {
+                    out.push(c);
+                }
+                continue;
+            }
+            _ => out.push(c),
+        }
+        kept = out.len();
+    }
+    out.truncate(kept);
+    Ok(out)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionsError {
+    /// Byte offset into the option string.
+    pub position: usize,
+    pub kind: ErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    EmptyKey,
+    MissingValue(String),
+    Duplicate(String),
+    UnterminatedQuote,
+    TrailingEscape,
+}
+
+impl OptionsError {
+    fn new(position: usize, kind: ErrorKind) -> Self {
+        Self { position, kind }
+    }
+}
+
+impl fmt::Display for OptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pos = self.position;
+        match &self.kind {
+            ErrorKind::EmptyKey => write!(f, "empty option name at {pos}"),
+            ErrorKind::MissingValue(k) => {
+                write!(f, "option `{k}` at {pos} has no value, expected `{k}=...`")
+            }
+            ErrorKind::Duplicate(k) => write!(f, "option `{k}` at {pos} given more than once"),
+            ErrorKind::UnterminatedQuote => write!(f, "quote at {pos} is never closed"),
+            ErrorKind::TrailingEscape => write!(f, "`\\` at {pos} escapes nothing"),
+        }
+    }
+}
+
+impl std::error::Error for OptionsError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_plain() {
+        assert_eq!(
+            parse("crf=18;preset=slow").unwrap(),
+            map(&[("crf", "18"), ("preset", "slow")])
+        );
+        assert_eq!(
+            parse(" crf = 18 ; ; preset=slow;").unwrap(),
+            map(&[("crf", "18"), ("preset", "slow")])
+        );
+        assert_eq!(parse("").unwrap(), map(&[]));
+        assert_eq!(parse("tune=").unwrap(), map(&[("tune", "")]));
+    }
+
+    #[test]
+    fn parse_quoted() {
+        assert_eq!(
+            parse(r#"x264-params="keyint=60:bframes=0";vf='scale=1280:-1; fps=30'"#).unwrap(),
+            map(&[
+                ("x264-params", "keyint=60:bframes=0"),
+                ("vf", "scale=1280:-1; fps=30")
+            ])
+        );
+        assert_eq!(
+            parse(r#"path="C:\\My Videos\\a \"b\"""#).unwrap(),
+            map(&[("path", r#"C:\My Videos\a "b""#)])
+        );
+        assert_eq!(
+            parse(r"path='C:\temp'").unwrap(),
+            map(&[("path", r"C:\temp")])
+        );
+        assert_eq!(parse(r#"a=" padded ""#).unwrap(), map(&[("a", " padded ")]));
+    }
+
+    #[test]
+    fn parse_escaped() {
+        assert_eq!(
+            parse(r"a=x\;y\=z;b=with\ space\\").unwrap(),
+            map(&[("a", "x;y=z"), ("b", r"with space\")])
+        );
+        assert_eq!(parse(r"a\=b=c").unwrap(), map(&[("a=b", "c")]));
+    }
+
+    #[test]
+    fn parse_errors() {
+        let kind = |s| parse(s).unwrap_err().kind;
+        assert_eq!(kind("crf"), ErrorKind::MissingValue("crf".to_owned()));
+        assert_eq!(
+            kind("crf=1;preset"),
+            ErrorKind::MissingValue("preset".to_owned())
+        );
+        assert_eq!(kind("=1"), ErrorKind::EmptyKey);
+        assert_eq!(kind("a=1;a=2"), ErrorKind::Duplicate("a".to_owned()));
+        assert_eq!(kind("a=\"x;b=1"), ErrorKind::UnterminatedQuote);
+        assert_eq!(kind("a=x\\"), ErrorKind::TrailingEscape);
+        assert_eq!(parse("a=1;b").unwrap_err().position, 4);
+    }
+
+    #[test]
+    fn parse_into_rejects_existing_keys() {
+        let mut options = parse("a=1").unwrap();
+        parse_into(&mut options, "b=2").unwrap();
+        assert_eq!(options, map(&[("a", "1"), ("b", "2")]));
+        let err = parse_into(&mut options, "c=3;a=4").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Duplicate("a".to_owned()));
+        assert_eq!(err.position, 4);
+    }
+
+    #[test]
+    fn format_round_trip() {
+        let options = map(&[
+            ("crf", "18"),
+            ("x264-params", "keyint=60:bframes=0"),
+            ("vf", "a;b"),
+            ("path", r#"C:\a "b" 'c'"#),
+            ("empty", ""),
+            ("space", " x "),
+        ]);
+        assert_eq!(parse(&format(&options)).unwrap(), options);
+    }
+}
@@ -0,0 +1,346 @@
+//! Rows of captured images and packing of their 4-byte pixels into 3-byte
+//! ones, with SIMD paths where the CPU has them.
+
+use std::{
+    iter::FusedIterator,
+    sync::LazyLock,
+};
+
+/// Which bytes of a 4-byte pixel make up the packed pixel, in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shuffle([u8; 3]);
+
+impl Shuffle {
+    /// Keeps the first three bytes, dropping the fourth.
+    pub const DROP_LAST: Shuffle = Shuffle::new([0, 1, 2]);
+    /// Reverses the first three bytes, BGRA to RGB or RGBA to BGR.
+    pub const REVERSE: Shuffle = Shuffle::new([2, 1, 0]);
+
+    pub const fn new(order: [u8; 3]) -> Self {
+        assert!(order[0] < 4 && order[1] < 4 && order[2] < 4);
+        Self(order)
+    }
+}
+
+/// Row packing implementations, fastest last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kernel {
+    Scalar,
+    #[cfg(target_arch = "x86_64")]
+    Ssse3,
+    #[cfg(target_arch = "x86_64")]
+    Avx2,
+}
+
+impl Kernel {
+    fn available() -> Vec<Kernel> {
+        #[allow(unused_mut)]
+        let mut kernels = vec![Kernel::Scalar];
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("ssse3") {
+                kernels.push(Kernel::Ssse3);
+            }
+            if is_x86_feature_detected!("avx2") {
+                kernels.push(Kernel::Avx2);
+            }
+        }
+        kernels
+    }
+
+    fn pack_row(self, src: &[u8], dst: &mut [u8], shuffle: Shuffle) {
+        let width = (src.len() / 4).min(dst.len() / 3);
+        let (src, dst) = (&src[..width * 4], &mut dst[..width * 3]);
+        match self {
+            Kernel::Scalar => pack_row_scalar(src, dst, shuffle),
+            // Only chosen when the CPU has the feature.
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Ssse3 => unsafe { x86::pack_row_ssse3(src, dst, shuffle) },
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx2 => unsafe { x86::pack_row_avx2(src, dst, shuffle) },
+        }
+    }
+}
+
+static KERNEL: LazyLock<Kernel> = LazyLock::new(|| *Kernel::available().last().unwrap());
+
+/// Packs as many pixels as both `src` and `dst` hold.
+pub fn pack_row(src: &[u8], dst: &mut [u8], shuffle: Shuffle) {
+    KERNEL.pack_row(src, dst, shuffle);
+}
+
+/// Rows of an image that are `pitch` bytes apart, top first or, from the
+/// back, bottom first.
+#[derive(Debug, Clone)]
+pub struct RowsByPitch<'a> {
+    data: &'a [u8],
+    width: usize,
+    pitch: usize,
+    /// Rows `front..back` are left.
+    front: usize,
+    back: usize,
+}
+
+/// The `height` rows of `width` bytes in `data`, which the last row may end.
+///
+/// Panics if `data` is too short or rows would overlap.
+pub fn rows_by_pitch(data: &[u8], width: usize, height: usize, pitch: usize) -> RowsByPitch<'_> {
+    assert!(pitch >= width);
+    if height > 0 {
+        assert!(data.len() >= (height - 1) * pitch + width);
+    }
+    RowsByPitch {
+        data,
+        width,
+        pitch,
+        front: 0,
+        back: height,
+    }
+}
+
+impl<'a> RowsByPitch<'a> {
+    fn row(&self, y: usize) -> &'a [u8] {
+        &self.data[y * self.pitch..][..self.width]
+    }
+}
+
+impl<'a> Iterator for RowsByPitch<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.front == self.back {
+            None?
+        }
+        self.front += 1;
+        Some(self.row(self.front - 1))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.back - self.front;
+        (len, Some(len))
+    }
+}
+
+impl<'a> DoubleEndedIterator for RowsByPitch<'a> {
+    fn next_back(&mut self) -> Option<&'a [u8]> {
+        if self.front == self.back {
+            None?
+        }
+        self.back -= 1;
+        Some(self.row(self.back))
+    }
+}
+
+impl ExactSizeIterator for RowsByPitch<'_> {}
+
+impl FusedIterator for RowsByPitch<'_> {}
+
+fn pack_row_scalar(src: &[u8], dst: &mut [u8], Shuffle(order): Shuffle) {
+    let [a, b, c] = order.map(usize::from);
+    let (src, _) = src.as_chunks::<4>();
+    let (dst, _) = dst.as_chunks_mut::<3>();
+    for (out, px) in dst.iter_mut().zip(src) {
+        *out = [px[a], px[b], px[c]];
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use super::{
+        Shuffle,
+        pack_row_scalar,
+    };
+
+    /// `pshufb` mask moving the packed bytes of four pixels to the front.
+    fn mask(Shuffle(order): Shuffle) -> [u8; 16] {
+        let mut mask = [0x80; 16];
+        for px in 0..4 {
+            for (c, &from) in order.iter().enumerate() {
+                mask[px * 3 + c] = px as u8 * 4 + from;
+            }
+        }
+        mask
+    }
+
+    /// Stores 16 bytes for every 12 packed ones, so it stops while the row
+    /// has room for the spare four.
+    #[target_feature(enable = "ssse3")]
+    pub(super) fn pack_row_ssse3(src: &[u8], dst: &mut [u8], shuffle: Shuffle) {
+        let mut x = 0;
+        unsafe {
+            let mask = _mm_loadu_si128(mask(shuffle).as_ptr().cast());
+            while x * 3 + 16 <= dst.len() {
+                let px = _mm_loadu_si128(src.as_ptr().add(x * 4).cast());
+                let packed = _mm_shuffle_epi8(px, mask);
+                _mm_storeu_si128(dst.as_mut_ptr().add(x * 3).cast(), packed);
+                x += 4;
+            }
+        }
+        pack_row_scalar(&src[x * 4..], &mut dst[x * 3..], shuffle);
+    }
+
+    /// Packs eight pixels at a time, each 128-bit lane like SSSE3 before the
+    /// lanes are joined, storing 32 bytes for 24 packed ones.
+    #[target_feature(enable = "avx2")]
+    pub(super) fn pack_row_avx2(src: &[u8], dst: &mut [u8], shuffle: Shuffle) {
+        let mut x = 0;
+        unsafe {
+            let lane = mask(shuffle);
+            let mut both = [0; 32];
+            both[..16].copy_from_slice(&lane);
+            both[16..].copy_from_slice(&lane);
+            let mask = _mm256_loadu_si256(both.as_ptr().cast());
+            let join = _mm256_setr_epi32(0, 1, 2, 4, 5, 6, 3, 7);
+            while x * 3 + 32 <= dst.len() {
+                let px = _mm256_loadu_si256(src.as_ptr().add(x * 4).cast());
+                let packed = _mm256_permutevar8x32_epi32(_mm256_shuffle_epi8(px, mask), join);
+                _mm256_storeu_si256(dst.as_mut_ptr().add(x * 3).cast(), packed);
+                x += 8;
+            }
+        }
+        pack_row_ssse3(&src[x * 4..], &mut dst[x * 3..], shuffle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Size;
+
+    /// Pixels whose bytes all differ, so any misplaced byte shows.
+    fn image(pitch: usize, height: usize) -> Vec<u8> {
+        (0..pitch * height)
+            .map(|i| (i * 7 + i / 251) as u8)
+            .collect()
+    }
+
+    fn expected(
+        src: &[u8],
+        src_pitch: usize,
+        dst: &mut [u8],
+        dst_pitch: usize,
+        width: usize,
+        height: usize,
+        Shuffle(order): Shuffle,
+    ) {
+        for y in 0..height {
+            for x in 0..width {
+                for c in 0..3 {
+                    dst[y * dst_pitch + x * 3 + c] = src[y * src_pitch + x * 4 + order[c] as usize];
+                }
+            }
+        }
+    }
+
+    /// Packs an image of `size`, whose rows are `src_pitch` bytes apart in
+    /// `src`, into rows `dst_pitch` bytes apart in `dst`.
+    fn pack_rows_with(
+        kernel: Kernel,
+        src: &[u8],
+        src_pitch: usize,
+        dst: &mut [u8],
+        dst_pitch: usize,
+        size: Size,
+        shuffle: Shuffle,
+    ) {
+        let (width, height) = (size.width as usize, size.height as usize);
+        assert!(src_pitch >= width * 4 && dst_pitch >= width * 3);
+        for y in 0..height {
+            let src = &src[y * src_pitch..][..width * 4];
+            let dst = &mut dst[y * dst_pitch..][..width * 3];
+            kernel.pack_row(src, dst, shuffle);
+        }
+    }
+
+    #[test]
+    fn kernels_match_reference() {
+        for kernel in Kernel::available() {
+            for width in [0, 1, 3, 4, 5, 7, 8, 11, 12, 15, 16, 17, 31, 33, 100] {
+                for (height, src_pad, dst_pad) in [(1, 0, 0), (3, 12, 0), (4, 64, 5)] {
+                    for shuffle in [
+                        Shuffle::DROP_LAST,
+                        Shuffle::REVERSE,
+                        Shuffle::new([3, 0, 0]),
+                    ] {
+                        let src_pitch = width * 4 + src_pad;
+                        let dst_pitch = width * 3 + dst_pad;
+                        let src = image(src_pitch, height);
+                        let mut want = vec![0xaa; dst_pitch * height];
+                        expected(
+                            &src, src_pitch, &mut want, dst_pitch, width, height, shuffle,
+                        );
+                        let mut got = vec![0xaa; dst_pitch * height];
+                        let size = Size::new(width as _, height as _);
+                        pack_rows_with(kernel, &src, src_pitch, &mut got, dst_pitch, size, shuffle);
+                        assert_eq!(got, want, "{kernel:?} {width}x{height} {shuffle:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pack_row_stops_at_shorter_buffer() {
+        let src = image(4 * 20, 1);
+        let mut dst = [0; 3 * 10 + 2];
+        pack_row(&src, &mut dst, Shuffle::DROP_LAST);
+        assert_eq!(dst[27..30], src[36..39]);
+        assert_eq!(dst[30..], [0, 0]);
+    }
+
+    #[test]
+    fn rows_both_ways() {
+        // Three rows of two bytes, with a pitch of three and no padding after
+        // the last row.
+        let data = [1, 2, 0, 3, 4, 0, 5, 6];
+        let rows = rows_by_pitch(&data, 2, 3, 3);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows.clone().collect::<Vec<_>>(), [[1, 2], [3, 4], [5, 6]]);
+        assert_eq!(rows.rev().collect::<Vec<_>>(), [[5, 6], [3, 4], [1, 2]]);
+    }
+
+    #[test]
+    fn rows_meet_in_the_middle() {
+        let data = [1, 2, 3, 4];
+        let mut rows = rows_by_pitch(&data, 1, 4, 1);
+        assert_eq!(rows.next(), Some(&[1][..]));
+        assert_eq!(rows.next_back(), Some(&[4][..]));
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows.next_back(), Some(&[3][..]));
+        assert_eq!(rows.next(), Some(&[2][..]));
+        assert_eq!(rows.len(), 0);
+        assert_eq!(rows.next(), None);
+        assert_eq!(rows.next_back(), None);
+    }
+
+    #[test]
+    fn no_rows() {
+        let mut rows = rows_by_pitch(&[], 4, 0, 8);
+        assert_eq!(rows.len(), 0);
+        assert_eq!(rows.next_back(), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn reject_short_rows() {
+        rows_by_pitch(&[0; 7], 2, 3, 3);
+    }
+
+    #[test]
+    #[should_panic]
+    fn reject_short_source() {
+        let src = vec![0; 4 * 10 * 2];
+        let mut dst = vec![0; 3 * 10 * 2];
+        pack_rows_with(
+            *KERNEL,
+            &src,
+            4 * 11,
+            &mut dst,
+            3 * 10,
+            Size::new(10, 2),
+            Shuffle::DROP_LAST,
+        );
+    }
+}
@@ -0,0 +1,344 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    num::NonZeroU64,
+    path::Path,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    config::{
+        Backpressure,
+        ColorMatrix,
+        ColorRange,
+        GraphicsSystem,
+        SegmentLimit,
+        SinkConfig,
+        SoundSystem,
+        StartDate,
+        TimeHook,
+        ToneMap,
+    },
+    geometry::{
+        Fit,
+        Rect,
+        ScaleAlgorithm,
+        Size,
+    },
+    images::{
+        FrameRange,
+        ImageFormat,
+    },
+};
+
+pub const DEFAULT_PROFILE_FILE: &str = "recordin.toml";
+
+/// A set of recording settings, every one of them optional so that profiles
+/// and command line flags can be layered on top of each other.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Profile {
+    pub fps: Option<f64>,
+    pub graphics: Option<GraphicsSystem>,
+    pub sound: Option<SoundSystem>,
+    pub video_encoder: Option<String>,
+    pub video_options: BTreeMap<String, String>,
+    pub pixel_format: Option<String>,
+    pub color_matrix: Option<ColorMatrix>,
+    pub color_range: Option<ColorRange>,
+    pub crop: Option<Rect>,
+    pub scale: Option<Size>,
+    pub fit: Option<Fit>,
+    pub scale_algorithm: Option<ScaleAlgorithm>,
+    pub continuous: Option<bool>,
+    pub segment: Option<SegmentLimit>,
+    pub video_output: Option<String>,
+    pub video_container: Option<String>,
+    pub video_container_options: BTreeMap<String, String>,
+    pub audio_encoder: Option<String>,
+    pub audio_options: BTreeMap<String, String>,
+    pub audio_sample_rate: Option<u32>,
+    pub audio_bitrate: Option<u64>,
+    pub audio_output: Option<String>,
+    pub audio_container: Option<String>,
+    pub audio_container_options: BTreeMap<String, String>,
+    pub merge_audio: Option<bool>,
+    pub images_output: Option<String>,
+    pub image_format: Option<ImageFormat>,
+    pub image_frames: Option<FrameRange>,
+    pub image_every: Option<NonZeroU64>,
+    pub recoverable: Option<bool>,
+    pub flip_vertical: Option<bool>,
+    pub tone_map: Option<ToneMap>,
+    pub start_date: Option<StartDate>,
+    pub disabled_time_hooks: Vec<TimeHook>,
+    pub backpressure: Option<Backpressure>,
+    pub sinks: Vec<SinkConfig>,
+    pub target_regex: Option<String>,
+    pub aggressive: Option<bool>,
+    pub force_tick: Option<u64>,
+}
+
+impl Profile {
+    /// Layers `over` on top of `self`; values present in `over` win.
+    /// Encoder and container options are merged key by key, sinks and
+    /// disabled time hooks given in `over` replace those of `self`.
+    pub fn merge(self, over: Profile) -> Profile {
+        let mut video_options = self.video_options;
+        video_options.extend(over.video_options);
+        let mut audio_options = self.audio_options;
+        audio_options.extend(over.audio_options);
+        let mut video_container_options = self.video_container_options;
+        video_container_options.extend(over.video_container_options);
+        let mut audio_container_options = self.audio_container_options;
+        audio_container_options.extend(over.audio_container_options);
+        let sinks = if over.sinks.is_empty() {
+            self.sinks
+        } else {
+            over.sinks
+        };
+        let disabled_time_hooks = if over.disabled_time_hooks.is_empty() {
+            self.disabled_time_hooks
+        } else {
+            over.disabled_time_hooks
+        };
+        Profile {
+            fps: over.fps.or(self.fps),
+            graphics: over.graphics.or(self.graphics),
+            sound: over.sound.or(self.sound),
+            video_encoder: over.video_encoder.or(self.video_encoder),
+            video_options,
+            pixel_format: over.pixel_format.or(self.pixel_format),
+            color_matrix: over.color_matrix.or(self.color_matrix),
+            color_range: over.color_range.or(self.color_range),
+            crop: over.crop.or(self.crop),
+            scale: over.scale.or(self.scale),
+            fit: over.fit.or(self.fit),
+            scale_algorithm: over.scale_algorithm.or(self.scale_algorithm),
+            continuous: over.continuous.or(self.continuous),
+            segment: over.segment.or(self.segment),
+            video_output: over.video_output.or(self.video_output),
+            video_container: over.video_container.or(self.video_container),
+            video_container_options,
+            audio_encoder: over.audio_encoder.or(self.audio_encoder),
+            audio_options,
+            audio_sample_rate: over.audio_sample_rate.or(self.audio_sample_rate),
+            audio_bitrate: over.audio_bitrate.or(self.audio_bitrate),
+            audio_output: over.audio_output.or(self.audio_output),
+            audio_container: over.audio_container.or(self.audio_container),
+            audio_container_options,
+            merge_audio: over.merge_audio.or(self.merge_audio),
+            images_output: over.images_output.or(self.images_output),
+            image_format: over.image_format.or(self.image_format),
+            image_frames: over.image_frames.or(self.image_frames),
+            image_every: over.image_every.or(self.image_every),
+            recoverable: over.recoverable.or(self.recoverable),
+            flip_vertical: over.flip_vertical.or(self.flip_vertical),
+            tone_map: over.tone_map.or(self.tone_map),
+            start_date: over.start_date.or(self.start_date),
+            disabled_time_hooks,
+            backpressure: over.backpressure.or(self.backpressure),
+            sinks,
+            target_regex: over.target_regex.or(self.target_regex),
+            aggressive: over.aggressive.or(self.aggressive),
+            force_tick: over.force_tick.or(self.force_tick),
+        }
+    }
+}
+
+/// Contents of a `recordin.toml` file.
+///
+/// ```toml
+/// default-profile = "tas"
+///
+/// [profile.tas]
+/// fps = 60
+/// graphics = "d3d11"
+/// video-encoder = "libx264"
+/// video-output = "out.mkv"
+///
+/// [profile.tas.video-options]
+/// crf = "18"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ProfileFile {
+    pub default_profile: Option<String>,
+    #[serde(rename = "profile")]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl ProfileFile {
+    pub fn parse(s: &str) -> Result<Self, ProfileError> {
+        toml::from_str(s).map_err(|e| ProfileError::Parse(e.to_string()))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ProfileError> {
+        let s = std::fs::read_to_string(path).map_err(ProfileError::Io)?;
+        Self::parse(&s)
+    }
+
+    /// Looks up `name`, falling back to `default-profile` and then to an
+    /// empty profile when neither is given.
+    pub fn select(&self, name: Option<&str>) -> Result<Profile, ProfileError> {
+        let Some(name) = name.or(self.default_profile.as_deref()) else {
+            return Ok(Profile::default());
+        };
+        self.profiles
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ProfileError::NotFound {
+                name: name.to_owned(),
+                available: self.profiles.keys().cloned().collect(),
+            })
+    }
+}
+
+#[derive(Debug)]
+pub enum ProfileError {
+    Io(std::io::Error),
+    Parse(String),
+    NotFound {
+        name: String,
+        available: Vec<String>,
+    },
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileError::Io(e) => write!(f, "unable to read profile file: {e}"),
+            ProfileError::Parse(e) => write!(f, "invalid profile file: {e}"),
+            ProfileError::NotFound { name, available } if available.is_empty() => {
+                write!(f, "profile `{name}` not found, no profiles defined")
+            }
+            ProfileError::NotFound { name, available } => {
+                write!(
+                    f,
+                    "profile `{name}` not found, available: {}",
+                    available.join(", ")
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProfileError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = r#"
+default-profile = "tas"
+
+[profile.tas]
+fps = 60
+graphics = "d3d11"
+sound = "wasapi"
+video-encoder = "libx264"
+video-output = "out.mkv"
+video-container = "mp4"
+scale = "1920x1080"
+target-regex = "game\\.exe"
+
+[profile.tas.video-options]
+crf = "18"
+preset = "slow"
+
+[profile.tas.video-container-options]
+movflags = "+faststart"
+
+[profile.preview]
+fps = 30.5
+graphics = "vulkan"
+audio-encoder = "flac"
+audio-sample-rate = 44100
+"#;
+
+    #[test]
+    fn parse_profiles() {
+        let file = ProfileFile::parse(FILE).unwrap();
+        assert_eq!(file.default_profile.as_deref(), Some("tas"));
+        let tas = &file.profiles["tas"];
+        assert_eq!(tas.fps, Some(60.0));
+        assert_eq!(tas.graphics, Some(GraphicsSystem::D3D11));
+        assert_eq!(tas.sound, Some(SoundSystem::Wasapi));
+        assert_eq!(tas.video_options["preset"], "slow");
+        assert_eq!(tas.target_regex.as_deref(), Some("game\\.exe"));
+        assert_eq!(tas.scale, Some(Size::new(1920, 1080)));
+        let preview = &file.profiles["preview"];
+        assert_eq!(preview.fps, Some(30.5));
+        assert_eq!(preview.graphics, Some(GraphicsSystem::Vulkan));
+        assert!(preview.video_options.is_empty());
+        assert_eq!(preview.audio_encoder.as_deref(), Some("flac"));
+        assert_eq!(preview.audio_sample_rate, Some(44100));
+    }
+
+    #[test]
+    fn reject_unknown_keys() {
+        let err = ProfileFile::parse("[profile.a]\nfsp = 60\n").unwrap_err();
+        assert!(matches!(err, ProfileError::Parse(_)));
+    }
+
+    #[test]
+    fn select_profile() {
+        let file = ProfileFile::parse(FILE).unwrap();
+        assert_eq!(file.select(None).unwrap().fps, Some(60.0));
+        assert_eq!(file.select(Some("preview")).unwrap().fps, Some(30.5));
+        let err = file.select(Some("missing")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "profile `missing` not found, available: preview, tas"
+        );
+        assert_eq!(
+            ProfileFile::default().select(None).unwrap(),
+            Profile::default()
+        );
+    }
+
+    #[test]
+    fn merge_overrides() {
+        let file = ProfileFile::parse(FILE).unwrap();
+        let base = file.select(Some("tas")).unwrap();
+        let cli = Profile {
+            fps: Some(120.0),
+            video_options: [("crf".to_owned(), "0".to_owned())].into(),
+            merge_audio: Some(true),
+            video_container_options: [("movflags".to_owned(), "+frag_keyframe".to_owned())].into(),
+            ..Default::default()
+        };
+        let merged = base.merge(cli);
+        assert_eq!(merged.fps, Some(120.0));
+        assert_eq!(merged.graphics, Some(GraphicsSystem::D3D11));
+        assert_eq!(merged.video_encoder.as_deref(), Some("libx264"));
+        assert_eq!(merged.video_options["crf"], "0");
+        assert_eq!(merged.video_options["preset"], "slow");
+        assert_eq!(merged.merge_audio, Some(true));
+        assert_eq!(merged.video_container.as_deref(), Some("mp4"));
+        assert_eq!(merged.video_container_options["movflags"], "+frag_keyframe");
+
+        let base = Profile {
+            sinks: vec![SinkConfig::RawVideo("video.raw".into())],
+            disabled_time_hooks: vec![TimeHook::InterruptTime],
+            ..Default::default()
+        };
+        assert_eq!(base.clone().merge(Profile::default()), base);
+        let cli = Profile {
+            sinks: vec![SinkConfig::RawAudio("audio.raw".into())],
+            disabled_time_hooks: vec![TimeHook::TimeGetSystemTime],
+            ..Default::default()
+        };
+        let merged = base.merge(cli.clone());
+        assert_eq!(merged.sinks, cli.sinks);
+        assert_eq!(merged.disabled_time_hooks, cli.disabled_time_hooks);
+    }
+}
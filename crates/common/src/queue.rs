@@ -0,0 +1,131 @@
+//! Bookkeeping of the queues between the capture hooks and the output
+//! threads: what backpressure does when no buffer is free, and which ticks
+//! made it through.
+
+use crate::config::Backpressure;
+
+/// What a capture does when every buffer is in use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shortage {
+    /// Skip this capture.
+    Drop,
+    /// Allocate one more buffer.
+    Grow,
+    /// Wait for the output thread to free one.
+    Wait,
+    /// Wait, as one more buffer would take more than the cap allows.
+    Capped(u64),
+}
+
+impl Backpressure {
+    /// What to do without a free buffer, with `buffers` allocated of at
+    /// most `buffer_bytes` each.
+    pub fn on_shortage(self, buffers: usize, buffer_bytes: usize) -> Shortage {
+        match self {
+            Backpressure::Block => Shortage::Wait,
+            Backpressure::Drop => Shortage::Drop,
+            Backpressure::Grow(cap) if ((buffers + 1) * buffer_bytes) as u64 > cap => {
+                Shortage::Capped(cap)
+            }
+            Backpressure::Grow(_) => Shortage::Grow,
+        }
+    }
+}
+
+/// Counts the ticks an output thread receives, each expected once and in
+/// order from the first one.
+#[derive(Debug, Clone)]
+pub struct TickTally {
+    first: i64,
+    /// Tick following the latest one received.
+    next: i64,
+    pub received: u64,
+    /// Ticks skipped between received ones.
+    pub missing: u64,
+    /// Ticks received more than once or out of order.
+    pub duplicated: u64,
+}
+
+impl TickTally {
+    pub fn new(first: i64) -> Self {
+        Self {
+            first,
+            next: first,
+            received: 0,
+            missing: 0,
+            duplicated: 0,
+        }
+    }
+
+    pub fn record(&mut self, tick: i64) {
+        self.received += 1;
+        if tick < self.next {
+            self.duplicated += 1;
+        } else {
+            self.missing += (tick - self.next) as u64;
+        }
+        self.next = self.next.max(tick + 1);
+    }
+
+    /// Ticks missing up to `last` inclusive, counting those after the latest
+    /// one received.
+    pub fn missing_until(&self, last: i64) -> u64 {
+        self.missing + (last + 1 - self.next).max(0) as u64
+    }
+
+    pub fn first(&self) -> i64 {
+        self.first
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shortage_by_policy() {
+        assert_eq!(Backpressure::Block.on_shortage(10, 1000), Shortage::Wait);
+        assert_eq!(Backpressure::Drop.on_shortage(10, 1000), Shortage::Drop);
+        assert_eq!(
+            Backpressure::Grow(11000).on_shortage(10, 1000),
+            Shortage::Grow
+        );
+        assert_eq!(
+            Backpressure::Grow(11000).on_shortage(11, 1000),
+            Shortage::Capped(11000)
+        );
+        assert_eq!(Backpressure::Grow(0).on_shortage(0, 0), Shortage::Grow);
+    }
+
+    #[test]
+    fn tally_in_order() {
+        let mut tally = TickTally::new(5);
+        for tick in 5..10 {
+            tally.record(tick);
+        }
+        assert_eq!((tally.received, tally.missing, tally.duplicated), (5, 0, 0));
+        assert_eq!(tally.missing_until(9), 0);
+        assert_eq!(tally.first(), 5);
+    }
+
+    #[test]
+    fn tally_gaps_and_repeats() {
+        let mut tally = TickTally::new(0);
+        for tick in [0, 1, 1, 4, 3, 5] {
+            tally.record(tick);
+        }
+        assert_eq!(tally.received, 6);
+        assert_eq!(tally.missing, 2);
+        assert_eq!(tally.duplicated, 2);
+        // Dropped after the last received tick.
+        assert_eq!(tally.missing_until(8), 5);
+        assert_eq!(tally.missing_until(5), 2);
+    }
+
+    #[test]
+    fn tally_nothing_received() {
+        let tally = TickTally::new(3);
+        assert_eq!(tally.missing_until(2), 0);
+        assert_eq!(tally.missing_until(4), 2);
+    }
+}
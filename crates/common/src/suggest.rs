@@ -0,0 +1,80 @@
+/// Picks the candidates that look like a misspelling of `name`, best first.
+pub fn close_matches<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Vec<&'a str> {
+    const MAX: usize = 5;
+    let name = name.to_lowercase();
+    let threshold = (name.chars().count() / 3).max(2);
+    let mut scored: Vec<_> = candidates
+        .into_iter()
+        .filter_map(|c| {
+            let lower = c.to_lowercase();
+            let distance = strsim::damerau_levenshtein(&name, &lower);
+            if distance <= threshold {
+                Some((distance, c))
+            } else if lower.contains(&name) || (name.len() >= 3 && name.contains(&lower)) {
+                Some((threshold + 1, c))
+            } else {
+                None
+            }
+        })
+        .collect();
+    scored.sort();
+    scored.dedup_by(|a, b| a.1 == b.1);
+    scored.into_iter().take(MAX).map(|(_, c)| c).collect()
+}
+
+/// Formats [`close_matches`] as a sentence to append to an error message.
+pub fn did_you_mean<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> String {
+    let matches = close_matches(name, candidates);
+    if matches.is_empty() {
+        String::new()
+    } else {
+        format!(", did you mean {}?", matches.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENCODERS: &[&str] = &[
+        "libx264", "libx265", "h264_qsv", "hevc_qsv", "ffv1", "utvideo",
+    ];
+
+    #[test]
+    fn typo() {
+        assert_eq!(
+            close_matches("libx246", ENCODERS.iter().copied())[0],
+            "libx264"
+        );
+        assert_eq!(close_matches("ffv2", ENCODERS.iter().copied()), ["ffv1"]);
+        assert_eq!(
+            close_matches("LIBX265", ENCODERS.iter().copied())[0],
+            "libx265"
+        );
+    }
+
+    #[test]
+    fn substring() {
+        assert_eq!(
+            close_matches("qsv", ENCODERS.iter().copied()),
+            ["h264_qsv", "hevc_qsv"]
+        );
+        assert_eq!(
+            close_matches("x264", ENCODERS.iter().copied())[0],
+            "libx264"
+        );
+    }
+
+    #[test]
+    fn nothing_close() {
+        assert!(close_matches("prores", ENCODERS.iter().copied()).is_empty());
+        assert_eq!(did_you_mean("prores", ENCODERS.iter().copied()), "");
+        assert_eq!(
+            did_you_mean("ffv2", ENCODERS.iter().copied()),
+            ", did you mean ffv1?"
+        );
+    }
+}
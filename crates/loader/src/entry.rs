@@ -5,7 +5,15 @@ use windows_sys::{
         Foundation::TRUE,
         System::{
             Console::AllocConsole,
-            SystemServices::DLL_PROCESS_ATTACH,
+            LibraryLoader::{
+                GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS,
+                GET_MODULE_HANDLE_EX_FLAG_PIN,
+                GetModuleHandleExW,
+            },
+            SystemServices::{
+                DLL_PROCESS_ATTACH,
+                DLL_PROCESS_DETACH,
+            },
         },
     },
     core::BOOL,
@@ -14,6 +22,7 @@ use windows_sys::{
 use crate::{
     env,
     hook,
+    output,
 };
 
 #[unsafe(no_mangle)]
@@ -21,10 +30,12 @@ use crate::{
 extern "system" fn DllMain(
     _h_module: windows_sys::Win32::Foundation::HMODULE,
     reason: u32,
-    _: *mut core::ffi::c_void,
+    reserved: *mut core::ffi::c_void,
 ) -> BOOL {
     if reason == DLL_PROCESS_ATTACH {
         on_attach();
+    } else if reason == DLL_PROCESS_DETACH {
+        on_detach(!reserved.is_null());
     }
     TRUE
 }
@@ -32,12 +43,34 @@ extern "system" fn DllMain(
 fn on_attach() -> Option<()> {
     start_logger();
     alloc_console();
+    pin();
     if let Err(e) = hook::init() {
         log::warn!("Error occurred while initializing hook: {}", e);
     }
     Some(())
 }
 
+/// Keeps the DLL loaded until the process exits, as the hooks and encoder
+/// threads run its code.
+fn pin() {
+    let mut module = std::ptr::null_mut();
+    let flags = GET_MODULE_HANDLE_EX_FLAG_PIN | GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS;
+    if unsafe { GetModuleHandleExW(flags, pin as *const u16, &mut module) } == 0 {
+        log::warn!("Unable to pin module, unloading it will crash the game");
+    }
+}
+
+fn on_detach(process_exit: bool) {
+    if process_exit {
+        // Other threads are terminated already, nothing can be flushed.
+        output::finalize::report_unfinished();
+    } else {
+        // Only reached if pinning failed. Encoder threads exiting need the
+        // loader lock held here, so they are not waited for.
+        output::finalize::request_shutdown();
+    }
+}
+
 fn alloc_console() {
     if *env::ALLOC_CONSOLE {
         log::info!("Allocating Console Enabled");
@@ -63,3 +96,6 @@ fn start_logger() -> Option<()> {
 
 #[unsafe(no_mangle)]
 static __MAGIC__: u64 = 1145141919810;
+
+#[unsafe(no_mangle)]
+static __CONFIG_VERSION__: u32 = recordin_common::config::CONFIG_VERSION;
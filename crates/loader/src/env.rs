@@ -1,73 +1,95 @@
 use std::{
     cell::Cell,
-    collections::BTreeMap,
-    convert::Into,
     path::PathBuf,
-    str::FromStr,
-    string::ToString,
     sync::LazyLock,
 };
 
-use arrayvec::ArrayString;
 use recordin_common::{
-    ENV_KEY_AGGRESSIVE,
     ENV_KEY_ALLOC_CONSOLE,
-    ENV_KEY_AUDIO_OUTPUT,
-    ENV_KEY_FORCE_TICK_THRESHOLD,
-    ENV_KEY_FPS_F64_HEX,
-    ENV_KEY_GRAPHICS_SYSTEM,
     ENV_KEY_IS_CLI,
     ENV_KEY_LOG_DIR,
-    ENV_KEY_SOUND_SYSTEM,
-    ENV_KEY_TARGET_REGEX,
-    ENV_KEY_VIDEO_ARGS,
-    ENV_KEY_VIDEO_ENCODER,
-    ENV_KEY_VIDEO_OUTPUT,
+    config::{
+        AudioConfig,
+        Backpressure,
+        ConfigError,
+        GraphicsSystem,
+        ImagesConfig,
+        RecordinConfig,
+        SinkConfig,
+        SoundSystem,
+        StartDate,
+        TimeHook,
+        ToneMap,
+        VideoConfig,
+    },
 };
 use regex::{
     Regex,
     RegexBuilder,
 };
 
-pub static FORCE_TICK_THRESHOLD: LazyLock<Option<u64>> = LazyLock::new(|| {
-    let a = std::env::var_os(ENV_KEY_FORCE_TICK_THRESHOLD)?;
-    u64::from_str_radix(&a.to_string_lossy(), 16).ok()
+pub static CONFIG: LazyLock<Result<RecordinConfig, ConfigError>> = LazyLock::new(|| {
+    let config = RecordinConfig::from_env();
+    match &config {
+        Ok(c) => log::info!("Config:\n{c:?}"),
+        Err(e) => log::error!("Refusing to record: {e}"),
+    }
+    config
 });
 
-pub static GRAPHICS_SYSTEM: LazyLock<Option<String>> = LazyLock::new(|| {
-    let e = std::env::var_os(ENV_KEY_GRAPHICS_SYSTEM)?;
-    Some(e.to_string_lossy().to_string().to_lowercase())
-});
+fn config() -> Option<&'static RecordinConfig> {
+    CONFIG.as_ref().ok()
+}
 
-pub static VIDEO_ARGS: LazyLock<Option<BTreeMap<String, String>>> = LazyLock::new(|| {
-    let args = std::env::var_os(ENV_KEY_VIDEO_ARGS)?;
-    let args = args
-        .to_string_lossy()
-        .split(';')
-        .filter_map(|s| s.split_once('='))
-        .map(|(k, v)| (k.to_owned(), v.to_owned()))
-        .collect();
-    log::info!("ffmpeg arguments:\n{:?}", args);
-    Some(args)
-});
+pub static FORCE_TICK_THRESHOLD: LazyLock<Option<u64>> =
+    LazyLock::new(|| config()?.force_tick_threshold);
 
-pub static SOUND_SYSTEM: LazyLock<Option<String>> = LazyLock::new(|| {
-    let e = std::env::var_os(ENV_KEY_SOUND_SYSTEM)?;
-    Some(e.to_string_lossy().to_string().to_lowercase())
-});
+pub static GRAPHICS_SYSTEM: LazyLock<Option<GraphicsSystem>> = LazyLock::new(|| config()?.graphics);
 
-pub static VIDEO_OUTPUT: LazyLock<Option<PathBuf>> = LazyLock::new(|| {
-    let e = std::env::var_os(ENV_KEY_VIDEO_OUTPUT)?;
-    Some(PathBuf::from(e))
-});
+pub static SOUND_SYSTEM: LazyLock<Option<SoundSystem>> = LazyLock::new(|| config()?.sound);
 
-pub static AUDIO_OUTPUT: LazyLock<Option<PathBuf>> = LazyLock::new(|| {
-    let e = std::env::var_os(ENV_KEY_AUDIO_OUTPUT)?;
-    Some(PathBuf::from(e))
-});
+pub fn video() -> Option<&'static VideoConfig> {
+    config()?.video.as_ref()
+}
+
+pub fn audio() -> Option<&'static AudioConfig> {
+    config()?.audio.as_ref()
+}
+
+pub fn images() -> Option<&'static ImagesConfig> {
+    config()?.images.as_ref()
+}
+
+pub fn sinks() -> &'static [SinkConfig] {
+    config().map_or(&[], |c| &c.sinks)
+}
 
-pub fn should_emit_video() -> bool {
-    VIDEO_ENCODER.get().is_some()
+pub fn merge_audio() -> bool {
+    config().is_some_and(|c| c.merge_audio)
+}
+
+pub fn backpressure() -> Backpressure {
+    config().map_or(Backpressure::Block, |c| c.backpressure)
+}
+
+pub fn recoverable() -> bool {
+    config().is_some_and(|c| c.recoverable)
+}
+
+pub fn flip_vertical() -> bool {
+    config().is_some_and(|c| c.flip_vertical)
+}
+
+pub fn tone_map() -> ToneMap {
+    config().map_or(ToneMap::default(), |c| c.tone_map)
+}
+
+pub fn start_date() -> Option<StartDate> {
+    config().and_then(|c| c.start_date)
+}
+
+pub fn time_hook_enabled(hook: TimeHook) -> bool {
+    config().is_none_or(|c| !c.disabled_time_hooks.contains(&hook))
 }
 
 // pub static CMDLINE: LazyLock<OsString> = LazyLock::new(|| {
@@ -76,10 +98,8 @@ pub fn should_emit_video() -> bool {
 // });
 
 pub static TARGET_REGEX: LazyLock<Option<Regex>> = LazyLock::new(|| {
-    let re = std::env::var(ENV_KEY_TARGET_REGEX)
-        .inspect_err(|e| log::warn!("Invalid target regular expression: {e}"))
-        .ok()?;
-    let re = RegexBuilder::new(&re)
+    let re = config()?.target_regex.as_deref()?;
+    let re = RegexBuilder::new(re)
         .case_insensitive(true)
         .build()
         .inspect_err(|e| log::warn!("Invalid target regular expression: {e}"))
@@ -91,8 +111,7 @@ pub static TARGET_REGEX: LazyLock<Option<Regex>> = LazyLock::new(|| {
 pub static PROCESS_IS_CLI: LazyLock<bool> =
     LazyLock::new(|| std::env::var_os(ENV_KEY_IS_CLI).is_some());
 
-pub static AGGRESSIVE: LazyLock<bool> =
-    LazyLock::new(|| std::env::var_os(ENV_KEY_AGGRESSIVE).is_some());
+pub static AGGRESSIVE: LazyLock<bool> = LazyLock::new(|| config().is_some_and(|c| c.aggressive));
 
 pub static LOG_DIR: LazyLock<Option<PathBuf>> = LazyLock::new(|| {
     let dir_var: PathBuf = std::env::var_os(ENV_KEY_LOG_DIR)?.into();
@@ -108,20 +127,7 @@ pub static ALLOC_CONSOLE: LazyLock<bool> =
     LazyLock::new(|| std::env::var_os(ENV_KEY_ALLOC_CONSOLE).is_some());
 
 std::thread_local! {
-    pub static VIDEO_ENCODER: Cell<Option<ArrayString<256>>> = {
-        let a = std::env::var_os(ENV_KEY_VIDEO_ENCODER)
-            .map(|a| ArrayString::from_str(&a.to_string_lossy()).unwrap());
-        Cell::new(a)
-    };
-
-    pub static FPS: Cell<f64> = {
-        let fps = std::env::var(ENV_KEY_FPS_F64_HEX)
-            .ok()
-            .and_then(|s| {
-                u64::from_str_radix(&s, 16).map(f64::from_bits).ok()
-            });
-        Cell::new(fps.unwrap_or(60.))
-    };
+    pub static FPS: Cell<f64> = Cell::new(config().map_or(60., |c| c.fps));
 
     // pub static EXECUTABLE: Cell<ArrayString<256>> = {
     //     let cmd = CMDLINE.to_string_lossy();
@@ -1,13 +1,17 @@
 use crate::env;
 
 mod com;
+mod exit;
 mod graphics;
 mod infect;
 mod lib_load;
 mod sound;
-mod timing;
+pub(crate) mod timing;
 
 pub(super) fn init() -> anyhow::Result<()> {
+    env::CONFIG
+        .as_ref()
+        .map_err(|e| anyhow::anyhow!("invalid config: {e}"))?;
     if *env::PROCESS_IS_CLI || *env::AGGRESSIVE {
         infect::init()?;
     }
@@ -17,5 +21,6 @@ pub(super) fn init() -> anyhow::Result<()> {
     com::init()?;
     lib_load::init()?;
     timing::init()?;
+    exit::init()?;
     Ok(())
 }
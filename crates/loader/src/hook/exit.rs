@@ -0,0 +1,89 @@
+use std::mem;
+
+use parking_lot::Mutex;
+use windows_sys::Win32::{
+    Foundation::HANDLE,
+    System::{
+        Diagnostics::Debug::{
+            EXCEPTION_POINTERS,
+            LPTOP_LEVEL_EXCEPTION_FILTER,
+            SetUnhandledExceptionFilter,
+        },
+        Threading::{
+            ExitProcess,
+            GetCurrentProcessId,
+            GetProcessId,
+            TerminateProcess,
+        },
+    },
+};
+
+use crate::output::finalize;
+
+const EXCEPTION_CONTINUE_SEARCH: i32 = 0;
+const EXCEPTION_CONTINUE_EXECUTION: i32 = -1;
+
+/// The filter the game installed, called from ours so that its crash
+/// handling still works.
+static GAME_FILTER: Mutex<LPTOP_LEVEL_EXCEPTION_FILTER> = Mutex::new(None);
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+unsafe extern "system" fn ExitProcess(exit_code: u32) {
+    log::trace!("ExitProcess");
+    // Encoder threads are killed before DLL_PROCESS_DETACH, so this is the
+    // last chance to write trailers.
+    finalize::finalize_all();
+    unsafe { orig_ExitProcess(exit_code) }
+}
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+unsafe extern "system" fn TerminateProcess(
+    process: HANDLE,
+    exit_code: u32,
+) -> windows_sys::core::BOOL {
+    log::trace!("TerminateProcess");
+    if unsafe { GetProcessId(process) == GetCurrentProcessId() } {
+        finalize::finalize_all();
+    }
+    unsafe { orig_TerminateProcess(process, exit_code) }
+}
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+unsafe extern "system" fn SetUnhandledExceptionFilter(
+    filter: LPTOP_LEVEL_EXCEPTION_FILTER,
+) -> LPTOP_LEVEL_EXCEPTION_FILTER {
+    log::trace!("SetUnhandledExceptionFilter");
+    mem::replace(&mut *GAME_FILTER.lock(), filter)
+}
+
+unsafe extern "system" fn on_unhandled_exception(info: *const EXCEPTION_POINTERS) -> i32 {
+    unsafe {
+        let code = (*(*info).ExceptionRecord).ExceptionCode;
+        log::error!("Unhandled exception {code:#010x}");
+        let filter = *GAME_FILTER.lock();
+        let result = match filter {
+            Some(filter) => filter(info),
+            None => EXCEPTION_CONTINUE_SEARCH,
+        };
+        // Finalizing cannot be undone, so only once the game does not
+        // recover from the exception.
+        if result != EXCEPTION_CONTINUE_EXECUTION {
+            finalize::finalize_all();
+        }
+        result
+    }
+}
+
+pub(super) fn init() -> anyhow::Result<()> {
+    unsafe {
+        let exit_process: unsafe extern "system" fn(u32) -> ! = ExitProcess;
+        init_ExitProcess(mem::transmute::<_, PFN_ExitProcess>(exit_process))?.enable()?;
+        init_TerminateProcess(TerminateProcess)?.enable()?;
+        init_SetUnhandledExceptionFilter(SetUnhandledExceptionFilter)?.enable()?;
+        *GAME_FILTER.lock() = orig_SetUnhandledExceptionFilter(Some(on_unhandled_exception));
+    }
+    Ok(())
+}
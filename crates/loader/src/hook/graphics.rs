@@ -1,80 +1,88 @@
 use std::{
-    iter::FusedIterator,
-    marker::PhantomData,
     ops::ControlFlow,
     slice,
 };
 
-use crate::env;
+use recordin_common::{
+    color::{
+        Converter,
+        SurfaceFormat,
+        Transfer,
+    },
+    config::GraphicsSystem,
+    pixels::{
+        self,
+        RowsByPitch,
+    },
+};
+
+use crate::{
+    env,
+    output::sink::CapturedFrame,
+};
 
 mod dxgi;
 mod vulkan;
 
 pub(super) fn lib_load_hook(filename: &str, h_module: usize) -> ControlFlow<anyhow::Result<()>> {
-    match env::GRAPHICS_SYSTEM.as_deref() {
-        Some("vulkan") => vulkan::lib_load_hook(filename, h_module)?,
-        Some("d3d11") => dxgi::lib_load_hook(filename, h_module)?,
-        _ => {}
+    match *env::GRAPHICS_SYSTEM {
+        Some(GraphicsSystem::Vulkan) => vulkan::lib_load_hook(filename, h_module)?,
+        Some(GraphicsSystem::D3D11) => dxgi::lib_load_hook(filename, h_module)?,
+        None => {}
     }
     ControlFlow::Continue(())
 }
 
 pub(super) fn init_early_loaded() -> Option<anyhow::Result<usize>> {
-    match env::GRAPHICS_SYSTEM.as_deref()? {
-        "vulkan" => Some(vulkan::init_early_loaded()?),
-        "d3d11" => Some(dxgi::init_early_loaded()?),
-        _ => None?,
+    match (*env::GRAPHICS_SYSTEM)? {
+        GraphicsSystem::Vulkan => Some(vulkan::init_early_loaded()?),
+        GraphicsSystem::D3D11 => Some(dxgi::init_early_loaded()?),
     }
 }
 
-#[derive(Debug)]
-struct SlicesByRowPitch<'m> {
+/// The rows of a mapped image, see [`pixels::rows_by_pitch`].
+unsafe fn slices_by_row_pitch<'m>(
     data: *const u8,
     width: usize,
+    height: usize,
     row_pitch: usize,
-    forward: usize,
-    backward: usize,
-    _marker: PhantomData<&'m ()>,
-}
-
-impl<'m> Iterator for SlicesByRowPitch<'m> {
-    type Item = &'m [u8];
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.forward == self.backward {
-            None?;
-        }
-        let p = self.data.wrapping_add(self.forward * self.row_pitch);
-        self.forward += 1;
-        Some(unsafe { slice::from_raw_parts(p, self.width) })
-    }
+) -> RowsByPitch<'m> {
+    let data = match height {
+        0 => &[],
+        _ => unsafe { slice::from_raw_parts(data, (height - 1) * row_pitch + width) },
+    };
+    pixels::rows_by_pitch(data, width, height, row_pitch)
 }
 
-impl<'m> DoubleEndedIterator for SlicesByRowPitch<'m> {
-    fn next_back(&mut self) -> Option<Self::Item> {
-        if self.backward == self.forward {
-            None?;
-        }
-        let p = self.data.wrapping_add(self.backward * self.row_pitch);
-        self.forward -= 1;
-        Some(unsafe { slice::from_raw_parts(p, self.width) })
+/// Converts a mapped image in the format of `converter`, whose rows are
+/// `row_pitch` bytes apart, into `frame`, which must already have the
+/// image's size. The rows are taken bottom first when the capture is
+/// flipped.
+unsafe fn convert_mapped(
+    mapped: *const u8,
+    row_pitch: usize,
+    converter: &Converter,
+    frame: &mut CapturedFrame,
+) {
+    let width = frame.width * converter.format().bytes_per_pixel();
+    let rows = unsafe { slices_by_row_pitch(mapped, width, frame.height, row_pitch) };
+    let stride = frame.stride().max(1);
+    let packed = frame.pixels.chunks_exact_mut(stride);
+    let pack = |(packed, mapped): (&mut [u8], &[u8])| converter.convert_row(mapped, packed);
+    if env::flip_vertical() {
+        packed.zip(rows.rev()).for_each(pack);
+    } else {
+        packed.zip(rows).for_each(pack);
     }
 }
 
-impl FusedIterator for SlicesByRowPitch<'_> {}
-
-unsafe fn slices_by_row_pitch<'m>(
-    data: *const u8,
-    width: usize,
-    height: usize,
-    row_pitch: usize,
-) -> SlicesByRowPitch<'m> {
-    SlicesByRowPitch {
-        data,
-        width,
-        row_pitch,
-        forward: 0,
-        backward: height - 1,
-        _marker: PhantomData,
-    }
+/// The converter of captured surfaces, with the configured tone mapping.
+fn converter(format: SurfaceFormat, transfer: Transfer) -> Converter {
+    let converter = Converter::new(format, transfer, env::tone_map());
+    log::debug!(
+        "Capturing {format:?} {transfer:?} as {:?} {:?}",
+        converter.depth(),
+        converter.transfer()
+    );
+    converter
 }
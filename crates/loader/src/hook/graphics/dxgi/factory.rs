@@ -34,7 +34,7 @@ use windows_sys::{
     core::HRESULT,
 };
 
-use crate::hook::graphics::dxgi::swap_chain::MyDXGISwapChain;
+use crate::hook::graphics::dxgi::swap_chain;
 
 #[recordin_macro::static_hook]
 pub(in super::super) unsafe extern "system" fn CreateDXGIFactory(
@@ -146,8 +146,7 @@ impl IDXGIFactory_Impl for MyDXGIFactory1_Impl {
             if res.is_ok()
                 && let Some(o) = out
             {
-                let maybe: IDXGISwapChain =
-                    MyDXGISwapChain::new(o).map_or_else(|a| a, |b| b.into());
+                let maybe = swap_chain::wrap(o);
                 res = out_swap_chain
                     .write(Some(maybe))
                     .map_or_else(|e| e.code(), |_| windows_core::HRESULT(S_OK));
@@ -1,38 +1,76 @@
 use std::{
     cell::OnceCell,
     mem::MaybeUninit,
-    sync::atomic::{
-        AtomicU64,
-        Ordering,
+    sync::{
+        Arc,
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
     },
 };
 
 use parking_lot::Mutex;
-use windows::Win32::Graphics::{
-    Direct3D11::{
-        D3D11_CPU_ACCESS_READ,
-        D3D11_MAP_READ,
-        D3D11_USAGE_STAGING,
-        ID3D11Device,
-        ID3D11DeviceContext,
-        ID3D11Texture2D,
+use recordin_common::color::{
+    Converter,
+    SurfaceFormat,
+    Transfer,
+};
+use windows::Win32::{
+    Foundation::{
+        HANDLE,
+        HWND,
     },
-    Dxgi::{
-        Common::{
-            DXGI_FORMAT,
-            DXGI_MODE_DESC,
+    Graphics::{
+        Direct3D11::{
+            D3D11_CPU_ACCESS_READ,
+            D3D11_MAP_READ,
+            D3D11_USAGE_STAGING,
+            ID3D11Device,
+            ID3D11DeviceContext,
+            ID3D11Texture2D,
+        },
+        Dxgi::{
+            Common::{
+                DXGI_COLOR_SPACE_RGB_FULL_G10_NONE_P709,
+                DXGI_COLOR_SPACE_RGB_FULL_G2084_NONE_P2020,
+                DXGI_COLOR_SPACE_TYPE,
+                DXGI_FORMAT,
+                DXGI_FORMAT_B8G8R8A8_UNORM,
+                DXGI_FORMAT_B8G8R8A8_UNORM_SRGB,
+                DXGI_FORMAT_B8G8R8X8_UNORM,
+                DXGI_FORMAT_B8G8R8X8_UNORM_SRGB,
+                DXGI_FORMAT_R8G8B8A8_UNORM,
+                DXGI_FORMAT_R8G8B8A8_UNORM_SRGB,
+                DXGI_FORMAT_R10G10B10A2_UNORM,
+                DXGI_FORMAT_R16G16B16A16_FLOAT,
+                DXGI_MODE_DESC,
+                DXGI_MODE_ROTATION,
+                DXGI_RGBA,
+            },
+            DXGI_FRAME_STATISTICS,
+            DXGI_HDR_METADATA_TYPE,
+            DXGI_MATRIX_3X2_F,
+            DXGI_PRESENT,
+            DXGI_PRESENT_PARAMETERS,
+            DXGI_SWAP_CHAIN_DESC,
+            DXGI_SWAP_CHAIN_DESC1,
+            DXGI_SWAP_CHAIN_FLAG,
+            DXGI_SWAP_CHAIN_FULLSCREEN_DESC,
+            IDXGIDeviceSubObject,
+            IDXGIDeviceSubObject_Impl,
+            IDXGIObject,
+            IDXGIObject_Impl,
+            IDXGIOutput,
+            IDXGISwapChain,
+            IDXGISwapChain_Impl,
+            IDXGISwapChain1,
+            IDXGISwapChain1_Impl,
+            IDXGISwapChain2_Impl,
+            IDXGISwapChain3_Impl,
+            IDXGISwapChain4,
+            IDXGISwapChain4_Impl,
         },
-        DXGI_FRAME_STATISTICS,
-        DXGI_PRESENT,
-        DXGI_SWAP_CHAIN_DESC,
-        DXGI_SWAP_CHAIN_FLAG,
-        IDXGIDeviceSubObject,
-        IDXGIDeviceSubObject_Impl,
-        IDXGIObject,
-        IDXGIObject_Impl,
-        IDXGIOutput,
-        IDXGISwapChain,
-        IDXGISwapChain_Impl,
     },
 };
 use windows_core::{
@@ -54,18 +92,47 @@ use crate::{
         timing,
     },
     output::{
-        video_codec,
-        video_codec::EncDuplex,
+        sink,
+        sink::VideoQueue,
     },
 };
 
+/// Wraps a swap chain created on a Direct3D 11 device to capture what it
+/// presents. One without `IDXGISwapChain4` is wrapped as the base interface
+/// and captured in the default color space of its format.
+pub(super) fn wrap(inner: IDXGISwapChain) -> IDXGISwapChain {
+    let Some(capturer) = Capturer::new(&inner) else {
+        return inner;
+    };
+    match inner.cast::<IDXGISwapChain4>() {
+        Ok(inner) => IDXGISwapChain4::from(MyDXGISwapChain4 { inner, capturer }).into(),
+        Err(e) => {
+            log::debug!("DXGI swap chain without IDXGISwapChain4: {}", e);
+            MyDXGISwapChain { inner, capturer }.into()
+        }
+    }
+}
+
 #[implement(IDXGISwapChain)]
-pub(super) struct MyDXGISwapChain {
+struct MyDXGISwapChain {
     inner: IDXGISwapChain,
+    capturer: Capturer,
+}
+
+#[implement(IDXGISwapChain4)]
+struct MyDXGISwapChain4 {
+    inner: IDXGISwapChain4,
+    capturer: Capturer,
+}
+
+struct Capturer {
     frame_count: AtomicU64,
     init_real_time: i64,
     device: ID3D11Device,
     context: ID3D11DeviceContext,
+    /// Set through `SetColorSpace1`, `None` while the default of the format
+    /// applies.
+    color_space: Mutex<Option<DXGI_COLOR_SPACE_TYPE>>,
     present_state: Mutex<OnceCell<PresentState>>,
 }
 
@@ -74,38 +141,43 @@ struct PresentState {
     image: ID3D11Texture2D,
     width: usize,
     height: usize,
-    encoder: Option<EncDuplex>,
+    /// `None` for swap chain formats captures cannot read.
+    format: Option<SurfaceFormat>,
+    /// Color space the converter was made for.
+    color_space: Option<DXGI_COLOR_SPACE_TYPE>,
+    converter: Option<Converter>,
+    encoder: Option<Arc<VideoQueue>>,
 }
 
-impl MyDXGISwapChain {
-    pub(super) fn new(inner: IDXGISwapChain) -> Result<Self, IDXGISwapChain> {
+impl Capturer {
+    fn new(inner: &IDXGISwapChain) -> Option<Self> {
         unsafe {
-            let Ok(device) = inner.GetDevice::<ID3D11Device>().inspect_err(|e| {
-                log::debug!(
-                    "DXGI swap chain created but not on Direct3D 11 device: {}",
-                    e
-                );
-            }) else {
-                Err(inner)?
-            };
+            let device = inner
+                .GetDevice::<ID3D11Device>()
+                .inspect_err(|e| {
+                    log::debug!(
+                        "DXGI swap chain created but not on Direct3D 11 device: {}",
+                        e
+                    );
+                })
+                .ok()?;
             log::debug!("ID3D11Device@{device:?} create IDXGISwapChain@{inner:?}");
             let context = device
                 .GetImmediateContext()
                 .expect("device should have immediate context");
-            let r = Self {
-                inner,
+            Some(Self {
                 frame_count: AtomicU64::new(0),
                 init_real_time: timing::real().0,
                 device,
                 context,
+                color_space: Mutex::new(None),
                 present_state: Mutex::new(OnceCell::new()),
-            };
-            Ok(r)
+            })
         }
     }
 }
 
-impl Drop for MyDXGISwapChain {
+impl Drop for Capturer {
     fn drop(&mut self) {
         log::debug!("MyDXGISwapChain drop");
         let fr = self.frame_count.load(Ordering::Relaxed) as f64;
@@ -119,10 +191,9 @@ impl Drop for MyDXGISwapChain {
     }
 }
 
-#[allow(non_snake_case)]
-impl IDXGISwapChain_Impl for MyDXGISwapChain_Impl {
-    fn Present(&self, sync_interval: u32, flags: DXGI_PRESENT) -> HRESULT {
-        // log::trace!("MyDXGISwapChain Present");
+impl Capturer {
+    /// Copies the image `swap_chain` is about to present to the video output.
+    fn capture(&self, swap_chain: &IDXGISwapChain) {
         self.frame_count.fetch_add(1, Ordering::Relaxed);
         let present_lock = self.present_state.lock();
         let PresentState {
@@ -130,9 +201,11 @@ impl IDXGISwapChain_Impl for MyDXGISwapChain_Impl {
             image,
             width,
             height,
+            converter,
             encoder,
+            ..
         } = present_lock.get_or_init(|| unsafe {
-            let present_image: ID3D11Texture2D = self.inner.GetBuffer(0).unwrap();
+            let present_image: ID3D11Texture2D = swap_chain.GetBuffer(0).unwrap();
             let mut image_desc = MaybeUninit::zeroed();
             present_image.GetDesc(image_desc.as_mut_ptr());
             let mut image_desc = image_desc.assume_init();
@@ -151,12 +224,23 @@ impl IDXGISwapChain_Impl for MyDXGISwapChain_Impl {
                 .CreateTexture2D(&image_desc, None, Some(&mut image))
                 .unwrap();
             let image = image.unwrap();
-            let encoder = video_codec::create_encoder(width, height);
+            let format = surface_format(image_desc.Format);
+            if format.is_none() {
+                log::warn!("Swap chain format {:?} not captured", image_desc.Format);
+            }
+            let color_space = *self.color_space.lock();
+            let converter = converter(format, color_space);
+            let encoder = converter
+                .as_ref()
+                .and_then(|converter| sink::video_queue(width, height, converter));
             PresentState {
                 present_image,
                 image,
                 width,
                 height,
+                format,
+                color_space,
+                converter,
                 encoder,
             }
         });
@@ -169,172 +253,400 @@ impl IDXGISwapChain_Impl for MyDXGISwapChain_Impl {
             let map_res = map_res.assume_init();
             let mapped = map_res.pData;
             let row_pitch = map_res.RowPitch;
-            if let Some((tx, rx)) = encoder
-                && let Ok(mut packed_bgr) = rx.recv()
+            if let Some(queue) = encoder
+                && let Some(converter) = converter
+                && let Some(mut frame) = queue.acquire_at(timing::ticks())
             {
-                packed_bgr.resize(width * height, [0; _]);
-                let packed_lines = packed_bgr.chunks_exact_mut(*width);
-                let mapped_slices = graphics::slices_by_row_pitch(
-                    mapped.cast(),
-                    width * 4,
-                    *height,
-                    row_pitch as usize,
-                );
-                let z = packed_lines.zip(mapped_slices);
-                for (packed_line, mapped_slice) in z {
-                    let (raw_c, _) = mapped_slice.as_chunks();
-                    let zz = packed_line.iter_mut().zip(raw_c);
-                    for (packed, &[b, g, r, _]) in zz {
-                        *packed = [b, g, r];
-                    }
-                }
-                tx.send(packed_bgr).ok();
+                frame.resize(*width, *height, converter);
+                graphics::convert_mapped(mapped.cast(), row_pitch as _, converter, &mut frame);
+                queue.submit(frame);
             }
             self.context.Unmap(image, 0);
         }
         timing::incr_tick();
-        unsafe { self.inner.Present(sync_interval, flags) }
     }
 
-    fn GetBuffer(
-        &self,
-        buffer: u32,
-        iid: *const GUID,
-        out_surface: *mut *mut core::ffi::c_void,
-    ) -> windows_result::Result<()> {
-        // log::trace!("MyDXGISwapChain GetBuffer");
-        let o: &IDXGISwapChain = &self.inner;
-        unsafe { (o.vtable().GetBuffer)(o.as_raw(), buffer, iid, out_surface).ok() }
+    /// Follows a color space set through `SetColorSpace1` from the next
+    /// present on, into the same video output.
+    fn set_color_space(&self, color_space: DXGI_COLOR_SPACE_TYPE) {
+        if self.color_space.lock().replace(color_space) == Some(color_space) {
+            return;
+        }
+        if let Some(state) = self.present_state.lock().get_mut() {
+            state.color_space = Some(color_space);
+            state.converter = converter(state.format, state.color_space);
+        }
     }
+}
 
-    fn SetFullscreenState(
-        &self,
-        fullscreen: BOOL,
-        target: Ref<IDXGIOutput>,
-    ) -> windows_result::Result<()> {
-        log::trace!("MyDXGISwapChain SetFullscreenState");
-        unsafe {
-            self.inner
-                .SetFullscreenState(fullscreen.as_bool(), target.as_ref())
+/// Forwards the interfaces every wrapped swap chain has to the real one,
+/// capturing at `Present`.
+macro_rules! forward_swap_chain {
+    ($name:ident) => {
+        #[allow(non_snake_case)]
+        impl IDXGISwapChain_Impl for $name {
+            fn Present(&self, sync_interval: u32, flags: DXGI_PRESENT) -> HRESULT {
+                // log::trace!("MyDXGISwapChain Present");
+                self.capturer.capture(&self.inner);
+                unsafe { self.inner.Present(sync_interval, flags) }
+            }
+
+            fn GetBuffer(
+                &self,
+                buffer: u32,
+                iid: *const GUID,
+                out_surface: *mut *mut core::ffi::c_void,
+            ) -> windows_result::Result<()> {
+                // log::trace!("MyDXGISwapChain GetBuffer");
+                let o: &IDXGISwapChain = &self.inner;
+                unsafe { (o.vtable().GetBuffer)(o.as_raw(), buffer, iid, out_surface).ok() }
+            }
+
+            fn SetFullscreenState(
+                &self,
+                fullscreen: BOOL,
+                target: Ref<IDXGIOutput>,
+            ) -> windows_result::Result<()> {
+                log::trace!("MyDXGISwapChain SetFullscreenState");
+                unsafe {
+                    self.inner
+                        .SetFullscreenState(fullscreen.as_bool(), target.as_ref())
+                }
+            }
+
+            fn GetFullscreenState(
+                &self,
+                out_fullscreen: *mut BOOL,
+                out_target: OutRef<IDXGIOutput>,
+            ) -> windows_result::Result<()> {
+                log::trace!("MyDXGISwapChain GetFullscreenState");
+                unsafe {
+                    let mut target = None;
+                    self.inner
+                        .GetFullscreenState(out_fullscreen.into(), Some(&mut target))?;
+                    out_target.write(target)?;
+                    Ok(())
+                }
+            }
+
+            fn GetDesc(&self) -> windows_result::Result<DXGI_SWAP_CHAIN_DESC> {
+                log::trace!("MyDXGISwapChain GetDesc");
+                unsafe { self.inner.GetDesc() }
+            }
+
+            fn ResizeBuffers(
+                &self,
+                buffer_count: u32,
+                width: u32,
+                height: u32,
+                new_format: DXGI_FORMAT,
+                swap_chain_flags: &DXGI_SWAP_CHAIN_FLAG,
+            ) -> windows_result::Result<()> {
+                log::trace!("MyDXGISwapChain ResizeBuffers");
+                self.capturer.present_state.lock().take();
+                unsafe {
+                    self.inner.ResizeBuffers(
+                        buffer_count,
+                        width,
+                        height,
+                        new_format,
+                        *swap_chain_flags,
+                    )
+                }
+            }
+
+            fn ResizeTarget(
+                &self,
+                new_target_parameters: *const DXGI_MODE_DESC,
+            ) -> windows_result::Result<()> {
+                log::trace!("MyDXGISwapChain ResizeTarget");
+                unsafe { self.inner.ResizeTarget(new_target_parameters) }
+            }
+
+            fn GetContainingOutput(&self) -> windows_result::Result<IDXGIOutput> {
+                log::trace!("MyDXGISwapChain GetContainingOutput");
+                unsafe { self.inner.GetContainingOutput() }
+            }
+
+            fn GetFrameStatistics(
+                &self,
+                out_stats: *mut DXGI_FRAME_STATISTICS,
+            ) -> windows_result::Result<()> {
+                log::trace!("MyDXGISwapChain GetFrameStatistics");
+                unsafe { self.inner.GetFrameStatistics(out_stats) }
+            }
+
+            fn GetLastPresentCount(&self) -> windows_result::Result<u32> {
+                log::trace!("MyDXGISwapChain GetLastPresentCount");
+                unsafe { self.inner.GetLastPresentCount() }
+            }
         }
-    }
 
-    fn GetFullscreenState(
-        &self,
-        out_fullscreen: *mut BOOL,
-        out_target: OutRef<IDXGIOutput>,
-    ) -> windows_result::Result<()> {
-        log::trace!("MyDXGISwapChain GetFullscreenState");
-        unsafe {
-            let mut target = None;
-            self.inner
-                .GetFullscreenState(out_fullscreen.into(), Some(&mut target))?;
-            out_target.write(target)?;
-            Ok(())
+        #[allow(non_snake_case)]
+        impl IDXGIDeviceSubObject_Impl for $name {
+            fn GetDevice(
+                &self,
+                iid: *const GUID,
+                out_device: *mut *mut core::ffi::c_void,
+            ) -> windows_result::Result<()> {
+                log::trace!("MyDXGISwapChain GetDevice");
+                let o: &IDXGIDeviceSubObject = &self.inner;
+                unsafe { (o.vtable().GetDevice)(o.as_raw(), iid, out_device).ok() }
+            }
+        }
+
+        #[allow(non_snake_case)]
+        impl IDXGIObject_Impl for $name {
+            fn SetPrivateData(
+                &self,
+                name: *const GUID,
+                size: u32,
+                data: *const core::ffi::c_void,
+            ) -> windows_result::Result<()> {
+                log::trace!("MyDXGISwapChain SetPrivateData");
+                unsafe { self.inner.SetPrivateData(name, size, data) }
+            }
+
+            fn SetPrivateDataInterface(
+                &self,
+                name: *const GUID,
+                interface: Ref<IUnknown>,
+            ) -> windows_result::Result<()> {
+                log::trace!("MyDXGISwapChain SetPrivateDataInterface");
+                unsafe { self.inner.SetPrivateDataInterface(name, interface.as_ref()) }
+            }
+
+            fn GetPrivateData(
+                &self,
+                name: *const GUID,
+                size: *mut u32,
+                data: *mut core::ffi::c_void,
+            ) -> windows_result::Result<()> {
+                log::trace!("MyDXGISwapChain GetPrivateData");
+                unsafe { self.inner.GetPrivateData(name, size, data) }
+            }
+
+            fn GetParent(
+                &self,
+                iid: *const GUID,
+                out_parent: *mut *mut core::ffi::c_void,
+            ) -> windows_result::Result<()> {
+                log::trace!("MyDXGISwapChain GetParent");
+                let o: &IDXGIObject = &self.inner;
+                unsafe { (o.vtable().GetParent)(o.as_raw(), iid, out_parent).ok() }
+            }
         }
+    };
+}
+
+forward_swap_chain!(MyDXGISwapChain_Impl);
+forward_swap_chain!(MyDXGISwapChain4_Impl);
+
+#[allow(non_snake_case)]
+impl IDXGISwapChain1_Impl for MyDXGISwapChain4_Impl {
+    fn GetDesc1(&self) -> windows_result::Result<DXGI_SWAP_CHAIN_DESC1> {
+        log::trace!("MyDXGISwapChain GetDesc1");
+        unsafe { self.inner.GetDesc1() }
     }
 
-    fn GetDesc(&self) -> windows_result::Result<DXGI_SWAP_CHAIN_DESC> {
-        log::trace!("MyDXGISwapChain GetDesc");
-        unsafe { self.inner.GetDesc() }
+    fn GetFullscreenDesc(&self) -> windows_result::Result<DXGI_SWAP_CHAIN_FULLSCREEN_DESC> {
+        log::trace!("MyDXGISwapChain GetFullscreenDesc");
+        unsafe { self.inner.GetFullscreenDesc() }
     }
 
-    fn ResizeBuffers(
+    fn GetHwnd(&self) -> windows_result::Result<HWND> {
+        log::trace!("MyDXGISwapChain GetHwnd");
+        unsafe { self.inner.GetHwnd() }
+    }
+
+    fn GetCoreWindow(
         &self,
-        buffer_count: u32,
-        width: u32,
-        height: u32,
-        new_format: DXGI_FORMAT,
-        swap_chain_flags: &DXGI_SWAP_CHAIN_FLAG,
+        iid: *const GUID,
+        out_unknown: *mut *mut core::ffi::c_void,
     ) -> windows_result::Result<()> {
-        log::trace!("MyDXGISwapChain ResizeBuffers");
-        {
-            self.present_state.lock().take();
-        }
+        log::trace!("MyDXGISwapChain GetCoreWindow");
+        let o: &IDXGISwapChain1 = &self.inner;
+        unsafe { (o.vtable().GetCoreWindow)(o.as_raw(), iid, out_unknown).ok() }
+    }
+
+    fn Present1(
+        &self,
+        sync_interval: u32,
+        flags: DXGI_PRESENT,
+        present_parameters: *const DXGI_PRESENT_PARAMETERS,
+    ) -> HRESULT {
+        self.capturer.capture(&self.inner);
         unsafe {
             self.inner
-                .ResizeBuffers(buffer_count, width, height, new_format, *swap_chain_flags)
+                .Present1(sync_interval, flags, present_parameters)
         }
     }
 
-    fn ResizeTarget(
-        &self,
-        new_target_parameters: *const DXGI_MODE_DESC,
-    ) -> windows_result::Result<()> {
-        log::trace!("MyDXGISwapChain ResizeTarget");
-        unsafe { self.inner.ResizeTarget(new_target_parameters) }
+    fn IsTemporaryMonoSupported(&self) -> BOOL {
+        log::trace!("MyDXGISwapChain IsTemporaryMonoSupported");
+        unsafe { self.inner.IsTemporaryMonoSupported() }
     }
 
-    fn GetContainingOutput(&self) -> windows_result::Result<IDXGIOutput> {
-        log::trace!("MyDXGISwapChain GetContainingOutput");
-        unsafe { self.inner.GetContainingOutput() }
+    fn GetRestrictToOutput(&self) -> windows_result::Result<IDXGIOutput> {
+        log::trace!("MyDXGISwapChain GetRestrictToOutput");
+        unsafe { self.inner.GetRestrictToOutput() }
     }
 
-    fn GetFrameStatistics(
-        &self,
-        out_stats: *mut DXGI_FRAME_STATISTICS,
-    ) -> windows_result::Result<()> {
-        log::trace!("MyDXGISwapChain GetFrameStatistics");
-        unsafe { self.inner.GetFrameStatistics(out_stats) }
+    fn SetBackgroundColor(&self, color: *const DXGI_RGBA) -> windows_result::Result<()> {
+        log::trace!("MyDXGISwapChain SetBackgroundColor");
+        unsafe { self.inner.SetBackgroundColor(color) }
+    }
+
+    fn GetBackgroundColor(&self) -> windows_result::Result<DXGI_RGBA> {
+        log::trace!("MyDXGISwapChain GetBackgroundColor");
+        unsafe { self.inner.GetBackgroundColor() }
+    }
+
+    fn SetRotation(&self, rotation: DXGI_MODE_ROTATION) -> windows_result::Result<()> {
+        log::trace!("MyDXGISwapChain SetRotation");
+        unsafe { self.inner.SetRotation(rotation) }
     }
 
-    fn GetLastPresentCount(&self) -> windows_result::Result<u32> {
-        log::trace!("MyDXGISwapChain GetLastPresentCount");
-        unsafe { self.inner.GetLastPresentCount() }
+    fn GetRotation(&self) -> windows_result::Result<DXGI_MODE_ROTATION> {
+        log::trace!("MyDXGISwapChain GetRotation");
+        unsafe { self.inner.GetRotation() }
     }
 }
 
 #[allow(non_snake_case)]
-impl IDXGIDeviceSubObject_Impl for MyDXGISwapChain_Impl {
-    fn GetDevice(
+impl IDXGISwapChain2_Impl for MyDXGISwapChain4_Impl {
+    fn SetSourceSize(&self, width: u32, height: u32) -> windows_result::Result<()> {
+        log::trace!("MyDXGISwapChain SetSourceSize");
+        unsafe { self.inner.SetSourceSize(width, height) }
+    }
+
+    fn GetSourceSize(
         &self,
-        iid: *const GUID,
-        out_device: *mut *mut core::ffi::c_void,
+        out_width: *mut u32,
+        out_height: *mut u32,
     ) -> windows_result::Result<()> {
-        log::trace!("MyDXGISwapChain GetDevice");
-        let o: &IDXGIDeviceSubObject = &self.inner;
-        unsafe { (o.vtable().GetDevice)(o.as_raw(), iid, out_device).ok() }
+        log::trace!("MyDXGISwapChain GetSourceSize");
+        unsafe { self.inner.GetSourceSize(out_width, out_height) }
+    }
+
+    fn SetMaximumFrameLatency(&self, max_latency: u32) -> windows_result::Result<()> {
+        log::trace!("MyDXGISwapChain SetMaximumFrameLatency");
+        unsafe { self.inner.SetMaximumFrameLatency(max_latency) }
+    }
+
+    fn GetMaximumFrameLatency(&self) -> windows_result::Result<u32> {
+        log::trace!("MyDXGISwapChain GetMaximumFrameLatency");
+        unsafe { self.inner.GetMaximumFrameLatency() }
+    }
+
+    fn GetFrameLatencyWaitableObject(&self) -> HANDLE {
+        log::trace!("MyDXGISwapChain GetFrameLatencyWaitableObject");
+        unsafe { self.inner.GetFrameLatencyWaitableObject() }
+    }
+
+    fn SetMatrixTransform(&self, matrix: *const DXGI_MATRIX_3X2_F) -> windows_result::Result<()> {
+        log::trace!("MyDXGISwapChain SetMatrixTransform");
+        unsafe { self.inner.SetMatrixTransform(matrix) }
+    }
+
+    fn GetMatrixTransform(&self) -> windows_result::Result<DXGI_MATRIX_3X2_F> {
+        log::trace!("MyDXGISwapChain GetMatrixTransform");
+        unsafe { self.inner.GetMatrixTransform() }
     }
 }
 
 #[allow(non_snake_case)]
-impl IDXGIObject_Impl for MyDXGISwapChain_Impl {
-    fn SetPrivateData(
-        &self,
-        name: *const GUID,
-        size: u32,
-        data: *const core::ffi::c_void,
-    ) -> windows_result::Result<()> {
-        log::trace!("MyDXGISwapChain SetPrivateData");
-        unsafe { self.inner.SetPrivateData(name, size, data) }
+impl IDXGISwapChain3_Impl for MyDXGISwapChain4_Impl {
+    fn GetCurrentBackBufferIndex(&self) -> u32 {
+        unsafe { self.inner.GetCurrentBackBufferIndex() }
     }
 
-    fn SetPrivateDataInterface(
+    fn CheckColorSpaceSupport(
         &self,
-        name: *const GUID,
-        interface: Ref<IUnknown>,
-    ) -> windows_result::Result<()> {
-        log::trace!("MyDXGISwapChain SetPrivateDataInterface");
-        unsafe { self.inner.SetPrivateDataInterface(name, interface.as_ref()) }
+        color_space: DXGI_COLOR_SPACE_TYPE,
+    ) -> windows_result::Result<u32> {
+        log::trace!("MyDXGISwapChain CheckColorSpaceSupport");
+        unsafe { self.inner.CheckColorSpaceSupport(color_space) }
+    }
+
+    fn SetColorSpace1(&self, color_space: DXGI_COLOR_SPACE_TYPE) -> windows_result::Result<()> {
+        log::trace!("MyDXGISwapChain SetColorSpace1 {color_space:?}");
+        unsafe { self.inner.SetColorSpace1(color_space)? };
+        self.capturer.set_color_space(color_space);
+        Ok(())
     }
 
-    fn GetPrivateData(
+    fn ResizeBuffers1(
         &self,
-        name: *const GUID,
-        size: *mut u32,
-        data: *mut core::ffi::c_void,
+        buffer_count: u32,
+        width: u32,
+        height: u32,
+        new_format: DXGI_FORMAT,
+        swap_chain_flags: u32,
+        creation_node_mask: *const u32,
+        present_queue: *const Option<IUnknown>,
     ) -> windows_result::Result<()> {
-        log::trace!("MyDXGISwapChain GetPrivateData");
-        unsafe { self.inner.GetPrivateData(name, size, data) }
+        log::trace!("MyDXGISwapChain ResizeBuffers1");
+        self.capturer.present_state.lock().take();
+        unsafe {
+            self.inner.ResizeBuffers1(
+                buffer_count,
+                width,
+                height,
+                new_format,
+                swap_chain_flags,
+                creation_node_mask,
+                present_queue,
+            )
+        }
     }
+}
 
-    fn GetParent(
+#[allow(non_snake_case)]
+impl IDXGISwapChain4_Impl for MyDXGISwapChain4_Impl {
+    fn SetHDRMetaData(
         &self,
-        iid: *const GUID,
-        out_parent: *mut *mut core::ffi::c_void,
+        r#type: DXGI_HDR_METADATA_TYPE,
+        size: u32,
+        metadata: *const core::ffi::c_void,
     ) -> windows_result::Result<()> {
-        log::trace!("MyDXGISwapChain GetParent");
-        let o: &IDXGIObject = &self.inner;
-        unsafe { (o.vtable().GetParent)(o.as_raw(), iid, out_parent).ok() }
+        log::trace!("MyDXGISwapChain SetHDRMetaData {:?}", r#type);
+        let o: &IDXGISwapChain4 = &self.inner;
+        unsafe { (o.vtable().SetHDRMetaData)(o.as_raw(), r#type, size, metadata).ok() }
+    }
+}
+
+/// Converter for captures of a swap chain `format` in `color_space`.
+fn converter(
+    format: Option<SurfaceFormat>,
+    color_space: Option<DXGI_COLOR_SPACE_TYPE>,
+) -> Option<Converter> {
+    format.map(|format| graphics::converter(format, transfer(format, color_space)))
+}
+
+/// Memory layout of a swap chain format, if captures can read it.
+fn surface_format(format: DXGI_FORMAT) -> Option<SurfaceFormat> {
+    Some(match format {
+        DXGI_FORMAT_B8G8R8A8_UNORM
+        | DXGI_FORMAT_B8G8R8A8_UNORM_SRGB
+        | DXGI_FORMAT_B8G8R8X8_UNORM
+        | DXGI_FORMAT_B8G8R8X8_UNORM_SRGB => SurfaceFormat::Bgra8,
+        DXGI_FORMAT_R8G8B8A8_UNORM | DXGI_FORMAT_R8G8B8A8_UNORM_SRGB => SurfaceFormat::Rgba8,
+        DXGI_FORMAT_R10G10B10A2_UNORM => SurfaceFormat::Rgb10A2,
+        DXGI_FORMAT_R16G16B16A16_FLOAT => SurfaceFormat::Rgba16F,
+        _ => None?,
+    })
+}
+
+/// How the values of a swap chain format relate to light in `color_space`,
+/// or in the default color space of the format.
+fn transfer(format: SurfaceFormat, color_space: Option<DXGI_COLOR_SPACE_TYPE>) -> Transfer {
+    match color_space {
+        Some(DXGI_COLOR_SPACE_RGB_FULL_G2084_NONE_P2020) => Transfer::Pq,
+        Some(DXGI_COLOR_SPACE_RGB_FULL_G10_NONE_P709) => Transfer::Linear,
+        Some(_) => Transfer::Srgb,
+        // Float swap chains are scRGB unless told otherwise.
+        None if format == SurfaceFormat::Rgba16F => Transfer::Linear,
+        None => Transfer::Srgb,
     }
 }
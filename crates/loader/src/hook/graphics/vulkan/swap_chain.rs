@@ -1,4 +1,5 @@
 use std::sync::{
+    Arc,
     LazyLock,
     atomic::{
         AtomicPtr,
@@ -7,11 +8,17 @@ use std::sync::{
 };
 
 use dashmap::DashMap;
+use recordin_common::color::{
+    Converter,
+    SurfaceFormat,
+    Transfer,
+};
 use vulkanalia::{
     VkResult,
     vk,
     vk::{
         DeviceV1_0,
+        Handle,
         HasBuilder,
         InstanceV1_0,
         KhrSwapchainExtensionDeviceCommands,
@@ -31,8 +38,8 @@ use crate::{
         timing,
     },
     output::{
-        video_codec,
-        video_codec::EncDuplex,
+        sink,
+        sink::VideoQueue,
     },
 };
 
@@ -105,7 +112,9 @@ pub(super) struct SwapChainState {
     pub(super) height: u32,
     pub(super) row_pitch: vk::DeviceSize,
     pub(super) mapped: AtomicPtr<core::ffi::c_void>,
-    pub(super) encoder: Option<EncDuplex>,
+    /// `None` for swap chain formats captures cannot read.
+    pub(super) converter: Option<Converter>,
+    pub(super) encoder: Option<Arc<VideoQueue>>,
     pub(super) init_real_time: i64,
     pub(super) frame_count: u64,
 }
@@ -132,7 +141,7 @@ impl SwapChainState {
             let height = info.image_extent.height;
             let image_info = vk::ImageCreateInfo::builder()
                 .image_type(vk::ImageType::_2D)
-                .format(vk::Format::B8G8R8A8_UNORM)
+                .format(info.image_format)
                 .extent(vk::Extent3D::builder().width(width).height(height).depth(1))
                 .array_layers(1)
                 .mip_levels(1)
@@ -174,7 +183,19 @@ impl SwapChainState {
                 vk::MemoryMapFlags::empty(),
             )?;
             let mapped = AtomicPtr::new(mapped);
-            let encoder = video_codec::create_encoder(width as _, height as _);
+            let converter = match surface_format(info.image_format) {
+                Some(format) => Some(graphics::converter(
+                    format,
+                    transfer(info.image_color_space),
+                )),
+                None => {
+                    log::warn!("Swap chain format {:?} not captured", info.image_format);
+                    None
+                }
+            };
+            let encoder = converter
+                .as_ref()
+                .and_then(|converter| sink::video_queue(width as _, height as _, converter));
             Ok(Self {
                 swap_images,
                 copy_semaphore,
@@ -186,6 +207,7 @@ impl SwapChainState {
                 height,
                 row_pitch,
                 mapped,
+                converter,
                 encoder,
                 init_real_time: timing::real().0,
                 frame_count: 0,
@@ -202,25 +224,41 @@ impl SwapChainState {
 
     pub(super) fn post_copy(&mut self) -> Option<()> {
         unsafe {
-            let mut packed_bgr = self.encoder.as_ref()?.1.recv().ok()?;
-            packed_bgr.resize((self.width * self.height) as _, [0; _]);
-            let packed_lines = packed_bgr.chunks_exact_mut(self.width as usize);
-            let mapped_slices = graphics::slices_by_row_pitch(
+            let queue = self.encoder.as_ref()?;
+            let converter = self.converter.as_ref()?;
+            let mut frame = queue.acquire_at(timing::ticks())?;
+            frame.resize(self.width as _, self.height as _, converter);
+            graphics::convert_mapped(
                 self.mapped.load(Ordering::Relaxed).cast(),
-                (self.width * 4) as _,
-                self.height as _,
                 self.row_pitch as _,
+                converter,
+                &mut frame,
             );
-            let z = packed_lines.zip(mapped_slices);
-            for (packed_line, mapped_slice) in z {
-                let (raw_c, _) = mapped_slice.as_chunks();
-                let zz = packed_line.iter_mut().zip(raw_c);
-                for (packed, &[b, g, r, _]) in zz {
-                    *packed = [b, g, r];
-                }
-            }
-            self.encoder.as_ref()?.0.send(packed_bgr).ok()?;
+            queue.submit(frame);
             Some(())
         }
     }
 }
+
+/// Memory layout of a swap chain format, if captures can read it.
+fn surface_format(format: vk::Format) -> Option<SurfaceFormat> {
+    Some(match format {
+        vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => SurfaceFormat::Bgra8,
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::A8B8G8R8_UNORM_PACK32
+        | vk::Format::A8B8G8R8_SRGB_PACK32 => SurfaceFormat::Rgba8,
+        vk::Format::A2R10G10B10_UNORM_PACK32 => SurfaceFormat::Bgr10A2,
+        vk::Format::A2B10G10R10_UNORM_PACK32 => SurfaceFormat::Rgb10A2,
+        vk::Format::R16G16B16A16_SFLOAT => SurfaceFormat::Rgba16F,
+        _ => None?,
+    })
+}
+
+fn transfer(color_space: vk::ColorSpaceKHR) -> Transfer {
+    match color_space {
+        vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => Transfer::Linear,
+        vk::ColorSpaceKHR::HDR10_ST2084_EXT => Transfer::Pq,
+        _ => Transfer::Srgb,
+    }
+}
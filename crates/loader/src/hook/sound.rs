@@ -1,5 +1,7 @@
 use std::ops::ControlFlow;
 
+use recordin_common::config::SoundSystem;
+
 use crate::env;
 
 mod wasapi;
@@ -14,11 +16,11 @@ pub(super) fn com_hook(
     out_v: *mut *mut core::ffi::c_void,
 ) -> ControlFlow<windows_sys::core::HRESULT> {
     #[allow(clippy::single_match)]
-    match env::SOUND_SYSTEM.as_deref() {
-        Some("wasapi") => {
+    match *env::SOUND_SYSTEM {
+        Some(SoundSystem::Wasapi) => {
             wasapi::com_hook(cls_id, outer, cls_context, iid, out_v)?;
         }
-        _ => {}
+        None => {}
     }
     ControlFlow::Continue(())
 }
@@ -24,8 +24,8 @@ use windows_sys::Win32::System::Threading::SetEvent;
 use crate::{
     hook::sound::wasapi::audio_client::MyAudioClient,
     output::{
-        audio_codec,
-        audio_codec::AudioEncDuplex,
+        sink,
+        sink::AudioQueue,
     },
 };
 
@@ -36,7 +36,7 @@ pub(super) struct MyAudioRenderClient {
     requested: AtomicBool,
     frame_req: AtomicU64,
     event: Option<usize>,
-    encoder: Option<AudioEncDuplex>,
+    encoder: Option<AudioQueue>,
 }
 
 impl MyAudioRenderClient {
@@ -44,7 +44,7 @@ impl MyAudioRenderClient {
         let buf = vec![0; buffer_size * 2 * 4].into_boxed_slice();
         let requested = AtomicBool::new(false);
         let frame_req = AtomicU64::new(0);
-        let encoder = audio_codec::create_encoder();
+        let encoder = sink::create_audio();
         Self {
             buf,
             counter,
@@ -55,13 +55,13 @@ impl MyAudioRenderClient {
         }
     }
     fn on_release(&self, len: usize, muted: bool) -> Option<()> {
-        let (tx, rx) = self.encoder.as_ref()?;
-        let mut buf = rx.recv().ok()?;
+        let queue = self.encoder.as_ref()?;
+        let mut buf = queue.acquire()?;
         buf.resize(len * 4 * 2, 0);
         if !muted {
             buf.copy_from_slice(&self.buf[0..len * 4 * 2])
         }
-        tx.send(buf).ok()?;
+        queue.submit(buf);
         Some(())
     }
 }
@@ -8,32 +8,72 @@ use std::{
     },
 };
 
+use recordin_common::config::TimeHook;
 use windows_sys::Win32::{
     Foundation::TRUE,
-    Media::timeGetTime,
+    Media::{
+        timeGetSystemTime,
+        timeGetTime,
+        timeKillEvent,
+        timeSetEvent,
+    },
     System::{
         Performance::{
             QueryPerformanceCounter,
             QueryPerformanceFrequency,
         },
         SystemInformation::{
+            GetLocalTime,
+            GetSystemTime,
+            GetSystemTimeAsFileTime,
+            GetSystemTimePreciseAsFileTime,
             GetTickCount,
             GetTickCount64,
         },
         Threading::{
+            CancelWaitableTimer,
+            ChangeTimerQueueTimer,
+            CloseThreadpoolTimer,
+            CreateTimerQueueTimer,
+            DeleteTimerQueue,
+            DeleteTimerQueueEx,
+            DeleteTimerQueueTimer,
+            IsThreadpoolTimerSet,
+            SetThreadpoolTimer,
+            SetThreadpoolTimerEx,
+            SetWaitableTimer,
+            SetWaitableTimerEx,
+            SignalObjectAndWait,
             Sleep,
+            SleepEx,
             WaitForMultipleObjects,
+            WaitForMultipleObjectsEx,
             WaitForSingleObject,
+            WaitForSingleObjectEx,
+        },
+        WindowsProgramming::{
+            QueryInterruptTime,
+            QueryInterruptTimePrecise,
+            QueryUnbiasedInterruptTime,
+            QueryUnbiasedInterruptTimePrecise,
         },
     },
+    UI::WindowsAndMessaging::{
+        MsgWaitForMultipleObjects,
+        MsgWaitForMultipleObjectsEx,
+    },
 };
 
 use crate::env;
 
 mod get_tick_count;
+mod interrupt_time;
+mod scheduler;
 mod sleep;
 mod sync;
+mod system_time;
 mod time_get_time;
+mod waitable_timer;
 
 static BASE_COUNT: AtomicI64 = AtomicI64::new(0);
 static OFFSET: AtomicI64 = AtomicI64::new(0);
@@ -52,6 +92,12 @@ pub fn perf() -> (i64, i64) {
     (pc, f)
 }
 
+/// Virtual time in milliseconds, what the millisecond clocks count.
+pub(super) fn millis() -> i64 {
+    let (pc, f) = perf();
+    (pc as i128 * 1000 / f as i128) as i64
+}
+
 pub fn real() -> (i64, i64) {
     let (mut pc, mut f) = (0, 0);
     unsafe {
@@ -61,12 +107,19 @@ pub fn real() -> (i64, i64) {
     (pc, f)
 }
 
+/// Frames of virtual time elapsed so far.
+pub(crate) fn ticks() -> i64 {
+    TICK.load(Ordering::Relaxed)
+}
+
 pub(super) fn incr_tick() {
     if !ENABLED.load(Ordering::Acquire) {
         ENABLED.store(true, Ordering::Release);
     } else {
         ALARM.store(0, Ordering::Relaxed);
         TICK.fetch_add(1, Ordering::Relaxed);
+        waitable_timer::tick();
+        scheduler::tick();
         sync::tick();
     }
 }
@@ -78,6 +131,8 @@ pub(super) fn pause() {
         let offset = BASE_COUNT.load(Ordering::Relaxed) - c;
         OFFSET.store(offset, Ordering::Relaxed);
         ENABLED.store(false, Ordering::Release);
+        waitable_timer::pause();
+        scheduler::tick();
     }
 }
 
@@ -133,16 +188,58 @@ unsafe extern "system" fn QueryPerformanceFrequency(p_freq: *mut i64) -> windows
 }
 
 pub(super) fn init() -> anyhow::Result<()> {
-    BASE_COUNT.store(perf().0, Ordering::Relaxed);
+    let count = perf().0;
+    BASE_COUNT.store(count, Ordering::Relaxed);
+    system_time::start(count);
     unsafe {
         init_QueryPerformanceFrequency(QueryPerformanceFrequency)?.enable()?;
         init_QueryPerformanceCounter(QueryPerformanceCounter)?.enable()?;
         get_tick_count::init_GetTickCount(GetTickCount)?.enable()?;
         get_tick_count::init_GetTickCount64(GetTickCount64)?.enable()?;
         time_get_time::init_timeGetTime(timeGetTime)?.enable()?;
+        system_time::init_GetSystemTimeAsFileTime(GetSystemTimeAsFileTime)?.enable()?;
+        system_time::init_GetSystemTimePreciseAsFileTime(GetSystemTimePreciseAsFileTime)?
+            .enable()?;
+        system_time::init_GetSystemTime(GetSystemTime)?.enable()?;
+        system_time::init_GetLocalTime(GetLocalTime)?.enable()?;
+        if env::time_hook_enabled(TimeHook::InterruptTime) {
+            interrupt_time::init_QueryInterruptTime(QueryInterruptTime)?.enable()?;
+            interrupt_time::init_QueryInterruptTimePrecise(QueryInterruptTimePrecise)?.enable()?;
+        }
+        if env::time_hook_enabled(TimeHook::UnbiasedInterruptTime) {
+            interrupt_time::init_QueryUnbiasedInterruptTime(QueryUnbiasedInterruptTime)?
+                .enable()?;
+            interrupt_time::init_QueryUnbiasedInterruptTimePrecise(
+                QueryUnbiasedInterruptTimePrecise,
+            )?
+            .enable()?;
+        }
+        if env::time_hook_enabled(TimeHook::TimeGetSystemTime) {
+            time_get_time::init_timeGetSystemTime(timeGetSystemTime)?.enable()?;
+        }
         sleep::init_Sleep(Sleep)?.enable()?;
+        sleep::init_SleepEx(SleepEx)?.enable()?;
         sync::init_WaitForSingleObject(WaitForSingleObject)?.enable()?;
+        sync::init_WaitForSingleObjectEx(WaitForSingleObjectEx)?.enable()?;
         sync::init_WaitForMultipleObjects(WaitForMultipleObjects)?.enable()?;
+        sync::init_WaitForMultipleObjectsEx(WaitForMultipleObjectsEx)?.enable()?;
+        sync::init_MsgWaitForMultipleObjects(MsgWaitForMultipleObjects)?.enable()?;
+        sync::init_MsgWaitForMultipleObjectsEx(MsgWaitForMultipleObjectsEx)?.enable()?;
+        sync::init_SignalObjectAndWait(SignalObjectAndWait)?.enable()?;
+        waitable_timer::init_SetWaitableTimer(SetWaitableTimer)?.enable()?;
+        waitable_timer::init_SetWaitableTimerEx(SetWaitableTimerEx)?.enable()?;
+        waitable_timer::init_CancelWaitableTimer(CancelWaitableTimer)?.enable()?;
+        scheduler::init_timeSetEvent(timeSetEvent)?.enable()?;
+        scheduler::init_timeKillEvent(timeKillEvent)?.enable()?;
+        scheduler::init_CreateTimerQueueTimer(CreateTimerQueueTimer)?.enable()?;
+        scheduler::init_ChangeTimerQueueTimer(ChangeTimerQueueTimer)?.enable()?;
+        scheduler::init_DeleteTimerQueueTimer(DeleteTimerQueueTimer)?.enable()?;
+        scheduler::init_DeleteTimerQueue(DeleteTimerQueue)?.enable()?;
+        scheduler::init_DeleteTimerQueueEx(DeleteTimerQueueEx)?.enable()?;
+        scheduler::init_SetThreadpoolTimer(SetThreadpoolTimer)?.enable()?;
+        scheduler::init_SetThreadpoolTimerEx(SetThreadpoolTimerEx)?.enable()?;
+        scheduler::init_IsThreadpoolTimerSet(IsThreadpoolTimerSet)?.enable()?;
+        scheduler::init_CloseThreadpoolTimer(CloseThreadpoolTimer)?.enable()?;
     }
     Ok(())
 }
@@ -0,0 +1,53 @@
+use windows_sys::Win32::Foundation::TRUE;
+
+use crate::hook::{
+    timing,
+    timing::system_time,
+};
+
+/// Interrupt time in 100 ns units, following the virtual performance
+/// counter just like the real one follows the real counter.
+pub(super) fn now() -> u64 {
+    let (pc, f) = timing::perf();
+    (pc as i128 * 10_000_000 / f as i128) as u64
+}
+
+/// Interrupt time of a timer's due time, relative when negative and an
+/// absolute wall clock time otherwise.
+pub(super) fn due(due: i64) -> u64 {
+    if due < 0 {
+        now() + due.unsigned_abs()
+    } else {
+        now().saturating_add_signed(due - system_time::file_time())
+    }
+}
+
+// Time spent suspended is not recorded, so biased and unbiased interrupt
+// time are the same.
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn QueryInterruptTime(p_time: *mut u64) {
+    unsafe { *p_time = now() }
+}
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn QueryInterruptTimePrecise(p_time: *mut u64) {
+    unsafe { *p_time = now() }
+}
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn QueryUnbiasedInterruptTime(
+    p_time: *mut u64,
+) -> windows_sys::core::BOOL {
+    unsafe { *p_time = now() }
+    TRUE
+}
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn QueryUnbiasedInterruptTimePrecise(p_time: *mut u64) {
+    unsafe { *p_time = now() }
+}
@@ -0,0 +1,465 @@
+//! Callback timers on virtual time: `timeSetEvent`, timer queue timers and
+//! thread pool timers.
+//!
+//! The real timers stay idle. They are fired once due at every frame while
+//! time is virtual, and by a scheduler thread sleeping until the next due
+//! time otherwise, when virtual time runs at real speed. Multimedia timer
+//! callbacks run on the scheduler thread, like on the one thread `winmm`
+//! uses; the others are made to expire at once, so their callbacks run
+//! where the system would run them. Those expire once per pass, a timer that
+//! missed several periods catches up in passes a short real time apart.
+
+use std::{
+    ffi::c_void,
+    ptr,
+    sync::{
+        LazyLock,
+        Once,
+        atomic::{
+            AtomicU32,
+            Ordering,
+        },
+    },
+};
+
+use dashmap::DashMap;
+use parking_lot::{
+    Mutex,
+    ReentrantMutex,
+};
+use windows_sys::{
+    Win32::{
+        Foundation::{
+            FALSE,
+            FILETIME,
+            HANDLE,
+            TRUE,
+        },
+        Media::{
+            LPTIMECALLBACK,
+            MMSYSERR_INVALPARAM,
+            TIME_CALLBACK_EVENT_PULSE,
+            TIME_CALLBACK_EVENT_SET,
+            TIME_KILL_SYNCHRONOUS,
+            TIME_PERIODIC,
+            TIMERR_NOERROR,
+        },
+        System::Threading::{
+            CreateEventW,
+            INFINITE,
+            PTP_TIMER,
+            PulseEvent,
+            SetEvent,
+            WAITORTIMERCALLBACK,
+            WORKER_THREAD_FLAGS,
+            WT_EXECUTEONLYONCE,
+        },
+    },
+    core::BOOL,
+};
+
+use crate::hook::timing::{
+    interrupt_time,
+    sync::orig_WaitForSingleObject,
+};
+
+/// Due time of a timer that is not set.
+const NEVER: u64 = u64::MAX;
+/// Due time and period of an idle timer queue timer. Periodic, since a
+/// one-shot timer that expired cannot be changed anymore.
+const NEVER_MS: u32 = 0x7fffffff;
+/// Absolute due time long gone, expiring a thread pool timer at once.
+const PAST: FILETIME = FILETIME {
+    dwLowDateTime: 1,
+    dwHighDateTime: 0,
+};
+/// `timeGetDevCaps` range of multimedia timer delays.
+const MAX_DELAY_MS: u32 = 1_000_000;
+/// Real time between passes while a timer has periods to catch up on.
+const CATCH_UP_MS: u32 = 1;
+
+static TIMERS: LazyLock<DashMap<Key, Timer>> = LazyLock::new(DashMap::new);
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+static WAKE_EVENT: LazyLock<usize> =
+    LazyLock::new(|| unsafe { CreateEventW(ptr::null(), FALSE, FALSE, ptr::null()) } as _);
+static THREAD: Once = Once::new();
+/// Multimedia timer calls of frames, waiting for the scheduler thread.
+static CALLS: Mutex<Vec<(MultimediaTimer, bool)>> = Mutex::new(Vec::new());
+/// Held while multimedia timer callbacks run, so that a timer killed with
+/// `TIME_KILL_SYNCHRONOUS` is not called anymore once `timeKillEvent`
+/// returns.
+static FIRING: ReentrantMutex<()> = parking_lot::const_reentrant_mutex(());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Key {
+    Multimedia(u32),
+    TimerQueue(usize),
+    Threadpool(usize),
+}
+
+#[derive(Clone, Copy)]
+struct MultimediaTimer {
+    id: u32,
+    /// An event handle for the `TIME_CALLBACK_EVENT_*` kinds.
+    callback: LPTIMECALLBACK,
+    user: usize,
+    kind: u32,
+}
+
+#[derive(Clone, Copy)]
+enum Target {
+    Multimedia(MultimediaTimer),
+    TimerQueue { queue: usize, timer: usize },
+    Threadpool(usize),
+}
+
+struct Timer {
+    target: Target,
+    /// Virtual interrupt time it fires at.
+    due: u64,
+    /// 100 ns, 0 for a one-shot timer.
+    period: u64,
+}
+
+impl MultimediaTimer {
+    fn call(&self) {
+        let event = self.callback.map_or(ptr::null_mut(), |f| f as HANDLE);
+        unsafe {
+            match self.kind & (TIME_CALLBACK_EVENT_SET | TIME_CALLBACK_EVENT_PULSE) {
+                TIME_CALLBACK_EVENT_SET => {
+                    SetEvent(event);
+                }
+                TIME_CALLBACK_EVENT_PULSE => {
+                    PulseEvent(event);
+                }
+                _ => {
+                    if let Some(callback) = self.callback {
+                        callback(self.id, 0, self.user, 0, 0);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Timer {
+    fn new(target: Target, due: u64, period_ms: u32) -> Self {
+        Timer {
+            target,
+            due,
+            period: period_ms as u64 * 10_000,
+        }
+    }
+
+    fn key(&self) -> Key {
+        match self.target {
+            Target::Multimedia(timer) => Key::Multimedia(timer.id),
+            Target::TimerQueue { timer, .. } => Key::TimerQueue(timer),
+            Target::Threadpool(timer) => Key::Threadpool(timer),
+        }
+    }
+
+    /// Fires the timer if it is due, returning whether to keep it.
+    /// Multimedia timers are called once per period that passed, the others
+    /// expire once per pass and stay due for the periods left.
+    fn fire(&mut self, now: u64, calls: &mut Vec<(MultimediaTimer, bool)>) -> bool {
+        if self.due > now {
+            return true;
+        }
+        let periods = match self.target {
+            Target::Multimedia(_) if self.period != 0 => (now - self.due) / self.period + 1,
+            _ => 1,
+        };
+        match self.target {
+            Target::Multimedia(timer) => {
+                for _ in 0..periods {
+                    calls.push((timer, self.period != 0));
+                }
+            }
+            Target::TimerQueue { queue, timer } => unsafe {
+                orig_ChangeTimerQueueTimer(queue as _, timer as _, 0, NEVER_MS);
+            },
+            Target::Threadpool(timer) => unsafe {
+                orig_SetThreadpoolTimer(timer as _, &PAST, 0, 0);
+            },
+        }
+        if self.period == 0 {
+            self.due = NEVER;
+            return !matches!(self.target, Target::Multimedia(_));
+        }
+        self.due += periods * self.period;
+        true
+    }
+}
+
+fn schedule(timer: Timer) -> Option<Timer> {
+    let old = TIMERS.insert(timer.key(), timer);
+    wake();
+    old
+}
+
+fn wake() {
+    THREAD.call_once(|| {
+        std::thread::spawn(run);
+    });
+    unsafe {
+        SetEvent(*WAKE_EVENT as _);
+    }
+}
+
+/// Whether a timer expiring on the real timer is still due.
+fn catching_up(now: u64) -> bool {
+    TIMERS
+        .iter()
+        .any(|timer| timer.due <= now && !matches!(timer.target, Target::Multimedia(_)))
+}
+
+/// Fires what came due, leaving multimedia timer calls to the scheduler
+/// thread.
+fn fire_due(calls: &mut Vec<(MultimediaTimer, bool)>) {
+    let now = interrupt_time::now();
+    TIMERS.retain(|_, timer| timer.fire(now, calls));
+}
+
+/// Fires the timers due at a new frame, before its waiters are released, or
+/// lets the scheduler catch up with virtual time running in real time again.
+/// Callbacks never run on the calling thread.
+pub(super) fn tick() {
+    if TIMERS.is_empty() {
+        return;
+    }
+    if super::ENABLED.load(Ordering::Acquire) {
+        let mut calls = Vec::new();
+        fire_due(&mut calls);
+        CALLS.lock().extend(calls);
+    }
+    wake();
+}
+
+fn run() {
+    loop {
+        let ms = if super::ENABLED.load(Ordering::Acquire) {
+            if catching_up(interrupt_time::now()) {
+                CATCH_UP_MS
+            } else {
+                INFINITE
+            }
+        } else {
+            let now = interrupt_time::now();
+            TIMERS
+                .iter()
+                .filter(|timer| timer.due != NEVER)
+                .map(|timer| timer.due.saturating_sub(now).div_ceil(10_000))
+                .min()
+                .map_or(INFINITE, |ms| ms.min(NEVER_MS as u64) as u32)
+        };
+        unsafe {
+            orig_WaitForSingleObject(*WAKE_EVENT as _, ms);
+        }
+        let mut calls = std::mem::take(&mut *CALLS.lock());
+        fire_due(&mut calls);
+        let _firing = FIRING.lock();
+        for (timer, periodic) in calls {
+            // A periodic timer may be killed by an earlier callback.
+            if !periodic || TIMERS.contains_key(&Key::Multimedia(timer.id)) {
+                timer.call();
+            }
+        }
+    }
+}
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn timeSetEvent(
+    delay: u32,
+    _resolution: u32,
+    callback: LPTIMECALLBACK,
+    user: usize,
+    kind: u32,
+) -> u32 {
+    if !(1..=MAX_DELAY_MS).contains(&delay) {
+        return 0;
+    }
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let target = Target::Multimedia(MultimediaTimer {
+        id,
+        callback,
+        user,
+        kind,
+    });
+    let period = if kind & TIME_PERIODIC != 0 { delay } else { 0 };
+    schedule(Timer::new(
+        target,
+        interrupt_time::due(-(delay as i64 * 10_000)),
+        period,
+    ));
+    id
+}
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn timeKillEvent(id: u32) -> u32 {
+    match TIMERS.remove(&Key::Multimedia(id)) {
+        Some((
+            _,
+            Timer {
+                target: Target::Multimedia(timer),
+                ..
+            },
+        )) => {
+            if timer.kind & TIME_KILL_SYNCHRONOUS != 0 {
+                drop(FIRING.lock());
+            }
+            TIMERR_NOERROR
+        }
+        _ => MMSYSERR_INVALPARAM,
+    }
+}
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn CreateTimerQueueTimer(
+    p_timer: *mut HANDLE,
+    queue: HANDLE,
+    callback: WAITORTIMERCALLBACK,
+    arg: *const c_void,
+    due: u32,
+    period: u32,
+    flags: WORKER_THREAD_FLAGS,
+) -> BOOL {
+    unsafe {
+        let real_flags = flags & !WT_EXECUTEONLYONCE;
+        if orig_CreateTimerQueueTimer(
+            p_timer, queue, callback, arg, NEVER_MS, NEVER_MS, real_flags,
+        ) == FALSE
+        {
+            return FALSE;
+        }
+        let target = Target::TimerQueue {
+            queue: queue as _,
+            timer: *p_timer as _,
+        };
+        let period = if flags & WT_EXECUTEONLYONCE != 0 {
+            0
+        } else {
+            period
+        };
+        schedule(Timer::new(
+            target,
+            interrupt_time::due(-(due as i64 * 10_000)),
+            period,
+        ));
+        TRUE
+    }
+}
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn ChangeTimerQueueTimer(
+    queue: HANDLE,
+    timer: HANDLE,
+    due: u32,
+    period: u32,
+) -> BOOL {
+    let Some(mut entry) = TIMERS.get_mut(&Key::TimerQueue(timer as _)) else {
+        return unsafe { orig_ChangeTimerQueueTimer(queue, timer, due, period) };
+    };
+    let target = entry.target;
+    *entry = Timer::new(target, interrupt_time::due(-(due as i64 * 10_000)), period);
+    drop(entry);
+    wake();
+    TRUE
+}
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn DeleteTimerQueueTimer(
+    queue: HANDLE,
+    timer: HANDLE,
+    event: HANDLE,
+) -> BOOL {
+    TIMERS.remove(&Key::TimerQueue(timer as _));
+    unsafe { orig_DeleteTimerQueueTimer(queue, timer, event) }
+}
+
+fn forget_queue(queue: HANDLE) {
+    TIMERS.retain(|_, timer| {
+        !matches!(timer.target, Target::TimerQueue { queue: q, .. } if q == queue as usize)
+    });
+}
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn DeleteTimerQueue(queue: HANDLE) -> BOOL {
+    forget_queue(queue);
+    unsafe { orig_DeleteTimerQueue(queue) }
+}
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn DeleteTimerQueueEx(queue: HANDLE, event: HANDLE) -> BOOL {
+    forget_queue(queue);
+    unsafe { orig_DeleteTimerQueueEx(queue, event) }
+}
+
+/// Sets a thread pool timer from `CreateThreadpoolTimer`, returning whether
+/// it was set before.
+unsafe fn set_threadpool_timer(timer: PTP_TIMER, p_due: *const FILETIME, period: u32) -> bool {
+    unsafe {
+        orig_SetThreadpoolTimer(timer, ptr::null(), 0, 0);
+        let key = Key::Threadpool(timer as _);
+        let old = if p_due.is_null() {
+            TIMERS.remove(&key).map(|(_, old)| old)
+        } else {
+            let due = (((*p_due).dwHighDateTime as i64) << 32) | (*p_due).dwLowDateTime as i64;
+            let target = Target::Threadpool(timer as _);
+            schedule(Timer::new(target, interrupt_time::due(due), period))
+        };
+        old.is_some_and(|old| old.due != NEVER)
+    }
+}
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn SetThreadpoolTimer(
+    timer: PTP_TIMER,
+    p_due: *const FILETIME,
+    period: u32,
+    _window: u32,
+) {
+    unsafe {
+        set_threadpool_timer(timer, p_due, period);
+    }
+}
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn SetThreadpoolTimerEx(
+    timer: PTP_TIMER,
+    p_due: *const FILETIME,
+    period: u32,
+    _window: u32,
+) -> BOOL {
+    if unsafe { set_threadpool_timer(timer, p_due, period) } {
+        TRUE
+    } else {
+        FALSE
+    }
+}
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn IsThreadpoolTimerSet(timer: PTP_TIMER) -> BOOL {
+    match TIMERS.get(&Key::Threadpool(timer as _)) {
+        Some(entry) if entry.due != NEVER => TRUE,
+        _ => FALSE,
+    }
+}
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn CloseThreadpoolTimer(timer: PTP_TIMER) {
+    TIMERS.remove(&Key::Threadpool(timer as _));
+    unsafe { orig_CloseThreadpoolTimer(timer) }
+}
@@ -1,6 +1,12 @@
 use std::sync::atomic::Ordering;
 
-use windows_sys::Win32::System::Threading::INFINITE;
+use windows_sys::{
+    Win32::{
+        Foundation::WAIT_IO_COMPLETION,
+        System::Threading::INFINITE,
+    },
+    core::BOOL,
+};
 
 use crate::hook::timing::{
     sync,
@@ -8,6 +14,7 @@ use crate::hook::timing::{
         NEXT_FRAME_EVENT,
         RESYNC_EVENT,
         orig_WaitForSingleObject,
+        orig_WaitForSingleObjectEx,
     },
 };
 
@@ -27,3 +34,24 @@ pub(super) unsafe extern "system" fn Sleep(ms: u32) {
         }
     }
 }
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn SleepEx(ms: u32, alertable: BOOL) -> u32 {
+    unsafe {
+        if !super::ENABLED.load(Ordering::Acquire) || ms == 0 || ms == INFINITE {
+            orig_SleepEx(ms, alertable)
+        } else {
+            orig_WaitForSingleObject(*RESYNC_EVENT as _, INFINITE);
+            let res = {
+                let _a = sync::WAITING.read();
+                orig_WaitForSingleObjectEx(*NEXT_FRAME_EVENT as _, ms, alertable)
+            };
+            if res == WAIT_IO_COMPLETION {
+                WAIT_IO_COMPLETION
+            } else {
+                0
+            }
+        }
+    }
+}
@@ -3,7 +3,10 @@ use std::{
     slice,
     sync::{
         LazyLock,
-        atomic::Ordering,
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
     },
 };
 
@@ -19,11 +22,19 @@ use windows_sys::{
             WAIT_OBJECT_0,
             WAIT_TIMEOUT,
         },
-        System::Threading::{
-            CreateEventW,
-            INFINITE,
-            ResetEvent,
-            SetEvent,
+        System::{
+            SystemServices::MAXIMUM_WAIT_OBJECTS,
+            Threading::{
+                CreateEventW,
+                INFINITE,
+                ResetEvent,
+                SetEvent,
+            },
+        },
+        UI::WindowsAndMessaging::{
+            MSG_WAIT_FOR_MULTIPLE_OBJECTS_EX_FLAGS,
+            MWMO_WAITALL,
+            QUEUE_STATUS_FLAGS,
         },
     },
     core::BOOL,
@@ -34,9 +45,13 @@ pub(super) static RESYNC_EVENT: LazyLock<usize> =
 pub(super) static NEXT_FRAME_EVENT: LazyLock<usize> =
     LazyLock::new(|| unsafe { CreateEventW(ptr::null(), TRUE, TRUE, ptr::null()) } as _);
 pub(super) static WAITING: RwLock<()> = RwLock::new(());
+/// Frames released so far, for waits that only hold [`WAITING`] while they
+/// actually wait.
+static FRAMES: AtomicU64 = AtomicU64::new(0);
 
 pub(super) fn tick() {
     unsafe {
+        FRAMES.fetch_add(1, Ordering::AcqRel);
         ResetEvent(*RESYNC_EVENT as _);
         SetEvent(*NEXT_FRAME_EVENT as _);
         {
@@ -47,25 +62,165 @@ pub(super) fn tick() {
     }
 }
 
+/// Whether a wait of `ms` is cut short at the next frame instead.
+fn virtual_wait(ms: u32) -> bool {
+    super::ENABLED.load(Ordering::Acquire) && ms <= 0x7fffffff
+}
+
+/// Real time a wait that cannot include the frame event sleeps before
+/// checking for the next frame.
+const SLICE_MS: u32 = 1;
+
+/// Hands `handles` with the frame event appended to `wait`.
+unsafe fn wait_with_frame(
+    handles: &[HANDLE],
+    wait: impl FnOnce(&[HANDLE]) -> WAIT_EVENT,
+) -> WAIT_EVENT {
+    unsafe {
+        orig_WaitForSingleObject(*RESYNC_EVENT as _, INFINITE);
+        let mut new_handles = ArrayVec::<HANDLE, { MAXIMUM_WAIT_OBJECTS as usize }>::new();
+        new_handles.extend(handles.iter().copied());
+        new_handles.push(*NEXT_FRAME_EVENT as _);
+        let _a = WAITING.read();
+        wait(&new_handles)
+    }
+}
+
+/// Calls `wait` with short timeouts until it returns, `ms` of real time pass
+/// or the next frame comes, for waits that have no room for the frame event
+/// or need all of their handles at once.
+unsafe fn wait_sliced(ms: u32, mut wait: impl FnMut(u32) -> WAIT_EVENT) -> WAIT_EVENT {
+    unsafe {
+        let frame = FRAMES.load(Ordering::Acquire);
+        let (start, f) = super::real();
+        loop {
+            let elapsed = (super::real().0 - start) as i128 * 1000 / f as i128;
+            let left = ms.saturating_sub(elapsed as u32);
+            // Frames may pass between slices, each one holds off only the
+            // frame that comes during it.
+            orig_WaitForSingleObject(*RESYNC_EVENT as _, INFINITE);
+            let res = {
+                let _a = WAITING.read();
+                wait(left.min(SLICE_MS))
+            };
+            if res != WAIT_TIMEOUT || left <= SLICE_MS || FRAMES.load(Ordering::Acquire) != frame {
+                return res;
+            }
+        }
+    }
+}
+
+unsafe fn wait_single(handle: HANDLE, ms: u32, alertable: BOOL) -> WAIT_EVENT {
+    unsafe {
+        let res = wait_with_frame(&[handle], |handles| {
+            orig_WaitForMultipleObjectsEx(
+                handles.len() as _,
+                handles.as_ptr(),
+                FALSE,
+                ms,
+                alertable,
+            )
+        });
+        if res == WAIT_OBJECT_0 + 1 {
+            WAIT_TIMEOUT
+        } else {
+            res
+        }
+    }
+}
+
+unsafe fn wait_multiple(
+    count: u32,
+    p_handles: *const HANDLE,
+    wait_all: BOOL,
+    ms: u32,
+    alertable: BOOL,
+) -> WAIT_EVENT {
+    unsafe {
+        if wait_all == TRUE || count >= MAXIMUM_WAIT_OBJECTS {
+            return wait_sliced(ms, |slice| {
+                orig_WaitForMultipleObjectsEx(count, p_handles, wait_all, slice, alertable)
+            });
+        }
+        let handles = slice::from_raw_parts(p_handles, count as usize);
+        let res = wait_with_frame(handles, |handles| {
+            orig_WaitForMultipleObjectsEx(
+                handles.len() as _,
+                handles.as_ptr(),
+                FALSE,
+                ms,
+                alertable,
+            )
+        });
+        if res == WAIT_OBJECT_0 + count {
+            WAIT_TIMEOUT
+        } else {
+            res
+        }
+    }
+}
+
+/// Like [`wait_multiple`], but the frame event goes before the message slot
+/// `WAIT_OBJECT_0 + count`, which is moved back in place. The message slot
+/// leaves room for one handle less.
+unsafe fn msg_wait(
+    count: u32,
+    p_handles: *const HANDLE,
+    ms: u32,
+    wake_mask: QUEUE_STATUS_FLAGS,
+    flags: MSG_WAIT_FOR_MULTIPLE_OBJECTS_EX_FLAGS,
+) -> WAIT_EVENT {
+    unsafe {
+        if flags & MWMO_WAITALL != 0 || count >= MAXIMUM_WAIT_OBJECTS - 1 {
+            return wait_sliced(ms, |slice| {
+                orig_MsgWaitForMultipleObjectsEx(count, p_handles, slice, wake_mask, flags)
+            });
+        }
+        let handles = slice::from_raw_parts(p_handles, count as usize);
+        let res = wait_with_frame(handles, |handles| {
+            orig_MsgWaitForMultipleObjectsEx(
+                handles.len() as _,
+                handles.as_ptr(),
+                ms,
+                wake_mask,
+                flags,
+            )
+        });
+        if res == WAIT_OBJECT_0 + count {
+            WAIT_TIMEOUT
+        } else if res == WAIT_OBJECT_0 + count + 1 {
+            WAIT_OBJECT_0 + count
+        } else {
+            res
+        }
+    }
+}
+
 #[recordin_macro::static_hook]
 #[allow(dead_code)]
 pub(super) unsafe extern "system" fn WaitForSingleObject(handle: HANDLE, ms: u32) -> WAIT_EVENT {
     // log::trace!("WaitForSingleObjects {ms}");
     unsafe {
-        if !super::ENABLED.load(Ordering::Acquire) || ms > 0x7fffffff {
+        if !virtual_wait(ms) {
             orig_WaitForSingleObject(handle, ms)
         } else {
-            orig_WaitForSingleObject(*RESYNC_EVENT as _, INFINITE);
-            let new_handles = [handle, *NEXT_FRAME_EVENT as _];
-            let res = {
-                let _a = WAITING.read();
-                orig_WaitForMultipleObjects(new_handles.len() as _, new_handles.as_ptr(), FALSE, ms)
-            };
-            if res == WAIT_OBJECT_0 + 1 {
-                WAIT_TIMEOUT
-            } else {
-                res
-            }
+            wait_single(handle, ms, FALSE)
+        }
+    }
+}
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn WaitForSingleObjectEx(
+    handle: HANDLE,
+    ms: u32,
+    alertable: BOOL,
+) -> WAIT_EVENT {
+    unsafe {
+        if !virtual_wait(ms) {
+            orig_WaitForSingleObjectEx(handle, ms, alertable)
+        } else {
+            wait_single(handle, ms, alertable)
         }
     }
 }
@@ -80,25 +235,88 @@ pub(super) unsafe extern "system" fn WaitForMultipleObjects(
 ) -> WAIT_EVENT {
     // log::trace!("WaitForMultipleObjects {count} {ms}");
     unsafe {
-        if !super::ENABLED.load(Ordering::Acquire) || ms > 0x7fffffff {
+        if !virtual_wait(ms) {
             orig_WaitForMultipleObjects(count, p_handles, wait_all, ms)
-        } else if wait_all == TRUE {
-            orig_WaitForMultipleObjects(count, p_handles, TRUE, 0)
         } else {
-            orig_WaitForSingleObject(*RESYNC_EVENT as _, INFINITE);
-            let handles = slice::from_raw_parts(p_handles, count as usize);
-            let mut new_handles = ArrayVec::<HANDLE, 16>::new();
-            new_handles.extend(handles.iter().copied());
-            new_handles.push(*NEXT_FRAME_EVENT as _);
-            let res = {
-                let _a = WAITING.read();
-                orig_WaitForMultipleObjects(new_handles.len() as _, new_handles.as_ptr(), FALSE, ms)
-            };
-            if res == WAIT_OBJECT_0 + count {
-                WAIT_TIMEOUT
-            } else {
-                res
-            }
+            wait_multiple(count, p_handles, wait_all, ms, FALSE)
+        }
+    }
+}
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn WaitForMultipleObjectsEx(
+    count: u32,
+    p_handles: *const HANDLE,
+    wait_all: BOOL,
+    ms: u32,
+    alertable: BOOL,
+) -> WAIT_EVENT {
+    unsafe {
+        if !virtual_wait(ms) {
+            orig_WaitForMultipleObjectsEx(count, p_handles, wait_all, ms, alertable)
+        } else {
+            wait_multiple(count, p_handles, wait_all, ms, alertable)
+        }
+    }
+}
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn MsgWaitForMultipleObjects(
+    count: u32,
+    p_handles: *const HANDLE,
+    wait_all: BOOL,
+    ms: u32,
+    wake_mask: QUEUE_STATUS_FLAGS,
+) -> WAIT_EVENT {
+    unsafe {
+        if !virtual_wait(ms) {
+            orig_MsgWaitForMultipleObjects(count, p_handles, wait_all, ms, wake_mask)
+        } else {
+            let flags = if wait_all == TRUE { MWMO_WAITALL } else { 0 };
+            msg_wait(count, p_handles, ms, wake_mask, flags)
+        }
+    }
+}
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn MsgWaitForMultipleObjectsEx(
+    count: u32,
+    p_handles: *const HANDLE,
+    ms: u32,
+    wake_mask: QUEUE_STATUS_FLAGS,
+    flags: MSG_WAIT_FOR_MULTIPLE_OBJECTS_EX_FLAGS,
+) -> WAIT_EVENT {
+    unsafe {
+        if !virtual_wait(ms) {
+            orig_MsgWaitForMultipleObjectsEx(count, p_handles, ms, wake_mask, flags)
+        } else {
+            msg_wait(count, p_handles, ms, wake_mask, flags)
+        }
+    }
+}
+
+/// Signals and polls atomically, then waits like `WaitForSingleObjectEx`.
+/// Only a waiter of a `PulseEvent` between the two could tell.
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn SignalObjectAndWait(
+    signal: HANDLE,
+    handle: HANDLE,
+    ms: u32,
+    alertable: BOOL,
+) -> WAIT_EVENT {
+    unsafe {
+        if !virtual_wait(ms) || ms == 0 {
+            return orig_SignalObjectAndWait(signal, handle, ms, alertable);
+        }
+        let res = orig_SignalObjectAndWait(signal, handle, 0, alertable);
+        if res == WAIT_TIMEOUT {
+            wait_single(handle, ms, alertable)
+        } else {
+            res
         }
     }
 }
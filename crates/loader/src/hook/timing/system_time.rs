@@ -0,0 +1,115 @@
+use std::{
+    mem::MaybeUninit,
+    ptr,
+    sync::atomic::{
+        AtomicI64,
+        Ordering,
+    },
+};
+
+use windows_sys::Win32::{
+    Foundation::{
+        FILETIME,
+        SYSTEMTIME,
+    },
+    System::{
+        SystemInformation::GetSystemTimePreciseAsFileTime,
+        Time::{
+            FileTimeToSystemTime,
+            SystemTimeToTzSpecificLocalTime,
+        },
+    },
+};
+
+use crate::{
+    env,
+    hook::timing,
+};
+
+/// 100 ns intervals between 1601-01-01, where `FILETIME` counts from, and
+/// 1970-01-01.
+const UNIX_EPOCH: i64 = 116_444_736_000_000_000;
+
+/// Performance counter when the virtual wall clock started.
+static START_COUNT: AtomicI64 = AtomicI64::new(0);
+/// Wall clock time at `START_COUNT` as a `FILETIME`.
+static START_TIME: AtomicI64 = AtomicI64::new(0);
+
+/// Starts the virtual wall clock at the configured date or the real time,
+/// before the hooks are enabled.
+pub(super) fn start(count: i64) {
+    let start = match env::start_date() {
+        Some(date) => date.unix_seconds * 10_000_000 + UNIX_EPOCH,
+        None => unsafe {
+            let mut ft = MaybeUninit::zeroed();
+            GetSystemTimePreciseAsFileTime(ft.as_mut_ptr());
+            from_file_time(ft.assume_init())
+        },
+    };
+    START_COUNT.store(count, Ordering::Relaxed);
+    START_TIME.store(start, Ordering::Relaxed);
+}
+
+/// Virtual wall clock time in 100 ns units since 1601, advancing with the
+/// performance counter.
+pub(super) fn file_time() -> i64 {
+    let (pc, f) = timing::perf();
+    let elapsed = (pc - START_COUNT.load(Ordering::Relaxed)) as i128 * 10_000_000 / f as i128;
+    START_TIME.load(Ordering::Relaxed) + elapsed as i64
+}
+
+/// Real wall clock time, which absolute due times of real timers count in.
+pub(super) fn real_file_time() -> i64 {
+    let mut ft = MaybeUninit::zeroed();
+    unsafe {
+        orig_GetSystemTimePreciseAsFileTime(ft.as_mut_ptr());
+        from_file_time(ft.assume_init())
+    }
+}
+
+fn now() -> FILETIME {
+    let t = file_time();
+    FILETIME {
+        dwLowDateTime: t as u32,
+        dwHighDateTime: (t >> 32) as u32,
+    }
+}
+
+fn from_file_time(ft: FILETIME) -> i64 {
+    ((ft.dwHighDateTime as i64) << 32) | ft.dwLowDateTime as i64
+}
+
+// The CRT's `time`, `_ftime` and friends read the file time functions,
+// statically linked or not, so they follow as well.
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn GetSystemTimeAsFileTime(p_time: *mut FILETIME) {
+    unsafe { *p_time = now() }
+}
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn GetSystemTimePreciseAsFileTime(p_time: *mut FILETIME) {
+    unsafe { *p_time = now() }
+}
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn GetSystemTime(p_time: *mut SYSTEMTIME) {
+    unsafe {
+        FileTimeToSystemTime(&now(), p_time);
+    }
+}
+
+/// In the time zone of the machine, with its daylight saving rules for the
+/// virtual date.
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn GetLocalTime(p_time: *mut SYSTEMTIME) {
+    unsafe {
+        let mut utc = MaybeUninit::zeroed();
+        FileTimeToSystemTime(&now(), utc.as_mut_ptr());
+        SystemTimeToTzSpecificLocalTime(ptr::null(), utc.as_ptr(), p_time);
+    }
+}
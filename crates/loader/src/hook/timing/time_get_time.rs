@@ -1,8 +1,29 @@
+use std::mem;
+
+use windows_sys::Win32::Media::{
+    MMTIME,
+    TIME_MS,
+    TIMERR_NOERROR,
+    TIMERR_STRUCT,
+};
+
 use crate::hook::timing;
 
 #[recordin_macro::static_hook]
 #[allow(dead_code)]
 pub(super) unsafe extern "system" fn timeGetTime() -> u32 {
-    let (pc, f) = timing::perf();
-    (pc / f * 1000) as _
+    timing::millis() as _
+}
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn timeGetSystemTime(p_time: *mut MMTIME, size: u32) -> u32 {
+    if p_time.is_null() || (size as usize) < mem::size_of::<MMTIME>() {
+        return TIMERR_STRUCT;
+    }
+    unsafe {
+        (*p_time).wType = TIME_MS;
+        (*p_time).u.ms = my_timeGetTime();
+    }
+    TIMERR_NOERROR
 }
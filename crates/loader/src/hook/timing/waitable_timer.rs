@@ -0,0 +1,372 @@
+use std::{
+    ffi::c_void,
+    ptr,
+    sync::{
+        LazyLock,
+        atomic::Ordering,
+    },
+};
+
+use dashmap::{
+    DashMap,
+    DashSet,
+};
+use windows_sys::{
+    Win32::{
+        Foundation::{
+            CloseHandle,
+            CompareObjectHandles,
+            DUPLICATE_SAME_ACCESS,
+            DuplicateHandle,
+            FALSE,
+            HANDLE,
+            TRUE,
+        },
+        System::Threading::{
+            GetCurrentProcess,
+            GetCurrentThread,
+            PTIMERAPCROUTINE,
+            QueueUserAPC,
+            REASON_CONTEXT,
+        },
+    },
+    core::BOOL,
+};
+
+use crate::hook::timing::{
+    interrupt_time,
+    system_time,
+};
+
+/// Absolute due time long gone, signaling a timer at once.
+const PAST: i64 = 1;
+/// Absolute due time that never comes, resetting a timer without firing it.
+const NEVER: i64 = i64::MAX;
+
+/// Timers set while time is virtual, by the handle the game set them with.
+/// The real timers stay idle and are signaled from [`tick`] once due.
+static TIMERS: LazyLock<DashMap<usize, WaitableTimer>> = LazyLock::new(DashMap::new);
+/// Handles of timers handed back to real time whose completion routine is
+/// not set again yet. Setting or canceling one drops the routine.
+static RESUMED: LazyLock<DashSet<usize>> = LazyLock::new(DashSet::new);
+
+struct WaitableTimer {
+    /// Duplicate of the game's handle, so a timer closed while pending is
+    /// not mistaken for whatever reuses the handle.
+    handle: usize,
+    /// Virtual interrupt time it fires at.
+    due: u64,
+    /// 100 ns, 0 for a one-shot timer.
+    period: u64,
+    completion: Option<Completion>,
+}
+
+/// Completion routine, queued to the thread that set the timer as the real
+/// timer would.
+struct Completion {
+    routine: PTIMERAPCROUTINE,
+    arg: usize,
+    thread: usize,
+}
+
+struct CompletionCall {
+    routine: PTIMERAPCROUTINE,
+    arg: usize,
+    file_time: i64,
+}
+
+/// Completion routine of a timer handed back to real time, given back to it
+/// from the thread that set it so that the routine is queued there.
+struct RealCompletion {
+    game_handle: usize,
+    handle: usize,
+    /// Real wall clock time the timer fires at.
+    due: i64,
+    /// Milliseconds.
+    period: i32,
+    routine: PTIMERAPCROUTINE,
+    arg: usize,
+}
+
+impl WaitableTimer {
+    /// Whether the game's handle still refers to this timer, rather than
+    /// being closed or reused for another object.
+    fn is_open(&self, game_handle: usize) -> bool {
+        unsafe { CompareObjectHandles(game_handle as _, self.handle as _) != FALSE }
+    }
+
+    /// Sets the real timer to fire as far ahead as the virtual one would.
+    /// The completion routine follows once the thread that set the timer is
+    /// alertable, the only time it could run anyway.
+    fn resume_real(&self, game_handle: usize, now: u64) {
+        let remaining = self.due.saturating_sub(now).max(1) as i64;
+        let period = (self.period / 10_000) as i32;
+        unsafe {
+            orig_SetWaitableTimer(
+                self.handle as _,
+                &-remaining,
+                period,
+                None,
+                ptr::null(),
+                FALSE,
+            );
+            let Some(completion) = &self.completion else {
+                return;
+            };
+            let process = GetCurrentProcess();
+            let mut handle = ptr::null_mut();
+            if DuplicateHandle(
+                process,
+                self.handle as _,
+                process,
+                &mut handle,
+                0,
+                FALSE,
+                DUPLICATE_SAME_ACCESS,
+            ) == FALSE
+            {
+                return;
+            }
+            RESUMED.insert(game_handle);
+            let call = Box::into_raw(Box::new(RealCompletion {
+                game_handle,
+                handle: handle as _,
+                due: system_time::real_file_time() + remaining,
+                period,
+                routine: completion.routine,
+                arg: completion.arg,
+            }));
+            if QueueUserAPC(Some(resume_completion), completion.thread as _, call as _) == 0 {
+                drop(Box::from_raw(call));
+                CloseHandle(handle);
+            }
+        }
+    }
+
+    /// Fires the timer if it is due, returning whether it is still pending.
+    fn fire(&mut self, now: u64) -> bool {
+        if self.due > now {
+            return true;
+        }
+        unsafe {
+            orig_SetWaitableTimer(self.handle as _, &PAST, 0, None, ptr::null(), FALSE);
+            if let Some(completion) = &self.completion {
+                let call = Box::into_raw(Box::new(CompletionCall {
+                    routine: completion.routine,
+                    arg: completion.arg,
+                    file_time: system_time::file_time(),
+                }));
+                if QueueUserAPC(Some(run_completion), completion.thread as _, call as _) == 0 {
+                    drop(Box::from_raw(call));
+                }
+            }
+        }
+        if self.period == 0 {
+            return false;
+        }
+        // Periods missed within one frame are signaled once, like a real
+        // timer whose waiters fall behind.
+        self.due += (now - self.due) / self.period * self.period + self.period;
+        true
+    }
+}
+
+impl Drop for WaitableTimer {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.handle as _);
+            if let Some(completion) = &self.completion {
+                CloseHandle(completion.thread as _);
+            }
+        }
+    }
+}
+
+unsafe extern "system" fn run_completion(call: usize) {
+    unsafe {
+        let call = Box::from_raw(call as *mut CompletionCall);
+        if let Some(routine) = call.routine {
+            routine(
+                call.arg as _,
+                call.file_time as u32,
+                (call.file_time >> 32) as u32,
+            );
+        }
+    }
+}
+
+/// Sets the routine on the real timer not fired yet. One that already fired
+/// has the routine run here, as its APC would have, and is set again from
+/// its next period.
+unsafe extern "system" fn resume_completion(call: usize) {
+    unsafe {
+        let call = Box::from_raw(call as *mut RealCompletion);
+        if RESUMED.remove(&call.game_handle).is_none()
+            || CompareObjectHandles(call.game_handle as _, call.handle as _) == FALSE
+        {
+            CloseHandle(call.handle as _);
+            return;
+        }
+        let now = system_time::real_file_time();
+        let mut due = call.due;
+        if due <= now {
+            if let Some(routine) = call.routine {
+                routine(call.arg as _, due as u32, (due >> 32) as u32);
+            }
+            let period = call.period as i64 * 10_000;
+            if period == 0 {
+                CloseHandle(call.handle as _);
+                return;
+            }
+            due += ((now - due) / period + 1) * period;
+        }
+        orig_SetWaitableTimer(
+            call.handle as _,
+            &due,
+            call.period,
+            call.routine,
+            call.arg as _,
+            FALSE,
+        );
+        CloseHandle(call.handle as _);
+    }
+}
+
+/// Fires every virtual timer that came due, before waiters of the frame are
+/// released. Timers the game closed are forgotten.
+pub(super) fn tick() {
+    let now = interrupt_time::now();
+    TIMERS.retain(|&game_handle, timer| timer.is_open(game_handle) && timer.fire(now));
+}
+
+/// Hands the pending virtual timers back to real time once time is no
+/// longer virtual.
+pub(super) fn pause() {
+    let now = interrupt_time::now();
+    TIMERS.retain(|&game_handle, timer| {
+        if timer.is_open(game_handle) {
+            timer.resume_real(game_handle, now);
+        }
+        false
+    });
+}
+
+unsafe fn set(
+    handle: HANDLE,
+    due: i64,
+    period: i32,
+    routine: PTIMERAPCROUTINE,
+    arg: *const c_void,
+) -> BOOL {
+    RESUMED.remove(&(handle as usize));
+    unsafe {
+        // Setting a timer resets it, which also checks the handle.
+        if orig_SetWaitableTimer(handle, &NEVER, 0, None, ptr::null(), FALSE) == FALSE {
+            return FALSE;
+        }
+        orig_CancelWaitableTimer(handle);
+        let process = GetCurrentProcess();
+        let mut own = ptr::null_mut();
+        if DuplicateHandle(
+            process,
+            handle,
+            process,
+            &mut own,
+            0,
+            FALSE,
+            DUPLICATE_SAME_ACCESS,
+        ) == FALSE
+        {
+            return FALSE;
+        }
+        let completion = routine.is_some().then(|| {
+            let mut thread = ptr::null_mut();
+            DuplicateHandle(
+                process,
+                GetCurrentThread(),
+                process,
+                &mut thread,
+                0,
+                FALSE,
+                DUPLICATE_SAME_ACCESS,
+            );
+            Completion {
+                routine,
+                arg: arg as _,
+                thread: thread as _,
+            }
+        });
+        TIMERS.insert(
+            handle as _,
+            WaitableTimer {
+                handle: own as _,
+                due: interrupt_time::due(due),
+                period: period.max(0) as u64 * 10_000,
+                completion,
+            },
+        );
+        TRUE
+    }
+}
+
+// Creating a timer involves no time, only setting it needs a hook.
+
+/// Timers set before the first frame or while paused run in real time.
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn SetWaitableTimer(
+    handle: HANDLE,
+    p_due: *const i64,
+    period: i32,
+    routine: PTIMERAPCROUTINE,
+    arg: *const c_void,
+    resume: BOOL,
+) -> BOOL {
+    unsafe {
+        if !super::ENABLED.load(Ordering::Acquire) {
+            TIMERS.remove(&(handle as usize));
+            RESUMED.remove(&(handle as usize));
+            orig_SetWaitableTimer(handle, p_due, period, routine, arg, resume)
+        } else {
+            set(handle, *p_due, period, routine, arg)
+        }
+    }
+}
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn SetWaitableTimerEx(
+    handle: HANDLE,
+    p_due: *const i64,
+    period: i32,
+    routine: PTIMERAPCROUTINE,
+    arg: *const c_void,
+    wake_context: *const REASON_CONTEXT,
+    tolerable_delay: u32,
+) -> BOOL {
+    unsafe {
+        if !super::ENABLED.load(Ordering::Acquire) {
+            TIMERS.remove(&(handle as usize));
+            RESUMED.remove(&(handle as usize));
+            orig_SetWaitableTimerEx(
+                handle,
+                p_due,
+                period,
+                routine,
+                arg,
+                wake_context,
+                tolerable_delay,
+            )
+        } else {
+            set(handle, *p_due, period, routine, arg)
+        }
+    }
+}
+
+#[recordin_macro::static_hook]
+#[allow(dead_code)]
+pub(super) unsafe extern "system" fn CancelWaitableTimer(handle: HANDLE) -> BOOL {
+    TIMERS.remove(&(handle as usize));
+    RESUMED.remove(&(handle as usize));
+    unsafe { orig_CancelWaitableTimer(handle) }
+}
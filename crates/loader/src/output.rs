@@ -1,2 +1,13 @@
 pub(super) mod audio_codec;
+pub(super) mod container;
+pub(super) mod dump;
+pub(super) mod finalize;
+pub(super) mod image_sequence;
+pub(super) mod muxer;
+pub(super) mod pipe;
+pub(super) mod probe;
+pub(super) mod queue;
+pub(super) mod scaler;
+pub(super) mod segment;
+pub(super) mod sink;
 pub(super) mod video_codec;
@@ -1,182 +1,506 @@
 use std::{
-    cell::LazyCell,
     fs::File,
     num::NonZero,
-    sync::atomic::{
-        AtomicU32,
-        Ordering,
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::{
+        Arc,
+        atomic::AtomicU32,
     },
 };
 
 use expanding_slice_rb::ExpSliceRB;
+use recordin_common::{
+    config::AudioConfig,
+    suggest,
+};
 use scuffle_ffmpeg::{
     AVSampleFormat,
     codec::EncoderCodec,
+    dict::Dictionary,
     encoder::{
         AudioEncoderSettings,
         Encoder,
     },
+    ffi,
     frame::{
         AudioChannelLayout,
         AudioFrame,
     },
-    io::{
-        Output,
-        OutputOptions,
-    },
+    io::Output,
     rational::Rational,
     resampler::Resampler,
 };
 
-use crate::env;
+use crate::{
+    env,
+    output::{
+        container::Container,
+        muxer::{
+            self,
+            Muxer,
+            SharedOutput,
+        },
+        probe,
+        sink::{
+            self,
+            AudioSink,
+        },
+    },
+};
+
+/// What the hooked audio clients render: interleaved stereo `f32` at 48 kHz.
+pub(super) const INPUT_RATE: i32 = 48000;
+pub(super) const CHANNELS: i32 = 2;
+pub(super) const BYTES_PER_SAMPLE: usize = 4 * CHANNELS as usize;
+
+/// Samples per frame for encoders that take any frame size.
+const VARIABLE_FRAME_SIZE: usize = 4096;
+
+/// Silence pushed through the rate converter at the end, more than its
+/// filter holds back.
+const FLUSH_SAMPLES: usize = 256;
+
+/// Preferred encoder sample formats, cheapest conversion from `f32` first.
+const SAMPLE_FORMATS: &[ffi::AVSampleFormat] = &[
+    ffi::AV_SAMPLE_FMT_FLT,
+    ffi::AV_SAMPLE_FMT_FLTP,
+    ffi::AV_SAMPLE_FMT_S32,
+    ffi::AV_SAMPLE_FMT_S32P,
+    ffi::AV_SAMPLE_FMT_S16,
+    ffi::AV_SAMPLE_FMT_S16P,
+];
 
 pub(crate) static STREAM_COUNTER: AtomicU32 = AtomicU32::new(0);
 
-pub(crate) type AudioEncDuplex = (kanal::Sender<Vec<u8>>, kanal::Receiver<Vec<u8>>);
+/// An opened encoder together with the format it was negotiated for.
+pub(crate) struct AudioStream {
+    encoder: Encoder,
+    sample_fmt: AVSampleFormat,
+    sample_rate: i32,
+    frame_size: usize,
+    /// Whether the last frame may be shorter than `frame_size`.
+    variable_frame_size: bool,
+}
+
+fn create_file(path: &Path) -> anyhow::Result<(PathBuf, File)> {
+    let new_path = sink::numbered_path(path, &STREAM_COUNTER)?;
+    log::trace!("Audio output: \n{:?}", new_path);
+    let file = File::create(&new_path)?;
+    Ok((new_path, file))
+}
+
+/// Opens the audio file of its own.
+fn open(path: &Path, audio: &AudioConfig, container: &Container) -> anyhow::Result<Pipeline> {
+    let (path, writer) = create_file(path)?;
+    let mut output = container.open(&path, writer)?;
+    let stream = new_encoder(&mut output, audio)?;
+    container.write_header(&mut output)?;
+    Pipeline::new(Muxer::Own(output), stream, 0)
+}
 
-pub(crate) fn create_encoder() -> Option<AudioEncDuplex> {
-    let (tx1, rx1) = kanal::bounded(30);
-    let (tx2, rx2) = kanal::bounded(30);
-    for _ in 0..10 {
-        tx2.try_send(Vec::new()).ok();
+/// Creates the encoder, picking the sample format, rate and frame size it
+/// supports closest to what the audio clients render.
+pub(super) fn new_encoder<T: Send + Sync>(
+    output: &mut Output<T>,
+    audio: &AudioConfig,
+) -> anyhow::Result<AudioStream> {
+    let codec = EncoderCodec::by_name(&audio.encoder).ok_or_else(|| {
+        let names = probe::encoder_names(ffi::AVMEDIA_TYPE_AUDIO);
+        anyhow::anyhow!(
+            "encoder {} not found{}",
+            audio.encoder,
+            suggest::did_you_mean(&audio.encoder, names.iter().map(String::as_str))
+        )
+    })?;
+    let unknown = probe::unknown_options(&audio.encoder, audio.options.keys());
+    if !unknown.is_empty() {
+        anyhow::bail!(
+            "encoder {} has no option {}",
+            audio.encoder,
+            unknown.join(", ")
+        );
     }
-    let path = env::AUDIO_OUTPUT.as_ref()?.clone();
-    std::thread::spawn(move || {
-        match loop_encode(rx1, tx2, move || {
-            let mut new_path = path.clone();
-            let mut stem = path
-                .file_stem()
-                .ok_or(anyhow::anyhow!("must have a file name"))?
-                .to_owned();
-            let c = STREAM_COUNTER.fetch_add(1, Ordering::Relaxed);
-            if c != 0 {
-                stem.push(c.to_string());
-            }
-            new_path.set_file_name(stem);
-            if let Some(ext) = path.extension() {
-                new_path.set_extension(ext);
+    let sample_fmt = match probe::sample_formats(&audio.encoder) {
+        Some(formats) => SAMPLE_FORMATS
+            .iter()
+            .find(|&&f| formats.contains(&f))
+            .or(formats.first())
+            .copied()
+            .ok_or(anyhow::anyhow!(
+                "encoder {} takes no sample format",
+                audio.encoder
+            ))?,
+        None => ffi::AV_SAMPLE_FMT_FLT,
+    };
+    let rates = probe::sample_rates(&audio.encoder);
+    let sample_rate = match (audio.sample_rate, rates) {
+        (Some(rate), Some(rates)) if !rates.contains(&(rate as i32)) => anyhow::bail!(
+            "encoder {} does not support sample rate {rate}, supported: {}",
+            audio.encoder,
+            rates
+                .iter()
+                .map(i32::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        (Some(rate), _) => rate as i32,
+        (None, Some(rates)) => rates
+            .into_iter()
+            .min_by_key(|r| (r - INPUT_RATE).abs())
+            .unwrap_or(INPUT_RATE),
+        (None, None) => INPUT_RATE,
+    };
+    let mut options = audio.options.clone();
+    if let Some(bitrate) = audio.bitrate {
+        options.insert("b".to_owned(), bitrate.to_string());
+    }
+    let fixed =
+        probe::audio_frame_size(&audio.encoder, sample_fmt, sample_rate, CHANNELS, &options)?;
+    let frame_size = fixed.unwrap_or(VARIABLE_FRAME_SIZE);
+    log::trace!(
+        "Audio format: {} Hz, sample format {sample_fmt}, {frame_size} samples per frame",
+        sample_rate
+    );
+    let dict = Dictionary::try_from_iter(options.iter().map(|(k, v)| (k.as_str(), v.as_str())))?;
+    let sample_fmt = AVSampleFormat::from(sample_fmt);
+    let audio_settings = AudioEncoderSettings::builder()
+        .sample_rate(sample_rate)
+        .ch_layout(AudioChannelLayout::new(CHANNELS).unwrap())
+        .sample_fmt(sample_fmt)
+        .codec_specific_options(dict)
+        .build();
+    let encoder = Encoder::new(
+        codec,
+        output,
+        Rational::new(1, NonZero::new(sample_rate).unwrap()),
+        Rational::new(1, NonZero::new(1000).unwrap()),
+        audio_settings,
+    )?;
+    Ok(AudioStream {
+        encoder,
+        sample_fmt,
+        sample_rate,
+        frame_size,
+        variable_frame_size: fixed.is_none(),
+    })
+}
+
+pub(super) fn at_input_rate(tick: i64, fps: f64) -> i64 {
+    (tick as f64 * INPUT_RATE as f64 / fps).round() as i64
+}
+
+fn packed_frame(samples: usize, sample_rate: i32) -> anyhow::Result<AudioFrame> {
+    Ok(AudioFrame::builder()
+        .channel_layout(AudioChannelLayout::new(CHANNELS).unwrap())
+        .nb_samples(samples as _)
+        .sample_fmt(AVSampleFormat::Flt)
+        .sample_rate(sample_rate)
+        .build()?)
+}
+
+/// Turns rendered audio into frames of the size and format the encoder wants.
+struct Pipeline {
+    muxer: Muxer,
+    stream: AudioStream,
+    /// Converts from the rendered rate, if the encoder runs at another one.
+    rate: Option<Resampler>,
+    /// Converts packed `f32` to the encoder's sample format, if it differs.
+    format: Option<Resampler>,
+    /// Packed `f32` at the encoder's rate, not yet encoded.
+    ring: ExpSliceRB<u8>,
+    frame: AudioFrame,
+    start: i64,
+    /// Samples encoded, at the encoder's rate.
+    count: i64,
+    /// Samples pushed, at the input rate.
+    pushed: i64,
+}
+
+impl Pipeline {
+    fn new(muxer: Muxer, stream: AudioStream, start: i64) -> anyhow::Result<Self> {
+        let layout = || AudioChannelLayout::new(CHANNELS).unwrap();
+        let rate = (stream.sample_rate != INPUT_RATE)
+            .then(|| {
+                Resampler::new(
+                    layout(),
+                    AVSampleFormat::Flt,
+                    INPUT_RATE,
+                    layout(),
+                    AVSampleFormat::Flt,
+                    stream.sample_rate,
+                )
+            })
+            .transpose()?;
+        let format = (stream.sample_fmt != AVSampleFormat::Flt)
+            .then(|| {
+                Resampler::new(
+                    layout(),
+                    AVSampleFormat::Flt,
+                    stream.sample_rate,
+                    layout(),
+                    stream.sample_fmt,
+                    stream.sample_rate,
+                )
+            })
+            .transpose()?;
+        let frame = packed_frame(stream.frame_size, stream.sample_rate)?;
+        let ring = ExpSliceRB::with_capacity(
+            NonZero::new(stream.frame_size * BYTES_PER_SAMPLE * 2).unwrap(),
+        );
+        Ok(Self {
+            muxer,
+            stream,
+            rate,
+            format,
+            ring,
+            frame,
+            start,
+            count: 0,
+            pushed: 0,
+        })
+    }
+
+    fn push(&mut self, input: &[u8]) -> anyhow::Result<()> {
+        self.pushed += (input.len() / BYTES_PER_SAMPLE) as i64;
+        self.convert(input)?;
+        let frame_len = self.stream.frame_size * BYTES_PER_SAMPLE;
+        while self.ring.len() >= frame_len {
+            self.encode_frame(self.stream.frame_size)?;
+        }
+        Ok(())
+    }
+
+    /// Adds `input` to the ring at the encoder's rate.
+    fn convert(&mut self, input: &[u8]) -> anyhow::Result<()> {
+        match &mut self.rate {
+            None => self.ring.write(input),
+            Some(re) => {
+                let mut fr = packed_frame(input.len() / BYTES_PER_SAMPLE, INPUT_RATE)?;
+                fr.data_mut(0).unwrap()[..input.len()].copy_from_slice(input);
+                let converted = re.process(&fr)?;
+                let len = converted.nb_samples() as usize * BYTES_PER_SAMPLE;
+                self.ring.write(&converted.data(0).unwrap()[..len]);
             }
-            log::trace!("Audio output: \n{:?}", path);
-            Ok(File::create(path)?)
-        }) {
-            Ok(_) => {
-                log::info!("Audio encoder successfully completed");
+        }
+        Ok(())
+    }
+
+    /// Encodes a frame of `samples` samples from the ring, padding it with
+    /// silence if the ring holds less.
+    fn encode_frame(&mut self, samples: usize) -> anyhow::Result<()> {
+        let mut short;
+        let frame = if samples == self.stream.frame_size {
+            &mut self.frame
+        } else {
+            short = packed_frame(samples, self.stream.sample_rate)?;
+            &mut short
+        };
+        let frame_len = samples * BYTES_PER_SAMPLE;
+        let data = &mut frame.data_mut(0).unwrap()[..frame_len];
+        let len = self.ring.len().min(frame_len);
+        self.ring.read_into(&mut data[..len]);
+        data[len..].fill(0);
+        let pts = self.start + self.count;
+        self.count += samples as i64;
+        let e = &mut self.stream.encoder;
+        match &mut self.format {
+            Some(re) => {
+                let mut rsp = re.process(&*frame)?;
+                rsp.set_pts(pts.into());
+                e.send_frame(&rsp)?;
             }
-            Err(e) => {
-                log::warn!("Audio encoder error: {}", e);
+            None => {
+                frame.set_pts(pts.into());
+                e.send_frame(&*frame)?;
             }
         }
-    });
-    (tx1, rx2).into()
-}
+        while let Some(packet) = e.receive_packet()? {
+            self.muxer.write(packet)?;
+        }
+        Ok(())
+    }
 
-fn loop_encode(
-    rx: kanal::Receiver<Vec<u8>>,
-    tx: kanal::Sender<Vec<u8>>,
-    lazy_file: impl FnOnce() -> anyhow::Result<File> + 'static,
-) -> anyhow::Result<()> {
-    log::trace!("Audio encoder: {}", "wavpack");
-    struct LazyGroup {
-        output: Output<File>,
-        encoder: Encoder,
-        frame: AudioFrame,
-        resampler: Resampler,
+    fn finish(&mut self) -> anyhow::Result<()> {
+        if self.rate.is_some() {
+            // Silence pushes out what the filter holds back, of which only
+            // as much as the input amounts to is kept.
+            self.convert(&[0; FLUSH_SAMPLES * BYTES_PER_SAMPLE])?;
+            let total = self.pushed * self.stream.sample_rate as i64 / INPUT_RATE as i64;
+            let keep = (total - self.count).max(0) as usize * BYTES_PER_SAMPLE;
+            let mut rest = vec![0; self.ring.len()];
+            self.ring.read_into(&mut rest);
+            rest.truncate(keep);
+            self.ring.write(&rest);
+        }
+        let frame_size = self.stream.frame_size;
+        while self.ring.len() >= frame_size * BYTES_PER_SAMPLE {
+            self.encode_frame(frame_size)?;
+        }
+        let rest = self.ring.len() / BYTES_PER_SAMPLE;
+        if rest > 0 {
+            // Only encoders of a fixed frame size need the last one padded.
+            let samples = if self.stream.variable_frame_size {
+                rest
+            } else {
+                frame_size
+            };
+            self.encode_frame(samples)?;
+        }
+        let e = &mut self.stream.encoder;
+        e.send_eof()?;
+        while let Some(packet) = e.receive_packet()? {
+            self.muxer.write(packet)?;
+        }
+        self.muxer.finish()?;
+        log::trace!("Audio encoded samples {}", self.count);
+        Ok(())
     }
-    let mut lazy_group = LazyCell::new(|| {
-        let writer = lazy_file()?;
-        let mut output = Output::seekable(
-            writer,
-            OutputOptions::builder().format_name("Matroska")?.build(),
-        )?;
-        let codec = EncoderCodec::by_name("wavpack").unwrap();
-        let audio_settings = AudioEncoderSettings::builder()
-            .sample_rate(48000)
-            .ch_layout(AudioChannelLayout::new(2).unwrap())
-            .sample_fmt(AVSampleFormat::Fltp)
-            .build();
-        let encoder = Encoder::new(
-            codec,
-            &mut output,
-            Rational::new(1, NonZero::new(48000).unwrap()),
-            Rational::new(1, NonZero::new(1000).unwrap()),
-            audio_settings,
-        )?;
-        output.write_header()?;
-        let frame = AudioFrame::builder()
-            .channel_layout(AudioChannelLayout::new(2).unwrap())
-            .nb_samples(24000)
-            .sample_fmt(AVSampleFormat::Flt)
-            .sample_rate(48000)
-            .build()?;
-        let resampler = Resampler::new(
-            AudioChannelLayout::new(2).unwrap(),
-            AVSampleFormat::Flt,
-            48000,
-            AudioChannelLayout::new(2).unwrap(),
-            AVSampleFormat::Fltp,
-            48000,
-        )?;
-        let lazy = LazyGroup {
-            output,
-            encoder,
-            frame,
-            resampler,
+}
+
+/// The encoded audio, in a file of its own or in the video output.
+pub(crate) struct AudioOutput {
+    audio: &'static AudioConfig,
+    /// `None` when merging into the video output.
+    container: Option<Container>,
+    fps: f64,
+    /// Virtual time of the next rendered sample, at the input rate.
+    position: i64,
+    /// Rendered audio not written yet, up to `position`. When merging, it
+    /// piles up here until the video thread opens an output.
+    pending: Vec<u8>,
+    pipeline: Option<Pipeline>,
+    /// The video output `pipeline` writes to when merging.
+    shared: Option<Arc<SharedOutput>>,
+}
+
+impl AudioOutput {
+    pub(crate) fn new(audio: &'static AudioConfig, start_tick: i64) -> anyhow::Result<Self> {
+        log::trace!("Audio encoder: {}", audio.encoder);
+        log::info!("ffmpeg arguments:\n{:?}", audio.options);
+        let merging = muxer::merging();
+        let container = match &audio.output {
+            Some(path) if !merging => Some(Container::resolve(
+                path,
+                audio.container.as_deref(),
+                &audio.container_options,
+                &audio.encoder,
+                env::recoverable(),
+            )?),
+            None if !merging => anyhow::bail!("Audio output not set"),
+            _ => None,
         };
-        anyhow::Ok(lazy)
-    });
-    let mut count = 0;
-    let mut ring = ExpSliceRB::with_capacity(NonZero::new(24000 * 4 * 2).unwrap());
-    while let Ok(buf) = rx.recv() {
-        let LazyGroup {
-            output: o,
-            encoder: e,
-            frame: fr,
-            resampler: re,
-        } = lazy_group.as_mut().map_err(|e| anyhow::anyhow!("{e}"))?;
-        ring.write(&buf);
-        tx.send(buf).ok();
-        while ring.len() >= 24000 * 4 * 2 {
-            let fr_data = fr.data_mut(0).unwrap();
-            ring.read_into(&mut fr_data[0..24000 * 4 * 2]);
-            let mut rsp = re.process(fr)?;
-            rsp.set_pts(count.into());
-            count += 24000;
-            e.send_frame(&rsp)?;
-            while let Some(packet) = e.receive_packet()? {
-                o.write_interleaved_packet(packet)?;
+        if let Some(container) = &container {
+            log::trace!(
+                "Audio container: {} {:?}",
+                container.name,
+                container.options
+            );
+        }
+        if merging {
+            SharedOutput::listen();
+        }
+        let fps = env::FPS.get();
+        let mut output = Self {
+            audio,
+            container,
+            fps,
+            position: 0,
+            pending: Vec::new(),
+            pipeline: None,
+            shared: None,
+        };
+        output.position = at_input_rate(start_tick, fps);
+        Ok(output)
+    }
+
+    /// Virtual time of the first pending sample, at the input rate.
+    fn pending_start(&self) -> i64 {
+        self.position - (self.pending.len() / BYTES_PER_SAMPLE) as i64
+    }
+
+    /// Writes pending audio into the video outputs in the order they were
+    /// opened, handing over to the next one at the virtual frame the video
+    /// stream of the current one ended, so that a resized or rotated output
+    /// gets its audio as well. Audio the video thread has not caught up with
+    /// waits unless `flush` is set.
+    fn follow_video(&mut self, flush: bool) -> anyhow::Result<()> {
+        loop {
+            let start = self.pending_start();
+            let (Some(shared), Some(p)) = (&self.shared, &mut self.pipeline) else {
+                let Some((shared, stream)) = SharedOutput::claim()? else {
+                    return Ok(());
+                };
+                // Audio from before the output started has no place in it.
+                let file_start = at_input_rate(shared.start_tick, self.fps);
+                let skip = (file_start - start).max(0) as usize * BYTES_PER_SAMPLE;
+                self.pending.drain(..skip.min(self.pending.len()));
+                // Samples are counted from the start of the output, like the
+                // frames of the video stream, so both follow the same clock.
+                let start = (self.pending_start() - file_start) * stream.sample_rate as i64
+                    / INPUT_RATE as i64;
+                self.pipeline = Some(Pipeline::new(Muxer::Shared(shared.clone()), stream, start)?);
+                self.shared = Some(shared);
+                continue;
+            };
+            let (limit, ended) = shared.audio_limit();
+            let split = if flush && !ended {
+                self.pending.len()
+            } else {
+                let limit = at_input_rate(limit, self.fps);
+                ((limit - start).max(0) as usize * BYTES_PER_SAMPLE).min(self.pending.len())
+            };
+            p.push(&self.pending[..split])?;
+            self.pending.drain(..split);
+            if !ended {
+                return Ok(());
             }
+            self.shared = None;
+            self.pipeline.take().unwrap().finish()?;
         }
     }
-    if count > 0
-        && let Ok(LazyGroup {
-            output: o,
-            encoder: e,
-            frame: _,
-            resampler: re,
-        }) = lazy_group.as_mut()
-    {
-        let rest = ring.len();
-        let mut fr = AudioFrame::builder()
-            .nb_samples(rest as i32 / 4 / 2)
-            .channel_layout(AudioChannelLayout::new(2).unwrap())
-            .sample_fmt(AVSampleFormat::Flt)
-            .sample_rate(48000)
-            .build()?;
-        let fr_data = fr.data_mut(0).unwrap();
-        ring.read_into(&mut fr_data[0..rest]);
-        let mut rsp = re.process(&fr)?;
-        rsp.set_pts(count.into());
-        count += rest as i64 / 4 / 2;
-        e.send_frame(&rsp)?;
-        while let Some(pk) = e.receive_packet()? {
-            o.write_interleaved_packet(pk)?;
+}
+
+impl AudioSink for AudioOutput {
+    fn push(&mut self, samples: &[u8]) -> anyhow::Result<()> {
+        self.position += (samples.len() / BYTES_PER_SAMPLE) as i64;
+        let (Some(container), Some(path)) = (&self.container, &self.audio.output) else {
+            self.pending.extend_from_slice(samples);
+            return self.follow_video(false);
+        };
+        if self.pipeline.is_none() {
+            self.pipeline = Some(open(path, self.audio, container)?);
         }
-        e.send_eof()?;
-        while let Some(pk) = e.receive_packet()? {
-            o.write_interleaved_packet(pk)?;
+        self.pipeline.as_mut().unwrap().push(samples)
+    }
+
+    fn finish(mut self: Box<Self>) -> anyhow::Result<()> {
+        if self.container.is_none() {
+            self.follow_video(true)?;
+        }
+        self.shared = None;
+        match self.pipeline.take() {
+            Some(mut p) => p.finish(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for AudioOutput {
+    fn drop(&mut self) {
+        if self.container.is_some() {
+            return;
+        }
+        // Outputs of a failed audio output must not wait for it.
+        if let Some(mut p) = self.pipeline.take()
+            && let Err(e) = p.finish()
+        {
+            log::warn!("Audio stream not finished: {e}");
+        }
+        if let Err(e) = SharedOutput::unlisten() {
+            log::warn!("Output without audio not finished: {e}");
         }
-        o.write_trailer()?;
-        log::trace!("Audio encoded samples {count}");
     }
-    Ok(())
 }
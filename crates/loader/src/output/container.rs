@@ -0,0 +1,181 @@
+use std::{
+    collections::BTreeMap,
+    ffi::CString,
+    fs::File,
+    path::Path,
+    ptr,
+};
+
+use recordin_common::suggest;
+use scuffle_ffmpeg::{
+    dict::Dictionary,
+    ffi,
+    io::{
+        Output,
+        OutputOptions,
+    },
+};
+
+use crate::output::probe;
+
+/// Muxer used when neither `--container` nor the extension says otherwise.
+const FALLBACK: &str = "matroska";
+
+/// Short names accepted on top of ffmpeg's muxer names.
+const ALIASES: &[(&str, &str)] = &[("mkv", "matroska"), ("mka", "matroska")];
+
+/// Raw stream muxers that are not named after their codec.
+const RAW_MUXERS: &[(&str, &str)] = &[
+    ("aac", "adts"),
+    ("av1", "obu"),
+    ("mpeg4", "m4v"),
+    ("vp8", "ivf"),
+    ("vp9", "ivf"),
+    ("wavpack", "wv"),
+];
+
+/// Muxers that move their index to the front of the file, or write it in
+/// fragments when recoverable, unless the user sets `movflags` themselves.
+const MOV_FAMILY: &[&str] = &["mp4", "mov"];
+
+/// Lets a file cut short before its trailer still play up to the last
+/// fragment.
+const FRAGMENTED: &str = "+frag_keyframe+empty_moov+default_base_moof";
+
+pub(crate) struct Container {
+    pub(crate) name: String,
+    pub(crate) options: BTreeMap<String, String>,
+}
+
+impl Container {
+    /// Picks the muxer named by `explicit` (or `raw` for the bare stream of
+    /// `encoder`), otherwise the one matching the extension of `path`.
+    pub(crate) fn resolve(
+        path: &Path,
+        explicit: Option<&str>,
+        options: &BTreeMap<String, String>,
+        encoder: &str,
+        recoverable: bool,
+    ) -> anyhow::Result<Self> {
+        let codec = codec_id(encoder);
+        let muxer = match explicit {
+            Some("raw") => {
+                let codec = codec.ok_or(anyhow::anyhow!("encoder {encoder} not found"))?;
+                raw_muxer(codec)?
+            }
+            Some(name) => {
+                let name = ALIASES
+                    .iter()
+                    .find(|(alias, _)| *alias == name)
+                    .map_or(name, |(_, muxer)| *muxer);
+                muxer(name).ok_or_else(|| {
+                    let mut names = probe::muxer_names();
+                    names.extend(["raw", "mkv"].map(str::to_owned));
+                    anyhow::anyhow!(
+                        "container {name} not found{}",
+                        suggest::did_you_mean(name, names.iter().map(String::as_str))
+                    )
+                })?
+            }
+            None => muxer_for_file(path)
+                .or_else(|| {
+                    log::warn!("No container known for {path:?}, using {FALLBACK}");
+                    muxer(FALLBACK)
+                })
+                .ok_or(anyhow::anyhow!("{FALLBACK} muxer not linked"))?,
+        };
+        let name = unsafe { probe::cstr((*muxer).name) };
+        check_codec(muxer, &name, encoder)?;
+        let unknown = probe::unknown_muxer_options(muxer, options.keys());
+        if !unknown.is_empty() {
+            anyhow::bail!("container {name} has no option {}", unknown.join(", "));
+        }
+        let mut all_options = BTreeMap::new();
+        if MOV_FAMILY.contains(&name.as_str()) {
+            let flags = if recoverable {
+                FRAGMENTED
+            } else {
+                "+faststart"
+            };
+            all_options.insert("movflags".to_owned(), flags.to_owned());
+        }
+        all_options.extend(options.clone());
+        Ok(Self {
+            name,
+            options: all_options,
+        })
+    }
+
+    /// Fails if streams of `encoder` cannot be put into this container.
+    pub(crate) fn check_codec(&self, encoder: &str) -> anyhow::Result<()> {
+        let muxer =
+            muxer(&self.name).ok_or(anyhow::anyhow!("container {} not found", self.name))?;
+        check_codec(muxer, &self.name, encoder)
+    }
+
+    pub(crate) fn output_options(&self) -> anyhow::Result<OutputOptions> {
+        Ok(OutputOptions::builder().format_name(&self.name)?.build())
+    }
+
+    pub(crate) fn open(&self, path: &Path, writer: File) -> anyhow::Result<Output<File>> {
+        let mut output = Output::seekable(writer, self.output_options()?)?;
+        // faststart reopens the finished file by name to move the index, which
+        // is impossible through the writer alone.
+        if let Some(url) = path.to_str().and_then(|p| CString::new(p).ok()) {
+            unsafe {
+                let ctx = output.as_mut_ptr();
+                ffi::av_free((*ctx).url.cast());
+                (*ctx).url = ffi::av_strdup(url.as_ptr());
+            }
+        }
+        Ok(output)
+    }
+
+    pub(crate) fn write_header<T: Send + Sync>(
+        &self,
+        output: &mut Output<T>,
+    ) -> anyhow::Result<()> {
+        let mut dict =
+            Dictionary::try_from_iter(self.options.iter().map(|(k, v)| (k.as_str(), v.as_str())))?;
+        output.write_header_with_options(&mut dict)?;
+        Ok(())
+    }
+}
+
+fn check_codec(muxer: *const ffi::AVOutputFormat, name: &str, encoder: &str) -> anyhow::Result<()> {
+    if let Some(codec) = codec_id(encoder)
+        && unsafe { ffi::avformat_query_codec(muxer, codec, ffi::FF_COMPLIANCE_NORMAL as _) } == 0
+    {
+        anyhow::bail!("container {name} cannot hold {encoder} streams");
+    }
+    Ok(())
+}
+
+fn muxer(name: &str) -> Option<*const ffi::AVOutputFormat> {
+    let name = CString::new(name).ok()?;
+    let muxer = unsafe { ffi::av_guess_format(name.as_ptr(), ptr::null(), ptr::null()) };
+    (!muxer.is_null()).then_some(muxer)
+}
+
+fn muxer_for_file(path: &Path) -> Option<*const ffi::AVOutputFormat> {
+    let file_name = CString::new(path.file_name()?.to_str()?).ok()?;
+    let muxer = unsafe { ffi::av_guess_format(ptr::null(), file_name.as_ptr(), ptr::null()) };
+    (!muxer.is_null()).then_some(muxer)
+}
+
+fn codec_id(encoder: &str) -> Option<ffi::AVCodecID> {
+    let name = CString::new(encoder).ok()?;
+    let codec = unsafe { ffi::avcodec_find_encoder_by_name(name.as_ptr()) };
+    (!codec.is_null()).then(|| unsafe { (*codec).id })
+}
+
+fn raw_muxer(codec: ffi::AVCodecID) -> anyhow::Result<*const ffi::AVOutputFormat> {
+    let codec_name = unsafe { probe::cstr(ffi::avcodec_get_name(codec)) };
+    let name = RAW_MUXERS
+        .iter()
+        .find(|(c, _)| *c == codec_name)
+        .map(|(_, m)| *m)
+        .or_else(|| codec_name.strip_prefix("pcm_"))
+        .unwrap_or(&codec_name);
+    muxer(name).ok_or(anyhow::anyhow!("no raw stream muxer for {codec_name}"))
+}
@@ -0,0 +1,108 @@
+use std::{
+    fs::File,
+    io::{
+        BufWriter,
+        Write,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::{
+        LazyLock,
+        atomic::AtomicU32,
+    },
+};
+
+use dashmap::DashMap;
+
+use crate::output::sink::{
+    self,
+    AudioSink,
+    CapturedFrame,
+    VideoSink,
+};
+
+/// Outputs created so far by configured path, so that every dump numbers its
+/// own files.
+static COUNTERS: LazyLock<DashMap<PathBuf, AtomicU32>> = LazyLock::new(DashMap::new);
+
+fn create(path: &Path) -> anyhow::Result<BufWriter<File>> {
+    let counter = COUNTERS.entry(path.to_owned()).or_default();
+    let path = sink::numbered_path(path, &counter)?;
+    drop(counter);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    log::trace!("Raw output: \n{path:?}");
+    Ok(BufWriter::new(File::create(path)?))
+}
+
+/// Captured frames as packed RGB, without any header. The size and pixel
+/// format are logged whenever they change, they are needed to read the file
+/// back.
+pub(crate) struct RawVideo {
+    file: BufWriter<File>,
+    format: Option<(usize, usize, &'static str)>,
+    frames: u64,
+}
+
+impl RawVideo {
+    pub(crate) fn create(path: &Path) -> anyhow::Result<Self> {
+        Ok(Self {
+            file: create(path)?,
+            format: None,
+            frames: 0,
+        })
+    }
+}
+
+impl VideoSink for RawVideo {
+    fn push(&mut self, _tick: i64, frame: &CapturedFrame) -> anyhow::Result<()> {
+        let format = (frame.width, frame.height, frame.format_name());
+        if self.format != Some(format) {
+            log::info!(
+                "Raw video is {}x{} {} from frame {}",
+                format.0,
+                format.1,
+                format.2,
+                self.frames
+            );
+            self.format = Some(format);
+        }
+        self.file.write_all(&frame.pixels)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> anyhow::Result<()> {
+        self.file.flush()?;
+        log::trace!("Raw video frames {}", self.frames);
+        Ok(())
+    }
+}
+
+/// Rendered audio as it is, interleaved stereo `f32le` at 48 kHz.
+pub(crate) struct RawAudio {
+    file: BufWriter<File>,
+}
+
+impl RawAudio {
+    pub(crate) fn create(path: &Path) -> anyhow::Result<Self> {
+        Ok(Self {
+            file: create(path)?,
+        })
+    }
+}
+
+impl AudioSink for RawAudio {
+    fn push(&mut self, samples: &[u8]) -> anyhow::Result<()> {
+        self.file.write_all(samples)?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> anyhow::Result<()> {
+        self.file.flush()?;
+        Ok(())
+    }
+}
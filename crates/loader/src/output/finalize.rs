@@ -0,0 +1,92 @@
+use std::{
+    sync::atomic::{
+        AtomicBool,
+        Ordering,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use parking_lot::{
+    Condvar,
+    Mutex,
+};
+
+/// How long an exiting game waits for encoders to write their trailers.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often an idle encoder thread looks for a shutdown request.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+/// Encoder threads whose output is not finished yet.
+static ACTIVE: Mutex<usize> = Mutex::new(0);
+static FINISHED: Condvar = Condvar::new();
+
+/// Held by an encoder thread until its output is complete.
+pub(crate) struct Active(());
+
+impl Active {
+    pub(crate) fn register() -> Self {
+        *ACTIVE.lock() += 1;
+        Self(())
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        *ACTIVE.lock() -= 1;
+        FINISHED.notify_all();
+    }
+}
+
+pub(crate) fn shutting_down() -> bool {
+    SHUTDOWN.load(Ordering::Acquire)
+}
+
+/// Receives the next buffer, or `None` once the hook dropped its end or a
+/// shutdown was requested and nothing is queued anymore.
+pub(crate) fn recv<T>(rx: &kanal::Receiver<T>) -> Option<T> {
+    loop {
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(buf) => return Some(buf),
+            Err(kanal::ReceiveErrorTimeout::Timeout) if !shutting_down() => continue,
+            Err(_) => return None,
+        }
+    }
+}
+
+/// Makes every encoder thread encode what is queued and finish its output,
+/// without waiting for them.
+pub(crate) fn request_shutdown() {
+    if !SHUTDOWN.swap(true, Ordering::AcqRel) {
+        log::info!("Finalizing outputs");
+    }
+}
+
+/// Like [`request_shutdown`], waiting for the encoder threads for a bounded
+/// time. Returns whether all of them finished.
+pub(crate) fn finalize_all() -> bool {
+    request_shutdown();
+    let deadline = Instant::now() + TIMEOUT;
+    let mut active = ACTIVE.lock();
+    while *active > 0 {
+        if FINISHED.wait_until(&mut active, deadline).timed_out() {
+            log::warn!("{} outputs not finalized within {TIMEOUT:?}", *active);
+            return false;
+        }
+    }
+    true
+}
+
+/// Logs outputs that were never finished, for when the encoder threads are
+/// gone already.
+pub(crate) fn report_unfinished() {
+    let active = *ACTIVE.lock();
+    if active > 0 {
+        log::warn!("{active} outputs left without trailer");
+    }
+}
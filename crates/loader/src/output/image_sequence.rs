@@ -0,0 +1,288 @@
+use std::{
+    fs,
+    num::NonZero,
+    ptr,
+    slice,
+    sync::LazyLock,
+};
+
+use recordin_common::{
+    color::{
+        self,
+        Depth,
+        Transfer,
+    },
+    config::ImagesConfig,
+    geometry::Size,
+    images::{
+        self,
+        ImageFormat,
+    },
+};
+use scuffle_ffmpeg::{
+    AVPixelFormat,
+    ffi,
+    frame::VideoFrame,
+    rational::Rational,
+};
+
+use crate::output::sink::{
+    CapturedFrame,
+    VideoSink,
+};
+
+/// sRGB to linear light, as OpenEXR expects, for every 8-bit value.
+static LINEAR: LazyLock<[f32; 256]> =
+    LazyLock::new(|| std::array::from_fn(|i| color::srgb_to_linear(i as f32 / 255.)));
+
+/// The same for every 16-bit value.
+static LINEAR_16: LazyLock<Box<[f32]>> = LazyLock::new(|| {
+    (0..=u16::MAX)
+        .map(|v| color::srgb_to_linear(v as f32 / 65535.))
+        .collect()
+});
+
+/// HDR10 to linear light with SDR white at 1.0, for every 16-bit value. The
+/// primaries stay BT.2020.
+static PQ_LINEAR_16: LazyLock<Box<[f32]>> = LazyLock::new(|| {
+    (0..=u16::MAX)
+        .map(|v| color::pq_to_linear(v as f32 / 65535.) * 10000. / 203.)
+        .collect()
+});
+
+/// Writes selected frames as numbered image files.
+pub(crate) struct ImageSequence {
+    config: &'static ImagesConfig,
+    /// Encoder for the current frame size, unused for raw images.
+    codec: Option<ImageCodec>,
+    written: u64,
+}
+
+impl ImageSequence {
+    pub(crate) fn new(config: &'static ImagesConfig) -> Self {
+        log::trace!(
+            "Image sequence: {:?} as {}",
+            config.output,
+            config.format.as_str()
+        );
+        Self {
+            config,
+            codec: None,
+            written: 0,
+        }
+    }
+
+    fn wanted(&self, frame: u64) -> bool {
+        let ImagesConfig { frames, every, .. } = self.config;
+        let first = frames.and_then(|r| r.first).unwrap_or(0);
+        frames.is_none_or(|r| r.contains(frame))
+            && every.is_none_or(|n| (frame - first) % n.get() == 0)
+    }
+}
+
+impl VideoSink for ImageSequence {
+    /// Writes `frame` if it is selected, named by virtual frame `tick`.
+    fn push(&mut self, tick: i64, frame: &CapturedFrame) -> anyhow::Result<()> {
+        let Ok(number) = u64::try_from(tick) else {
+            return Ok(());
+        };
+        if !self.wanted(number) {
+            return Ok(());
+        }
+        let path = images::frame_path(&self.config.output, number);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        if self.config.format == ImageFormat::Raw {
+            fs::write(&path, &frame.pixels)?;
+        } else {
+            if self.codec.as_ref().is_none_or(|c| !c.fits(frame)) {
+                self.codec = Some(ImageCodec::new(self.config.format, frame)?);
+            }
+            let codec = self.codec.as_mut().unwrap();
+            codec.load(frame);
+            codec.encode(|data| fs::write(&path, data))??;
+        }
+        self.written += 1;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> anyhow::Result<()> {
+        log::trace!("Images written {}", self.written);
+        Ok(())
+    }
+}
+
+/// A libavcodec image encoder with a frame in its pixel format.
+struct ImageCodec {
+    size: Size,
+    depth: Depth,
+    transfer: Transfer,
+    format: ImageFormat,
+    ctx: *mut ffi::AVCodecContext,
+    packet: *mut ffi::AVPacket,
+    frame: VideoFrame,
+}
+
+/// Codec and pixel format each image format is written with, from captures
+/// of the given depth.
+fn codec_of(format: ImageFormat, depth: Depth) -> (ffi::AVCodecID, ffi::AVPixelFormat) {
+    match format {
+        ImageFormat::Png if depth == Depth::Sixteen => {
+            (ffi::AV_CODEC_ID_PNG, ffi::AV_PIX_FMT_RGB48BE)
+        }
+        ImageFormat::Png => (ffi::AV_CODEC_ID_PNG, ffi::AV_PIX_FMT_RGB24),
+        ImageFormat::Exr => (ffi::AV_CODEC_ID_EXR, ffi::AV_PIX_FMT_GBRPF32LE),
+        ImageFormat::Tga => (ffi::AV_CODEC_ID_TARGA, ffi::AV_PIX_FMT_BGR24),
+        ImageFormat::Raw => (ffi::AV_CODEC_ID_RAWVIDEO, ffi::AV_PIX_FMT_RGB24),
+    }
+}
+
+/// Fails if the encoder for `format` is not linked.
+pub(super) fn check(format: ImageFormat) -> anyhow::Result<()> {
+    let (id, _) = codec_of(format, Depth::Eight);
+    if unsafe { ffi::avcodec_find_encoder(id) }.is_null() {
+        anyhow::bail!("no {} encoder linked", format.as_str());
+    }
+    Ok(())
+}
+
+impl ImageCodec {
+    fn new(format: ImageFormat, captured: &CapturedFrame) -> anyhow::Result<Self> {
+        check(format)?;
+        let size = Size::new(captured.width as _, captured.height as _);
+        let (id, pix_fmt) = codec_of(format, captured.depth);
+        let frame = VideoFrame::builder()
+            .width(size.width as _)
+            .height(size.height as _)
+            .pix_fmt(AVPixelFormat::from(pix_fmt))
+            .time_base(Rational::new(1, NonZero::new(1).unwrap()))
+            .build()?;
+        unsafe {
+            let codec = ffi::avcodec_find_encoder(id);
+            let mut ctx = ffi::avcodec_alloc_context3(codec);
+            if ctx.is_null() {
+                anyhow::bail!("unable to allocate {} encoder", format.as_str());
+            }
+            (*ctx).width = size.width as _;
+            (*ctx).height = size.height as _;
+            (*ctx).pix_fmt = pix_fmt;
+            (*ctx).time_base = ffi::AVRational { num: 1, den: 1 };
+            if captured.transfer == Transfer::Pq {
+                // Lets PNG tag the image as HDR10.
+                (*ctx).color_primaries = ffi::AVCOL_PRI_BT2020;
+                (*ctx).color_trc = ffi::AVCOL_TRC_SMPTE2084;
+            }
+            let ret = ffi::avcodec_open2(ctx, codec, ptr::null_mut());
+            if ret < 0 {
+                ffi::avcodec_free_context(&mut ctx);
+                anyhow::bail!("unable to open {} encoder (error {ret})", format.as_str());
+            }
+            let packet = ffi::av_packet_alloc();
+            if packet.is_null() {
+                ffi::avcodec_free_context(&mut ctx);
+                anyhow::bail!("unable to allocate {} packet", format.as_str());
+            }
+            Ok(Self {
+                size,
+                depth: captured.depth,
+                transfer: captured.transfer,
+                format,
+                ctx,
+                packet,
+                frame,
+            })
+        }
+    }
+
+    /// Whether frames like `captured` can be loaded.
+    fn fits(&self, captured: &CapturedFrame) -> bool {
+        self.size == Size::new(captured.width as _, captured.height as _)
+            && self.depth == captured.depth
+            && self.transfer == captured.transfer
+    }
+
+    /// Copies the packed RGB of `captured` into the frame.
+    fn load(&mut self, captured: &CapturedFrame) {
+        let pixel = captured.depth.bytes_per_pixel();
+        match self.format {
+            ImageFormat::Png | ImageFormat::Raw => {
+                let mut data = self.frame.data_mut(0).unwrap();
+                for (h, row) in captured.rows().enumerate() {
+                    let line = data.get_row_mut(h).unwrap();
+                    line.copy_from_slice(row);
+                    if captured.depth == Depth::Sixteen {
+                        // to big-endian
+                        let (line, _) = line.as_chunks_mut::<2>();
+                        line.iter_mut().for_each(|v| v.reverse());
+                    }
+                }
+            }
+            ImageFormat::Tga => {
+                // the high byte of 16-bit channels
+                let [r, g, b] = match captured.depth {
+                    Depth::Eight => [0, 1, 2],
+                    Depth::Sixteen => [1, 3, 5],
+                };
+                let mut data = self.frame.data_mut(0).unwrap();
+                for (h, row) in captured.rows().enumerate() {
+                    let line = data.get_row_mut(h).unwrap();
+                    for (out, px) in line.chunks_exact_mut(3).zip(row.chunks_exact(pixel)) {
+                        out.copy_from_slice(&[px[b], px[g], px[r]]);
+                    }
+                }
+            }
+            ImageFormat::Exr => {
+                let table = match (captured.depth, captured.transfer) {
+                    (Depth::Eight, _) => &LINEAR[..],
+                    (Depth::Sixteen, Transfer::Pq) => &PQ_LINEAR_16[..],
+                    (Depth::Sixteen, _) => &LINEAR_16[..],
+                };
+                // planes are in G, B, R order
+                for (plane, channel) in [1, 2, 0].into_iter().enumerate() {
+                    let mut data = self.frame.data_mut(plane).unwrap();
+                    for (h, row) in captured.rows().enumerate() {
+                        let line = data.get_row_mut(h).unwrap();
+                        for (out, px) in line.chunks_exact_mut(4).zip(row.chunks_exact(pixel)) {
+                            let v = match captured.depth {
+                                Depth::Eight => px[channel] as usize,
+                                Depth::Sixteen => {
+                                    u16::from_le_bytes([px[channel * 2], px[channel * 2 + 1]])
+                                        as usize
+                                }
+                            };
+                            out.copy_from_slice(&table[v].to_le_bytes());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Encodes the frame and hands the image file contents to `write`.
+    fn encode<R>(&mut self, write: impl FnOnce(&[u8]) -> R) -> anyhow::Result<R> {
+        unsafe {
+            let ret = ffi::avcodec_send_frame(self.ctx, self.frame.as_ptr());
+            if ret < 0 {
+                anyhow::bail!("{} encoding failed (error {ret})", self.format.as_str());
+            }
+            let ret = ffi::avcodec_receive_packet(self.ctx, self.packet);
+            if ret < 0 {
+                anyhow::bail!("{} encoding failed (error {ret})", self.format.as_str());
+            }
+            let p = &*self.packet;
+            let result = write(slice::from_raw_parts(p.data, p.size as _));
+            ffi::av_packet_unref(self.packet);
+            Ok(result)
+        }
+    }
+}
+
+impl Drop for ImageCodec {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::av_packet_free(&mut self.packet);
+            ffi::avcodec_free_context(&mut self.ctx);
+        }
+    }
+}
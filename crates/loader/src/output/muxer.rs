@@ -0,0 +1,241 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    sync::{
+        Arc,
+        atomic::{
+            AtomicI64,
+            AtomicU64,
+            Ordering,
+        },
+    },
+};
+
+use parking_lot::Mutex;
+use scuffle_ffmpeg::{
+    ffi,
+    io::Output,
+    packet::Packet,
+};
+
+use crate::{
+    env,
+    output::audio_codec::AudioStream,
+};
+
+/// Shared outputs in the order the video thread opened them, waiting for the
+/// audio thread to write their stretch of audio.
+static OUTPUTS: Mutex<Outputs> = Mutex::new(Outputs {
+    files: VecDeque::new(),
+    listeners: 0,
+    failed: false,
+});
+
+struct Outputs {
+    files: VecDeque<Arc<SharedOutput>>,
+    /// Audio outputs that will fill the queued files.
+    listeners: usize,
+    /// The video thread stopped, no output will be queued for the audio.
+    failed: bool,
+}
+
+/// Whether audio goes into the video file rather than a file of its own.
+pub(crate) fn merging() -> bool {
+    env::merge_audio() && env::video().is_some()
+}
+
+/// Notes that the video thread stopped on an error, so that the audio thread
+/// stops too instead of holding its samples for outputs that never come.
+pub(crate) fn video_failed() {
+    OUTPUTS.lock().failed = true;
+}
+
+/// Where an encoder thread writes its packets.
+pub(crate) enum Muxer {
+    Own(Output<File>),
+    Shared(Arc<SharedOutput>),
+}
+
+/// Size of the file so far, including what is still buffered.
+fn tell(output: &mut Output<File>) -> u64 {
+    unsafe { ffi::avio_tell((*output.as_mut_ptr()).pb).max(0) as u64 }
+}
+
+impl Muxer {
+    pub(crate) fn write(&mut self, packet: Packet) -> anyhow::Result<()> {
+        match self {
+            Muxer::Own(output) => output.write_interleaved_packet(packet)?,
+            Muxer::Shared(shared) => {
+                let mut inner = shared.inner.lock();
+                inner.output.write_interleaved_packet(packet)?;
+                shared
+                    .bytes
+                    .store(tell(&mut inner.output), Ordering::Relaxed);
+            }
+        }
+        Ok(())
+    }
+
+    /// Size of the file so far, without waiting for the other stream of a
+    /// shared output.
+    pub(crate) fn bytes_written(&mut self) -> u64 {
+        match self {
+            Muxer::Own(output) => tell(output),
+            Muxer::Shared(shared) => shared.bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Writes the trailer, for a shared output once every stream is done.
+    pub(crate) fn finish(&mut self) -> anyhow::Result<()> {
+        match self {
+            Muxer::Own(output) => output.write_trailer()?,
+            Muxer::Shared(shared) => shared.finish()?,
+        }
+        Ok(())
+    }
+}
+
+/// An output holding both the video and the audio stream, written to by
+/// both encoder threads.
+pub(crate) struct SharedOutput {
+    /// Virtual frame the video stream starts at, where both streams count
+    /// their timestamps from.
+    pub(crate) start_tick: i64,
+    /// Virtual frame after the latest one the video thread wrote.
+    video_tick: AtomicI64,
+    /// Virtual frame after the last one of the video stream, `i64::MAX`
+    /// while the video thread still writes to it.
+    end_tick: AtomicI64,
+    /// Size of the file after the latest packet.
+    bytes: AtomicU64,
+    inner: Mutex<Shared>,
+}
+
+struct Shared {
+    output: Output<File>,
+    /// Streams that have not finished yet. The audio stream counts from the
+    /// start, so that the trailer waits for audio lagging behind.
+    writers: usize,
+    /// Audio stream until an audio output takes it.
+    audio: Option<AudioStream>,
+}
+
+impl SharedOutput {
+    /// Shares an output whose header is written, queueing it for the audio
+    /// thread to [`claim`](Self::claim) `audio`.
+    pub(crate) fn publish(output: Output<File>, audio: AudioStream, start_tick: i64) -> Arc<Self> {
+        let shared = Arc::new(Self {
+            start_tick,
+            video_tick: AtomicI64::new(start_tick),
+            end_tick: AtomicI64::new(i64::MAX),
+            bytes: AtomicU64::new(0),
+            inner: Mutex::new(Shared {
+                output,
+                writers: 2,
+                audio: Some(audio),
+            }),
+        });
+        let mut outputs = OUTPUTS.lock();
+        outputs.files.push_back(shared.clone());
+        outputs.failed = false;
+        shared
+    }
+
+    /// Registers an audio output that will claim the queued outputs.
+    pub(crate) fn listen() {
+        OUTPUTS.lock().listeners += 1;
+    }
+
+    /// Unregisters an audio output. Once none is left, the audio streams of
+    /// outputs the video thread is done with are given up.
+    pub(crate) fn unlisten() -> anyhow::Result<()> {
+        let mut outputs = OUTPUTS.lock();
+        outputs.listeners -= 1;
+        if outputs.listeners > 0 {
+            return Ok(());
+        }
+        let mut result = Ok(());
+        outputs.files.retain(|shared| {
+            if shared.end_tick().is_none() {
+                return true;
+            }
+            if let Err(e) = shared.release_audio() {
+                result = Err(e);
+            }
+            false
+        });
+        result
+    }
+
+    /// Takes the audio stream of the oldest output the audio thread has not
+    /// written to yet. Fails once the video thread stopped.
+    pub(crate) fn claim() -> anyhow::Result<Option<(Arc<Self>, AudioStream)>> {
+        let mut outputs = OUTPUTS.lock();
+        while let Some(shared) = outputs.files.pop_front() {
+            if let Some(stream) = shared.inner.lock().audio.take() {
+                return Ok(Some((shared, stream)));
+            }
+        }
+        if outputs.failed {
+            anyhow::bail!("video output failed");
+        }
+        Ok(None)
+    }
+
+    /// Virtual frame the video stream ended at, once it did.
+    pub(crate) fn end_tick(&self) -> Option<i64> {
+        Some(self.end_tick.load(Ordering::Acquire)).filter(|&t| t != i64::MAX)
+    }
+
+    /// Virtual frame up to which audio may be written: where the video
+    /// stream ended, or as far as it got. Audio beyond waits, as it may
+    /// belong to the next output.
+    pub(crate) fn audio_limit(&self) -> (i64, bool) {
+        match self.end_tick() {
+            Some(end) => (end, true),
+            None => (self.video_tick.load(Ordering::Acquire), false),
+        }
+    }
+
+    /// Notes that the video stream got up to before `tick`.
+    pub(crate) fn video_written(&self, tick: i64) {
+        self.video_tick.store(tick, Ordering::Release);
+    }
+
+    /// Marks the video stream ended before `end_tick`, the audio thread
+    /// stops writing to the output there.
+    pub(crate) fn end_video(&self, end_tick: i64) -> anyhow::Result<()> {
+        self.end_tick.store(end_tick, Ordering::Release);
+        let mut outputs = OUTPUTS.lock();
+        if outputs.listeners == 0 {
+            outputs.files.retain(|s| !std::ptr::eq(&**s, self));
+            drop(outputs);
+            self.release_audio()?;
+        }
+        Ok(())
+    }
+
+    /// Gives up the audio stream if no audio output has taken it.
+    fn release_audio(&self) -> anyhow::Result<()> {
+        let mut shared = self.inner.lock();
+        if shared.audio.take().is_some() {
+            log::debug!("No audio for output from tick {}", self.start_tick);
+            shared.release()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&self) -> anyhow::Result<()> {
+        self.inner.lock().release()
+    }
+}
+
+impl Shared {
+    fn release(&mut self) -> anyhow::Result<()> {
+        self.writers -= 1;
+        if self.writers == 0 {
+            self.output.write_trailer()?;
+        }
+        Ok(())
+    }
+}
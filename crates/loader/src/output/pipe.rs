@@ -0,0 +1,352 @@
+use std::{
+    fs::OpenOptions,
+    io::{
+        self,
+        BufWriter,
+        Write,
+    },
+    mem,
+    net::TcpStream,
+    os::windows::process::CommandExt,
+    path::Path,
+    process::{
+        Child,
+        Command,
+        Stdio,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use recordin_common::{
+    ENV_KEY_CONFIG,
+    ENV_KEY_CONFIG_FILE,
+    color::Depth,
+    config::PipeTarget,
+};
+use windows_sys::Win32::Networking::WinSock::{
+    AF_UNIX,
+    INVALID_SOCKET,
+    SOCK_STREAM,
+    SOCKADDR,
+    SOCKADDR_UN,
+    SOCKET,
+    SOCKET_ERROR,
+    WSADATA,
+    WSAGetLastError,
+    WSAStartup,
+    closesocket,
+    connect,
+    send,
+    socket,
+};
+
+use crate::{
+    env,
+    output::{
+        audio_codec::{
+            self,
+            CHANNELS,
+            INPUT_RATE,
+        },
+        sink::{
+            AudioSink,
+            CapturedFrame,
+            VideoSink,
+        },
+    },
+};
+
+const BUFFER_SIZE: usize = 1 << 20;
+
+/// How long a finished pipe waits for its command to exit, well within the
+/// time an exiting game gives the outputs.
+const EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+const FRAME_MAGIC: [u8; 4] = *b"RCFR";
+
+const CHUNK_MAGIC: [u8; 4] = *b"RCAU";
+
+/// Code of interleaved 32-bit little-endian float samples.
+const FORMAT_F32: [u8; 4] = *b"F32L";
+
+/// V4L2 code of packed 8-bit RGB.
+const FORMAT_RGB24: [u8; 4] = *b"RGB3";
+
+/// Code of packed 16-bit little-endian RGB, which V4L2 has none for.
+const FORMAT_RGB48: [u8; 4] = *b"RG48";
+
+/// Written before every frame of a video pipe, all fields little-endian:
+/// magic `RCFR`, width and height as `u32`, pixel format code `RGB3` or
+/// `RG48`, the virtual frame and the virtual time in nanoseconds as `i64`.
+/// The packed pixels follow.
+struct FrameHeader {
+    width: u32,
+    height: u32,
+    depth: Depth,
+    tick: i64,
+    time_ns: i64,
+}
+
+impl FrameHeader {
+    const LEN: usize = 32;
+
+    fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+        bytes[0..4].copy_from_slice(&FRAME_MAGIC);
+        bytes[4..8].copy_from_slice(&self.width.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.height.to_le_bytes());
+        bytes[12..16].copy_from_slice(match self.depth {
+            Depth::Eight => &FORMAT_RGB24,
+            Depth::Sixteen => &FORMAT_RGB48,
+        });
+        bytes[16..24].copy_from_slice(&self.tick.to_le_bytes());
+        bytes[24..32].copy_from_slice(&self.time_ns.to_le_bytes());
+        bytes
+    }
+}
+
+/// Written before every chunk of an audio pipe, all fields little-endian:
+/// magic `RCAU`, sample format code `F32L`, channels, sample rate and the
+/// number of sample frames as `u32`, the virtual frame and the virtual time
+/// in nanoseconds of the first sample as `i64`. The samples follow.
+struct ChunkHeader {
+    frames: u32,
+    tick: i64,
+    time_ns: i64,
+}
+
+impl ChunkHeader {
+    const LEN: usize = 36;
+
+    fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+        bytes[0..4].copy_from_slice(&CHUNK_MAGIC);
+        bytes[4..8].copy_from_slice(&FORMAT_F32);
+        bytes[8..12].copy_from_slice(&(CHANNELS as u32).to_le_bytes());
+        bytes[12..16].copy_from_slice(&(INPUT_RATE as u32).to_le_bytes());
+        bytes[16..20].copy_from_slice(&self.frames.to_le_bytes());
+        bytes[20..28].copy_from_slice(&self.tick.to_le_bytes());
+        bytes[28..36].copy_from_slice(&self.time_ns.to_le_bytes());
+        bytes
+    }
+}
+
+/// A stream to another process, and that process if it was started for it.
+struct Pipe {
+    writer: BufWriter<Box<dyn Write>>,
+    child: Option<Child>,
+}
+
+impl Pipe {
+    fn open(target: &PipeTarget) -> anyhow::Result<Self> {
+        log::trace!("Pipe output: {target}");
+        let (writer, child): (Box<dyn Write>, _) = match target {
+            PipeTarget::Command(command) => {
+                let mut child = spawn(command)?;
+                (Box::new(child.stdin.take().unwrap()), Some(child))
+            }
+            PipeTarget::Tcp(address) => {
+                let stream = TcpStream::connect(address.as_str())?;
+                stream.set_nodelay(true)?;
+                (Box::new(stream), None)
+            }
+            PipeTarget::Unix(path) => (Box::new(UnixStream::connect(path)?), None),
+            PipeTarget::Path(path) => (Box::new(OpenOptions::new().write(true).open(path)?), None),
+        };
+        Ok(Self {
+            writer: BufWriter::with_capacity(BUFFER_SIZE, writer),
+            child,
+        })
+    }
+
+    /// Closes the stream and waits a bounded time for the command reading
+    /// it to exit.
+    fn finish(self) -> anyhow::Result<()> {
+        let Self { mut writer, child } = self;
+        writer.flush()?;
+        drop(writer);
+        let Some(mut child) = child else {
+            return Ok(());
+        };
+        let deadline = Instant::now() + EXIT_TIMEOUT;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                anyhow::bail!("piped command did not exit within {EXIT_TIMEOUT:?}");
+            }
+            std::thread::sleep(EXIT_POLL_INTERVAL);
+        };
+        if !status.success() {
+            anyhow::bail!("piped command exited with {status}");
+        }
+        Ok(())
+    }
+}
+
+/// Starts `command` through the shell, without the recording config so that
+/// it is not recorded itself.
+fn spawn(command: &str) -> anyhow::Result<Child> {
+    Ok(Command::new("cmd")
+        .arg("/C")
+        .raw_arg(command)
+        .env_remove(ENV_KEY_CONFIG)
+        .env_remove(ENV_KEY_CONFIG_FILE)
+        .stdin(Stdio::piped())
+        .spawn()?)
+}
+
+/// Captured frames written to a pipe, each after a [`FrameHeader`] unless
+/// the reader takes bare frames.
+pub(crate) struct VideoPipe {
+    pipe: Pipe,
+    headers: bool,
+    fps: f64,
+    format: Option<(usize, usize, Depth)>,
+}
+
+impl VideoPipe {
+    pub(crate) fn open(target: &PipeTarget, headers: bool) -> anyhow::Result<Self> {
+        Ok(Self {
+            pipe: Pipe::open(target)?,
+            headers,
+            fps: env::FPS.get(),
+            format: None,
+        })
+    }
+}
+
+impl VideoSink for VideoPipe {
+    fn push(&mut self, tick: i64, frame: &CapturedFrame) -> anyhow::Result<()> {
+        let format = (frame.width, frame.height, frame.depth);
+        if self.format.is_some_and(|f| f != format) && !self.headers {
+            anyhow::bail!("capture changed, bare frames cannot change their size or format");
+        }
+        self.format = Some(format);
+        if self.headers {
+            let header = FrameHeader {
+                width: frame.width as _,
+                height: frame.height as _,
+                depth: frame.depth,
+                tick,
+                time_ns: (tick as f64 * 1e9 / self.fps).round() as i64,
+            };
+            self.pipe.writer.write_all(&header.to_bytes())?;
+        }
+        self.pipe.writer.write_all(&frame.pixels)?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> anyhow::Result<()> {
+        self.pipe.finish()
+    }
+}
+
+/// Rendered audio written to a pipe, each chunk after a [`ChunkHeader`].
+pub(crate) struct AudioPipe {
+    pipe: Pipe,
+    fps: f64,
+    /// Sample frames since virtual time zero at the next chunk.
+    position: i64,
+}
+
+impl AudioPipe {
+    pub(crate) fn open(target: &PipeTarget, start_tick: i64) -> anyhow::Result<Self> {
+        let fps = env::FPS.get();
+        Ok(Self {
+            pipe: Pipe::open(target)?,
+            fps,
+            position: audio_codec::at_input_rate(start_tick, fps),
+        })
+    }
+}
+
+impl AudioSink for AudioPipe {
+    fn push(&mut self, samples: &[u8]) -> anyhow::Result<()> {
+        let frames = samples.len() / audio_codec::BYTES_PER_SAMPLE;
+        let header = ChunkHeader {
+            frames: frames as _,
+            tick: (self.position as f64 * self.fps / INPUT_RATE as f64).floor() as i64,
+            time_ns: (self.position as i128 * 1_000_000_000 / INPUT_RATE as i128) as i64,
+        };
+        self.pipe.writer.write_all(&header.to_bytes())?;
+        self.pipe.writer.write_all(samples)?;
+        self.position += frames as i64;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> anyhow::Result<()> {
+        self.pipe.finish()
+    }
+}
+
+/// A connected `AF_UNIX` socket, which the standard library only offers on
+/// Unix.
+struct UnixStream(SOCKET);
+
+impl UnixStream {
+    fn connect(path: &Path) -> io::Result<Self> {
+        let path = path.to_string_lossy();
+        unsafe {
+            let mut addr: SOCKADDR_UN = mem::zeroed();
+            if path.len() >= addr.sun_path.len() {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "socket path too long",
+                ))?
+            }
+            addr.sun_family = AF_UNIX;
+            for (out, b) in addr.sun_path.iter_mut().zip(path.bytes()) {
+                *out = b as _;
+            }
+            let mut data: WSADATA = mem::zeroed();
+            let ret = WSAStartup(0x0202, &mut data);
+            if ret != 0 {
+                Err(io::Error::from_raw_os_error(ret))?
+            }
+            let sock = socket(AF_UNIX as _, SOCK_STREAM, 0);
+            if sock == INVALID_SOCKET {
+                Err(io::Error::from_raw_os_error(WSAGetLastError()))?
+            }
+            let stream = Self(sock);
+            let ret = connect(
+                sock,
+                (&raw const addr).cast::<SOCKADDR>(),
+                mem::size_of::<SOCKADDR_UN>() as _,
+            );
+            if ret == SOCKET_ERROR {
+                Err(io::Error::from_raw_os_error(WSAGetLastError()))?
+            }
+            Ok(stream)
+        }
+    }
+}
+
+impl Write for UnixStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(i32::MAX as usize);
+        let sent = unsafe { send(self.0, buf.as_ptr(), len as _, 0) };
+        if sent == SOCKET_ERROR {
+            return Err(io::Error::from_raw_os_error(unsafe { WSAGetLastError() }));
+        }
+        Ok(sent as _)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for UnixStream {
+    fn drop(&mut self) {
+        unsafe {
+            closesocket(self.0);
+        }
+    }
+}
@@ -0,0 +1,427 @@
+use std::{
+    collections::BTreeMap,
+    ffi::{
+        CStr,
+        CString,
+        c_void,
+    },
+    io,
+    ptr,
+    slice,
+};
+
+use recordin_common::{
+    abi,
+    abi::{
+        EncoderInfo,
+        Inventory,
+        MediaKind,
+        MuxerInfo,
+        OptionInfo,
+    },
+    config::RecordinConfig,
+    geometry::{
+        Layout,
+        Size,
+    },
+};
+use scuffle_ffmpeg::{
+    ffi,
+    io::Output,
+};
+
+use crate::output::{
+    audio_codec,
+    container::Container,
+    image_sequence,
+    scaler::ColorFormat,
+    video_codec,
+};
+
+/// Names of all encoders of `media_type` linked into this build.
+pub(crate) fn encoder_names(media_type: ffi::AVMediaType) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut opaque = ptr::null_mut();
+    unsafe {
+        loop {
+            let codec = ffi::av_codec_iterate(&mut opaque);
+            if codec.is_null() {
+                break;
+            }
+            if ffi::av_codec_is_encoder(codec) != 0 && (*codec).type_ == media_type {
+                names.push(cstr((*codec).name));
+            }
+        }
+    }
+    names
+}
+
+/// Pixel formats `encoder` accepts, or `None` if it does not say.
+pub(crate) fn pixel_formats(encoder: &str) -> Option<Vec<String>> {
+    let name = CString::new(encoder).ok()?;
+    let mut formats = Vec::new();
+    unsafe {
+        let codec = ffi::avcodec_find_encoder_by_name(name.as_ptr());
+        if codec.is_null() || (*codec).pix_fmts.is_null() {
+            return None;
+        }
+        let mut p = (*codec).pix_fmts;
+        while *p != ffi::AV_PIX_FMT_NONE {
+            formats.push(cstr(ffi::av_get_pix_fmt_name(*p)));
+            p = p.add(1);
+        }
+    }
+    Some(formats)
+}
+
+/// Sample formats `encoder` accepts, or `None` if it does not say.
+pub(crate) fn sample_formats(encoder: &str) -> Option<Vec<ffi::AVSampleFormat>> {
+    let name = CString::new(encoder).ok()?;
+    let mut formats = Vec::new();
+    unsafe {
+        let codec = ffi::avcodec_find_encoder_by_name(name.as_ptr());
+        if codec.is_null() || (*codec).sample_fmts.is_null() {
+            return None;
+        }
+        let mut p = (*codec).sample_fmts;
+        while *p != ffi::AV_SAMPLE_FMT_NONE {
+            formats.push(*p);
+            p = p.add(1);
+        }
+    }
+    Some(formats)
+}
+
+/// Sample rates `encoder` accepts, or `None` if it takes any.
+pub(crate) fn sample_rates(encoder: &str) -> Option<Vec<i32>> {
+    let name = CString::new(encoder).ok()?;
+    let mut rates = Vec::new();
+    unsafe {
+        let codec = ffi::avcodec_find_encoder_by_name(name.as_ptr());
+        if codec.is_null() || (*codec).supported_samplerates.is_null() {
+            return None;
+        }
+        let mut p = (*codec).supported_samplerates;
+        while *p != 0 {
+            rates.push(*p);
+            p = p.add(1);
+        }
+    }
+    Some(rates)
+}
+
+/// Samples per frame `encoder` wants with these settings, or `None` if it
+/// takes frames of any size. Opens a throwaway codec context, since some
+/// encoders only decide once opened.
+pub(crate) fn audio_frame_size(
+    encoder: &str,
+    sample_fmt: ffi::AVSampleFormat,
+    sample_rate: i32,
+    channels: i32,
+    options: &BTreeMap<String, String>,
+) -> anyhow::Result<Option<usize>> {
+    let name = CString::new(encoder)?;
+    unsafe {
+        let codec = ffi::avcodec_find_encoder_by_name(name.as_ptr());
+        if codec.is_null() {
+            anyhow::bail!("encoder {encoder} not found");
+        }
+        if (*codec).capabilities & ffi::AV_CODEC_CAP_VARIABLE_FRAME_SIZE as i32 != 0 {
+            return Ok(None);
+        }
+        let mut ctx = ffi::avcodec_alloc_context3(codec);
+        if ctx.is_null() {
+            anyhow::bail!("unable to allocate context for encoder {encoder}");
+        }
+        (*ctx).sample_fmt = sample_fmt;
+        (*ctx).sample_rate = sample_rate;
+        (*ctx).time_base = ffi::AVRational {
+            num: 1,
+            den: sample_rate,
+        };
+        ffi::av_channel_layout_default(&mut (*ctx).ch_layout, channels);
+        let mut dict = ptr::null_mut();
+        for (k, v) in options {
+            let (Ok(k), Ok(v)) = (CString::new(k.as_str()), CString::new(v.as_str())) else {
+                continue;
+            };
+            ffi::av_dict_set(&mut dict, k.as_ptr(), v.as_ptr(), 0);
+        }
+        let ret = ffi::avcodec_open2(ctx, codec, &mut dict);
+        let frame_size = (*ctx).frame_size;
+        ffi::av_dict_free(&mut dict);
+        ffi::avcodec_free_context(&mut ctx);
+        if ret < 0 {
+            anyhow::bail!("unable to open encoder {encoder} (error {ret})");
+        }
+        Ok((frame_size > 0).then_some(frame_size as usize))
+    }
+}
+
+/// Keys that are neither generic codec options nor private options of
+/// `encoder`, which ffmpeg would otherwise silently ignore.
+pub(crate) fn unknown_options<'a>(
+    encoder: &str,
+    keys: impl IntoIterator<Item = &'a String>,
+) -> Vec<&'a str> {
+    let Ok(name) = CString::new(encoder) else {
+        return Vec::new();
+    };
+    unsafe {
+        let codec = ffi::avcodec_find_encoder_by_name(name.as_ptr());
+        if codec.is_null() {
+            return Vec::new();
+        }
+        unknown_keys(&[ffi::avcodec_get_class(), (*codec).priv_class], keys)
+    }
+}
+
+/// Like [`unknown_options`], for the generic and private options of a muxer.
+pub(crate) fn unknown_muxer_options<'a>(
+    muxer: *const ffi::AVOutputFormat,
+    keys: impl IntoIterator<Item = &'a String>,
+) -> Vec<&'a str> {
+    unsafe { unknown_keys(&[ffi::avformat_get_class(), (*muxer).priv_class], keys) }
+}
+
+/// Names of all muxers linked into this build.
+pub(crate) fn muxer_names() -> Vec<String> {
+    let mut names = Vec::new();
+    let mut opaque = ptr::null_mut();
+    unsafe {
+        loop {
+            let muxer = ffi::av_muxer_iterate(&mut opaque);
+            if muxer.is_null() {
+                break;
+            }
+            names.push(cstr((*muxer).name));
+        }
+    }
+    names
+}
+
+unsafe fn unknown_keys<'a>(
+    classes: &[*const ffi::AVClass],
+    keys: impl IntoIterator<Item = &'a String>,
+) -> Vec<&'a str> {
+    keys.into_iter()
+        .filter(|k| {
+            let Ok(k) = CString::new(k.as_str()) else {
+                return true;
+            };
+            !classes
+                .iter()
+                .any(|class| !class.is_null() && unsafe { has_option(class, &k) })
+        })
+        .map(String::as_str)
+        .collect()
+}
+
+fn inventory() -> Inventory {
+    let mut encoders = Vec::new();
+    let mut opaque = ptr::null_mut();
+    unsafe {
+        loop {
+            let codec = ffi::av_codec_iterate(&mut opaque);
+            if codec.is_null() {
+                break;
+            }
+            if ffi::av_codec_is_encoder(codec) == 0 {
+                continue;
+            }
+            let c = &*codec;
+            let kind = match c.type_ {
+                ffi::AVMEDIA_TYPE_VIDEO => MediaKind::Video,
+                ffi::AVMEDIA_TYPE_AUDIO => MediaKind::Audio,
+                _ => continue,
+            };
+            let name = cstr(c.name);
+            let pixel_formats = pixel_formats(&name).unwrap_or_default();
+            let mut sample_formats = Vec::new();
+            let mut p = c.sample_fmts;
+            while !p.is_null() && *p != ffi::AV_SAMPLE_FMT_NONE {
+                sample_formats.push(cstr(ffi::av_get_sample_fmt_name(*p)));
+                p = p.add(1);
+            }
+            encoders.push(EncoderInfo {
+                long_name: cstr(c.long_name),
+                name,
+                kind,
+                pixel_formats,
+                sample_formats,
+                options: private_options(c.priv_class),
+            });
+        }
+    }
+    let mut muxers = Vec::new();
+    let mut opaque = ptr::null_mut();
+    unsafe {
+        loop {
+            let muxer = ffi::av_muxer_iterate(&mut opaque);
+            if muxer.is_null() {
+                break;
+            }
+            let m = &*muxer;
+            let extensions = cstr(m.extensions);
+            muxers.push(MuxerInfo {
+                name: cstr(m.name),
+                long_name: cstr(m.long_name),
+                extensions: extensions
+                    .split(',')
+                    .filter(|e| !e.is_empty())
+                    .map(str::to_owned)
+                    .collect(),
+            });
+        }
+    }
+    Inventory { encoders, muxers }
+}
+
+unsafe fn private_options(class: *const ffi::AVClass) -> Vec<OptionInfo> {
+    if class.is_null() {
+        return Vec::new();
+    }
+    let mut options: Vec<(OptionInfo, String)> = Vec::new();
+    let mut constants: Vec<(String, String)> = Vec::new();
+    let obj = ptr::from_ref(&class).cast_mut().cast();
+    let mut opt = ptr::null();
+    unsafe {
+        loop {
+            opt = ffi::av_opt_next(obj, opt);
+            if opt.is_null() {
+                break;
+            }
+            let o = &*opt;
+            if o.type_ == ffi::AV_OPT_TYPE_CONST {
+                constants.push((cstr(o.unit), cstr(o.name)));
+            } else {
+                let info = OptionInfo {
+                    name: cstr(o.name),
+                    help: cstr(o.help),
+                    values: Vec::new(),
+                };
+                options.push((info, cstr(o.unit)));
+            }
+        }
+    }
+    for (info, unit) in &mut options {
+        if !unit.is_empty() {
+            info.values = constants
+                .iter()
+                .filter(|(u, _)| u == unit)
+                .map(|(_, name)| name.clone())
+                .collect();
+        }
+    }
+    options.into_iter().map(|(info, _)| info).collect()
+}
+
+/// Copies a C string from FFmpeg, empty for null.
+///
+/// `p` must be null or point to a NUL-terminated string that stays valid
+/// during the call.
+pub(super) unsafe fn cstr(p: *const core::ffi::c_char) -> String {
+    if p.is_null() {
+        String::new()
+    } else {
+        unsafe { CStr::from_ptr(p) }.to_string_lossy().into_owned()
+    }
+}
+
+unsafe fn has_option(class: &*const ffi::AVClass, name: &CStr) -> bool {
+    unsafe {
+        !ffi::av_opt_find(
+            ptr::from_ref(class).cast_mut().cast(),
+            name.as_ptr(),
+            ptr::null(),
+            0,
+            ffi::AV_OPT_SEARCH_FAKE_OBJ as _,
+        )
+        .is_null()
+    }
+}
+
+/// Opens every encoder and container the config asks for without writing
+/// anything, using the same code path as the encoder threads.
+fn probe(config: &RecordinConfig) -> anyhow::Result<()> {
+    if let Some(video) = &config.video {
+        let container = Container::resolve(
+            &video.output,
+            video.container.as_deref(),
+            &video.container_options,
+            &video.encoder,
+            config.recoverable,
+        )
+        .map_err(|e| anyhow::anyhow!("Video: {e}"))?;
+        let mut output = Output::new(io::sink(), container.output_options()?)?;
+        let layout = Layout::new(Size::new(1280, 720), video.crop, video.scale, video.fit)
+            .expect("probe frame is not empty");
+        ColorFormat::resolve(video)
+            .and_then(|color| {
+                video_codec::new_encoder(&mut output, video, &color, layout.output, config.fps)
+            })
+            .map_err(|e| anyhow::anyhow!("Video: {e}"))?;
+        if config.merge_audio
+            && let Some(audio) = &config.audio
+        {
+            container
+                .check_codec(&audio.encoder)
+                .and_then(|()| audio_codec::new_encoder(&mut output, audio))
+                .map_err(|e| anyhow::anyhow!("Merged audio: {e}"))?;
+        }
+    }
+    if let Some(images) = &config.images {
+        image_sequence::check(images.format).map_err(|e| anyhow::anyhow!("Images: {e}"))?;
+    }
+    if let Some(audio) = &config.audio
+        && let Some(path) = &audio.output
+    {
+        let container = Container::resolve(
+            path,
+            audio.container.as_deref(),
+            &audio.container_options,
+            &audio.encoder,
+            config.recoverable,
+        )
+        .map_err(|e| anyhow::anyhow!("Audio: {e}"))?;
+        let mut output = Output::new(io::sink(), container.output_options()?)?;
+        audio_codec::new_encoder(&mut output, audio).map_err(|e| anyhow::anyhow!("Audio: {e}"))?;
+    }
+    Ok(())
+}
+
+#[unsafe(no_mangle)]
+unsafe extern "C" fn recordin_probe(
+    config: *const u8,
+    config_len: usize,
+    write: abi::WriteFn,
+    ctx: *mut c_void,
+) -> bool {
+    let config = unsafe { slice::from_raw_parts(config, config_len) };
+    let result = str::from_utf8(config)
+        .map_err(anyhow::Error::from)
+        .and_then(|s| Ok(RecordinConfig::decode(s)?))
+        .and_then(|c| probe(&c));
+    match result {
+        Ok(()) => true,
+        Err(e) => {
+            let msg = e.to_string();
+            unsafe {
+                write(ctx, msg.as_ptr(), msg.len());
+            }
+            false
+        }
+    }
+}
+
+const _: abi::ProbeFn = recordin_probe;
+
+#[unsafe(no_mangle)]
+unsafe extern "C" fn recordin_inventory(write: abi::WriteFn, ctx: *mut c_void) {
+    match inventory().encode() {
+        Ok(s) => unsafe { write(ctx, s.as_ptr(), s.len()) },
+        Err(e) => log::warn!("Unable to encode inventory: {e}"),
+    }
+}
+
+const _: abi::InventoryFn = recordin_inventory;
@@ -0,0 +1,295 @@
+use std::{
+    ops::{
+        Deref,
+        DerefMut,
+    },
+    sync::{
+        Arc,
+        atomic::{
+            AtomicBool,
+            AtomicI64,
+            AtomicU64,
+            AtomicUsize,
+            Ordering,
+        },
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use recordin_common::{
+    config::Backpressure,
+    queue::{
+        Shortage,
+        TickTally,
+    },
+};
+
+use crate::output::finalize;
+
+/// A capture buffer, whose size tells how much one more would take.
+pub(crate) trait Buffer: Default {
+    fn bytes(&self) -> usize;
+}
+
+impl Buffer for Vec<u8> {
+    fn bytes(&self) -> usize {
+        self.capacity()
+    }
+}
+
+/// A buffer passed back and forth between a hook and an output thread.
+pub(crate) struct Queued<T> {
+    /// Virtual frame an image was captured at, sequence number of rendered
+    /// audio.
+    pub(crate) tick: i64,
+    submitted: Instant,
+    data: T,
+}
+
+impl<T> Deref for Queued<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.data
+    }
+}
+
+impl<T> DerefMut for Queued<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.data
+    }
+}
+
+impl<T> Queued<T> {
+    fn new(data: T) -> Self {
+        Self {
+            tick: 0,
+            submitted: Instant::now(),
+            data,
+        }
+    }
+}
+
+/// Counted by the hook side, reported by the output thread.
+#[derive(Default)]
+struct Counters {
+    /// Sequence number of the next buffer handed out by [`Queue::acquire`].
+    sequence: AtomicI64,
+    /// Latest tick a buffer was asked for.
+    latest: AtomicI64,
+    dropped: AtomicU64,
+    stalls: AtomicU64,
+    stall_nanos: AtomicU64,
+    buffers: AtomicUsize,
+}
+
+/// The hook side of a queue, handing out free buffers according to the
+/// backpressure policy.
+pub(crate) struct Queue<T> {
+    name: &'static str,
+    policy: Backpressure,
+    full: kanal::Sender<Queued<T>>,
+    free: kanal::Receiver<Queued<T>>,
+    /// Largest buffer submitted so far.
+    buffer_bytes: AtomicUsize,
+    at_cap: AtomicBool,
+    closed: AtomicBool,
+    counters: Arc<Counters>,
+}
+
+/// The output thread side of a queue.
+pub(crate) struct Drain<T> {
+    name: &'static str,
+    full: kanal::Receiver<Queued<T>>,
+    free: kanal::Sender<Queued<T>>,
+    counters: Arc<Counters>,
+    tally: TickTally,
+    max_depth: usize,
+    depth_sum: u64,
+    max_latency: Duration,
+    latency_sum: Duration,
+}
+
+/// Connects a hook to an output thread, with `buffers` to pass between them
+/// and ticks counted from `start_tick`.
+pub(crate) fn queue<T: Buffer>(
+    name: &'static str,
+    policy: Backpressure,
+    start_tick: i64,
+    buffers: impl IntoIterator<Item = T>,
+) -> (Queue<T>, Drain<T>) {
+    // The buffers bound how much either channel can hold.
+    let (full_tx, full_rx) = kanal::unbounded();
+    let (free_tx, free_rx) = kanal::unbounded();
+    let counters = Arc::new(Counters {
+        sequence: AtomicI64::new(start_tick),
+        latest: AtomicI64::new(start_tick - 1),
+        ..Default::default()
+    });
+    let mut buffer_bytes = 0;
+    for buf in buffers {
+        buffer_bytes = buffer_bytes.max(buf.bytes());
+        counters.buffers.fetch_add(1, Ordering::Relaxed);
+        free_tx.send(Queued::new(buf)).unwrap();
+    }
+    let queue = Queue {
+        name,
+        policy,
+        full: full_tx,
+        free: free_rx,
+        buffer_bytes: AtomicUsize::new(buffer_bytes),
+        at_cap: AtomicBool::new(false),
+        closed: AtomicBool::new(false),
+        counters: counters.clone(),
+    };
+    let drain = Drain {
+        name,
+        full: full_rx,
+        free: free_tx,
+        counters,
+        tally: TickTally::new(start_tick),
+        max_depth: 0,
+        depth_sum: 0,
+        max_latency: Duration::ZERO,
+        latency_sum: Duration::ZERO,
+    };
+    (queue, drain)
+}
+
+impl<T: Buffer> Queue<T> {
+    /// A free buffer tagged with the next sequence number, for captures
+    /// that are not tied to a virtual frame.
+    pub(crate) fn acquire(&self) -> Option<Queued<T>> {
+        let tick = self.counters.sequence.fetch_add(1, Ordering::Relaxed);
+        self.acquire_at(tick)
+    }
+
+    /// A free buffer for what is captured at `tick`, or `None` if it is
+    /// dropped or the output thread is gone.
+    pub(crate) fn acquire_at(&self, tick: i64) -> Option<Queued<T>> {
+        self.counters.latest.fetch_max(tick, Ordering::Relaxed);
+        let buf = match self.free.try_recv() {
+            Ok(Some(buf)) => Some(buf),
+            Ok(None) => match self.shortage() {
+                Shortage::Drop => {
+                    self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+                    return None;
+                }
+                Shortage::Grow => {
+                    self.counters.buffers.fetch_add(1, Ordering::Relaxed);
+                    Some(Queued::new(T::default()))
+                }
+                Shortage::Wait => self.wait(),
+                Shortage::Capped(cap) => {
+                    if !self.at_cap.swap(true, Ordering::Relaxed) {
+                        log::warn!("{} buffers reached {cap} bytes, waiting", self.name);
+                    }
+                    self.wait()
+                }
+            },
+            Err(_) => None,
+        };
+        let Some(mut buf) = buf else {
+            self.on_closed(tick);
+            return None;
+        };
+        buf.tick = tick;
+        Some(buf)
+    }
+
+    /// Hands a filled buffer to the output thread.
+    pub(crate) fn submit(&self, mut buf: Queued<T>) {
+        let tick = buf.tick;
+        self.buffer_bytes
+            .fetch_max(buf.data.bytes(), Ordering::Relaxed);
+        buf.submitted = Instant::now();
+        if self.full.send(buf).is_err() {
+            self.on_closed(tick);
+        }
+    }
+
+    fn shortage(&self) -> Shortage {
+        self.policy.on_shortage(
+            self.counters.buffers.load(Ordering::Relaxed),
+            self.buffer_bytes.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Blocks until the output thread frees a buffer.
+    fn wait(&self) -> Option<Queued<T>> {
+        let start = Instant::now();
+        let buf = self.free.recv().ok()?;
+        let c = &self.counters;
+        c.stalls.fetch_add(1, Ordering::Relaxed);
+        c.stall_nanos
+            .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        Some(buf)
+    }
+
+    fn on_closed(&self, tick: i64) {
+        if !self.closed.swap(true, Ordering::Relaxed) {
+            log::warn!(
+                "{} output stopped, nothing from tick {tick} on is recorded",
+                self.name
+            );
+        }
+    }
+}
+
+impl<T> Drain<T> {
+    /// The next filled buffer, see [`finalize::recv`].
+    pub(crate) fn recv(&mut self) -> Option<Queued<T>> {
+        let buf = finalize::recv(&self.full)?;
+        let depth = self.full.len();
+        self.max_depth = self.max_depth.max(depth);
+        self.depth_sum += depth as u64;
+        self.tally.record(buf.tick);
+        Some(buf)
+    }
+
+    /// Hands a buffer back once every sink is done with it.
+    pub(crate) fn release(&mut self, buf: Queued<T>) {
+        let latency = buf.submitted.elapsed();
+        self.max_latency = self.max_latency.max(latency);
+        self.latency_sum += latency;
+        self.free.send(buf).ok();
+    }
+
+    /// Logs what went through the queue and whether any tick is missing.
+    pub(crate) fn report(&self) {
+        let c = &self.counters;
+        let n = self.tally.received.max(1);
+        log::info!(
+            "{} queue: {} received, queue depth mean {:.1} max {}, latency mean {:?} max {:?}, \
+                capture waited {} times for {:?}, {} buffers",
+            self.name,
+            self.tally.received,
+            self.depth_sum as f64 / n as f64,
+            self.max_depth,
+            self.latency_sum / n as u32,
+            self.max_latency,
+            c.stalls.load(Ordering::Relaxed),
+            Duration::from_nanos(c.stall_nanos.load(Ordering::Relaxed)),
+            c.buffers.load(Ordering::Relaxed),
+        );
+        let (first, last) = (self.tally.first(), c.latest.load(Ordering::Relaxed));
+        if last < first {
+            return;
+        }
+        let missing = self.tally.missing_until(last);
+        let duplicated = self.tally.duplicated;
+        let dropped = c.dropped.load(Ordering::Relaxed);
+        if missing == 0 && duplicated == 0 {
+            log::info!("{}: every tick from {first} to {last} recorded", self.name);
+        } else {
+            log::warn!(
+                "{}: ticks {first} to {last}, {missing} missing ({dropped} dropped by \
+                    backpressure), {duplicated} duplicated",
+                self.name,
+            );
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use std::{
+    array,
+    ffi::CString,
+    ptr,
+};
+
+use recordin_common::{
+    color::Transfer,
+    config::{
+        ColorMatrix,
+        ColorRange,
+        VideoConfig,
+    },
+    geometry::{
+        Layout,
+        ScaleAlgorithm,
+    },
+};
+use scuffle_ffmpeg::{
+    AVPixelFormat,
+    ffi,
+    frame::VideoFrame,
+    rational::Rational,
+};
+
+use crate::output::probe;
+
+const DEFAULT_PIXEL_FORMAT: &str = "yuv420p";
+
+/// Short pixel format names accepted on top of ffmpeg's.
+const ALIASES: &[(&str, &str)] = &[("rgb", "rgb24"), ("bgr", "bgr24")];
+
+/// Pixel format and color description of the encoded video.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ColorFormat {
+    pub(crate) pixel_format: AVPixelFormat,
+    rgb: bool,
+    /// Bits of the first component.
+    bits: u32,
+    matrix: ColorMatrix,
+    range: ColorRange,
+    /// Captures are HDR10 rather than sRGB.
+    hdr10: bool,
+}
+
+impl ColorFormat {
+    /// Looks up the configured pixel format, accepting names without the
+    /// endianness suffix like `yuv420p10`, and checks `encoder` takes it.
+    pub(crate) fn resolve(video: &VideoConfig) -> anyhow::Result<Self> {
+        let requested = video
+            .pixel_format
+            .as_deref()
+            .unwrap_or(DEFAULT_PIXEL_FORMAT);
+        let name = ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == requested)
+            .map_or(requested, |(_, name)| *name);
+        let (name, format) = [name.to_owned(), format!("{name}le")]
+            .into_iter()
+            .find_map(|name| {
+                let c = CString::new(name.as_str()).ok()?;
+                let format = unsafe { ffi::av_get_pix_fmt(c.as_ptr()) };
+                (format != ffi::AV_PIX_FMT_NONE).then_some((name, format))
+            })
+            .ok_or(anyhow::anyhow!("unknown pixel format {requested}"))?;
+        if let Some(formats) = probe::pixel_formats(&video.encoder)
+            && !formats.contains(&name)
+        {
+            anyhow::bail!(
+                "encoder {} does not support pixel format {name}, supported: {}",
+                video.encoder,
+                formats.join(", ")
+            );
+        }
+        let desc = unsafe { ffi::av_pix_fmt_desc_get(format) };
+        let rgb =
+            !desc.is_null() && unsafe { (*desc).flags } & ffi::AV_PIX_FMT_FLAG_RGB as u64 != 0;
+        let bits = match desc.is_null() {
+            true => 8,
+            false => unsafe { (*desc).comp[0].depth as u32 },
+        };
+        Ok(Self {
+            pixel_format: AVPixelFormat::from(format),
+            rgb,
+            bits,
+            matrix: video.matrix.unwrap_or(ColorMatrix::Bt709),
+            range: video.range.unwrap_or(if rgb {
+                ColorRange::Full
+            } else {
+                ColorRange::Limited
+            }),
+            hdr10: false,
+        })
+    }
+
+    /// The same format for captures of `transfer`, HDR10 being encoded
+    /// with BT.2020 whatever matrix was asked for.
+    pub(crate) fn for_transfer(self, transfer: Transfer) -> Self {
+        let hdr10 = transfer == Transfer::Pq;
+        if hdr10 && self.bits < 10 {
+            log::warn!("HDR10 kept in an 8-bit pixel format will band, use a 10-bit one");
+        }
+        Self { hdr10, ..self }
+    }
+
+    /// Codec options tagging the stream, so players do not have to guess.
+    /// SDR captures are sRGB, which shares BT.709 primaries.
+    pub(crate) fn tags(&self) -> [(&'static str, &'static str); 4] {
+        let colorspace = match (self.rgb, self.hdr10, self.matrix) {
+            (true, ..) => "rgb",
+            (false, true, _) => "bt2020nc",
+            (false, false, ColorMatrix::Bt601) => "smpte170m",
+            (false, false, ColorMatrix::Bt709) => "bt709",
+        };
+        let (primaries, trc) = match self.hdr10 {
+            true => ("bt2020", "smpte2084"),
+            false => ("bt709", "iec61966-2-1"),
+        };
+        let range = match self.range {
+            ColorRange::Limited => "tv",
+            ColorRange::Full => "pc",
+        };
+        [
+            ("colorspace", colorspace),
+            ("color_range", range),
+            ("color_primaries", primaries),
+            ("color_trc", trc),
+        ]
+    }
+}
+
+/// Converts captured frames to the encoder's pixel format with the chosen
+/// matrix and range, which `VideoScaler` leaves to swscale's defaults, and
+/// crops, scales and letterboxes them according to a [`Layout`].
+pub(crate) struct ColorScaler {
+    ctx: *mut ffi::SwsContext,
+    input: ffi::AVPixelFormat,
+    output: ffi::AVPixelFormat,
+    layout: Layout,
+    frame: VideoFrame,
+}
+
+impl ColorScaler {
+    pub(crate) fn new(
+        layout: Layout,
+        input: AVPixelFormat,
+        color: &ColorFormat,
+        algorithm: ScaleAlgorithm,
+        time_base: Rational,
+    ) -> anyhow::Result<Self> {
+        let flags = match algorithm {
+            ScaleAlgorithm::Neighbor => ffi::SWS_POINT,
+            ScaleAlgorithm::Bilinear => ffi::SWS_BILINEAR,
+            ScaleAlgorithm::Bicubic => ffi::SWS_BICUBIC,
+            ScaleAlgorithm::Area => ffi::SWS_AREA,
+            ScaleAlgorithm::Lanczos => ffi::SWS_LANCZOS,
+            ScaleAlgorithm::Spline => ffi::SWS_SPLINE,
+        };
+        let Layout {
+            source, placement, ..
+        } = layout;
+        let ctx = unsafe {
+            ffi::sws_getContext(
+                source.width as _,
+                source.height as _,
+                input.into(),
+                placement.width as _,
+                placement.height as _,
+                color.pixel_format.into(),
+                flags as _,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null(),
+            )
+        };
+        if ctx.is_null() {
+            anyhow::bail!("unable to create pixel format converter");
+        }
+        let matrix = match (color.hdr10, color.matrix) {
+            (true, _) => ffi::SWS_CS_BT2020,
+            (false, ColorMatrix::Bt601) => ffi::SWS_CS_ITU601,
+            (false, ColorMatrix::Bt709) => ffi::SWS_CS_ITU709,
+        };
+        let full = (color.range == ColorRange::Full) as i32;
+        unsafe {
+            let table = ffi::sws_getCoefficients(matrix as _);
+            // The source is full range RGB, for which the matrix is unused.
+            ffi::sws_setColorspaceDetails(ctx, table, 1, table, full, 0, 1 << 16, 1 << 16);
+        }
+        let frame = VideoFrame::builder()
+            .width(layout.output.width as _)
+            .height(layout.output.height as _)
+            .pix_fmt(color.pixel_format)
+            .time_base(time_base)
+            .build();
+        let mut frame = match frame {
+            Ok(frame) => frame,
+            Err(e) => {
+                unsafe { ffi::sws_freeContext(ctx) };
+                Err(e)?
+            }
+        };
+        // Bars around the placement are never written again.
+        unsafe {
+            let f = frame.as_mut_ptr();
+            let linesize = (*f).linesize.map(|l| l as isize);
+            let range = match color.range {
+                ColorRange::Limited => ffi::AVCOL_RANGE_MPEG,
+                ColorRange::Full => ffi::AVCOL_RANGE_JPEG,
+            };
+            ffi::av_image_fill_black(
+                (*f).data.as_ptr().cast(),
+                linesize.as_ptr(),
+                color.pixel_format.into(),
+                range,
+                layout.output.width as _,
+                layout.output.height as _,
+            );
+        }
+        Ok(Self {
+            ctx,
+            input: input.into(),
+            output: color.pixel_format.into(),
+            layout,
+            frame,
+        })
+    }
+
+    /// Converts a captured image of the input format, whose rows are
+    /// `stride` bytes apart in `data`, straight into the output frame.
+    pub(crate) fn process(
+        &mut self,
+        data: &[u8],
+        stride: usize,
+        pts: i64,
+    ) -> anyhow::Result<&mut VideoFrame> {
+        let Layout {
+            source, placement, ..
+        } = self.layout;
+        if data.len() < (source.y + source.height) as usize * stride {
+            anyhow::bail!("captured image smaller than {source}");
+        }
+        unsafe {
+            // bytes in front of column `x` of the single packed plane
+            let mut x_bytes = [0; 4];
+            ffi::av_image_fill_linesizes(x_bytes.as_mut_ptr(), self.input, source.x as _);
+            let offset = source.y as usize * stride + x_bytes[0] as usize;
+            let src_planes = [
+                data.as_ptr().add(offset),
+                ptr::null(),
+                ptr::null(),
+                ptr::null(),
+            ];
+            let src_strides = [stride as i32, 0, 0, 0];
+            let dst = self.frame.as_mut_ptr();
+            let dst_planes = planes_at(dst, self.output, placement.x, placement.y);
+            let ret = ffi::sws_scale(
+                self.ctx,
+                src_planes.as_ptr(),
+                src_strides.as_ptr(),
+                0,
+                source.height as _,
+                dst_planes.as_ptr(),
+                (*dst).linesize.as_ptr(),
+            );
+            if ret < 0 {
+                anyhow::bail!("pixel format conversion failed (error {ret})");
+            }
+        }
+        self.frame.set_pts(Some(pts));
+        Ok(&mut self.frame)
+    }
+}
+
+/// Plane pointers of `frame` moved to pixel (`x`, `y`), which must be even
+/// for chroma subsampled formats.
+unsafe fn planes_at(
+    frame: *const ffi::AVFrame,
+    format: ffi::AVPixelFormat,
+    x: u32,
+    y: u32,
+) -> [*mut u8; 4] {
+    unsafe {
+        let desc = ffi::av_pix_fmt_desc_get(format);
+        // bytes in front of column `x` in each plane
+        let mut x_bytes = [0; 4];
+        ffi::av_image_fill_linesizes(x_bytes.as_mut_ptr(), format, x as _);
+        array::from_fn(|i| {
+            let data = (*frame).data[i];
+            if data.is_null() {
+                return data;
+            }
+            let rows = if i == 1 || i == 2 {
+                y >> (*desc).log2_chroma_h
+            } else {
+                y
+            };
+            data.offset(rows as isize * (*frame).linesize[i] as isize + x_bytes[i] as isize)
+        })
+    }
+}
+
+impl Drop for ColorScaler {
+    fn drop(&mut self) {
+        unsafe { ffi::sws_freeContext(self.ctx) };
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+use recordin_common::config::SegmentLimit;
+
+use crate::output::muxer::Muxer;
+
+/// Names the files of a video output and decides when one is complete.
+/// Without a limit the whole recording goes to `base`.
+pub(crate) struct Segments {
+    base: PathBuf,
+    frames: Option<u64>,
+    ticks: Option<i64>,
+    bytes: Option<u64>,
+    fps: f64,
+    index: u32,
+    /// One line per segment, written as soon as it is opened so that the
+    /// list is complete even if the game crashes.
+    manifest: Option<File>,
+}
+
+impl Segments {
+    pub(crate) fn new(
+        base: PathBuf,
+        limit: Option<SegmentLimit>,
+        fps: f64,
+    ) -> anyhow::Result<Self> {
+        let (mut frames, mut ticks, mut bytes) = (None, None, None);
+        match limit {
+            None => {}
+            Some(SegmentLimit::Frames(n)) => frames = Some(n),
+            // Counted in virtual time, as dropped frames leave gaps between
+            // the ticks of those written.
+            Some(SegmentLimit::Duration(secs)) => ticks = Some((secs * fps).round().max(1.) as i64),
+            Some(SegmentLimit::Size(n)) => bytes = Some(n),
+        }
+        let manifest = match limit {
+            Some(_) => {
+                let path = sibling(&base, ".segments", "csv")?;
+                log::trace!("Segment manifest: \n{path:?}");
+                let mut file = File::create(path)?;
+                writeln!(file, "file,start_frame,start_time")?;
+                Some(file)
+            }
+            None => None,
+        };
+        Ok(Self {
+            base,
+            frames,
+            ticks,
+            bytes,
+            fps,
+            index: 0,
+            manifest,
+        })
+    }
+
+    /// Path of the next file, starting at virtual frame `start_tick`.
+    pub(crate) fn next(&mut self, start_tick: i64) -> anyhow::Result<PathBuf> {
+        let Some(manifest) = &mut self.manifest else {
+            log::trace!("Video output: \n{:?}", self.base);
+            return Ok(self.base.clone());
+        };
+        let ext = self.base.extension().unwrap_or_default().to_string_lossy();
+        let path = sibling(&self.base, &format!("-{:03}", self.index), &ext)?;
+        self.index += 1;
+        log::trace!("Video segment: \n{path:?}");
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let time = start_tick as f64 / self.fps;
+        writeln!(manifest, "{name},{start_tick},{time:.6}")?;
+        Ok(path)
+    }
+
+    /// Whether a segment of `frames` frames spanning `ticks` virtual frames,
+    /// written through `muxer`, is done.
+    pub(crate) fn is_full(&self, frames: u64, ticks: i64, muxer: &mut Muxer) -> bool {
+        self.frames.is_some_and(|n| frames >= n)
+            || self.ticks.is_some_and(|n| ticks >= n)
+            || self
+                .bytes
+                .is_some_and(|n| frames > 0 && muxer.bytes_written() >= n)
+    }
+}
+
+/// `base` with `suffix` appended to its stem and extension `ext`.
+fn sibling(base: &Path, suffix: &str, ext: &str) -> anyhow::Result<PathBuf> {
+    let mut stem = base
+        .file_stem()
+        .ok_or(anyhow::anyhow!("must have a file name"))?
+        .to_owned();
+    stem.push(suffix);
+    let mut path = base.with_file_name(stem);
+    if !ext.is_empty() {
+        path.set_extension(ext);
+    }
+    Ok(path)
+}
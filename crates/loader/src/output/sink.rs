@@ -0,0 +1,440 @@
+use std::{
+    path::{
+        Path,
+        PathBuf,
+    },
+    slice::ChunksExact,
+    sync::{
+        Arc,
+        atomic::{
+            AtomicU32,
+            Ordering,
+        },
+    },
+};
+
+use parking_lot::Mutex;
+use recordin_common::{
+    color::{
+        Converter,
+        Depth,
+        Transfer,
+    },
+    config::{
+        Backpressure,
+        SinkConfig,
+    },
+    geometry::Size,
+};
+
+use crate::{
+    env,
+    hook::timing,
+    output::{
+        audio_codec::AudioOutput,
+        dump::{
+            RawAudio,
+            RawVideo,
+        },
+        finalize::{
+            self,
+            Active,
+        },
+        image_sequence::ImageSequence,
+        muxer,
+        pipe::{
+            AudioPipe,
+            VideoPipe,
+        },
+        queue::{
+            self,
+            Buffer,
+            Drain,
+            Queue,
+        },
+        video_codec::VideoOutput,
+    },
+};
+
+pub(crate) type VideoQueue = Queue<CapturedFrame>;
+
+pub(crate) type AudioQueue = Queue<Vec<u8>>;
+
+/// Packed RGB of one presented image, passed back and forth between the
+/// graphics hooks and the video output thread. The hooks convert the mapped
+/// image into it once, and the scaler reads it in place as the source of the
+/// encoder's frame, so there is no copy in between.
+#[derive(Default)]
+pub(crate) struct CapturedFrame {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) depth: Depth,
+    /// sRGB, or HDR10 when HDR is kept.
+    pub(crate) transfer: Transfer,
+    /// Rows of `stride` bytes without padding.
+    pub(crate) pixels: Vec<u8>,
+}
+
+impl CapturedFrame {
+    /// Makes room for an image of the given size as `converter` outputs it.
+    pub(crate) fn resize(&mut self, width: usize, height: usize, converter: &Converter) {
+        self.width = width;
+        self.height = height;
+        self.depth = converter.depth();
+        self.transfer = converter.transfer();
+        self.pixels.resize(self.stride() * height, 0);
+    }
+
+    pub(crate) fn stride(&self) -> usize {
+        self.width * self.depth.bytes_per_pixel()
+    }
+
+    /// FFmpeg name of the pixel format.
+    pub(crate) fn format_name(&self) -> &'static str {
+        match self.depth {
+            Depth::Eight => "rgb24",
+            Depth::Sixteen => "rgb48le",
+        }
+    }
+
+    pub(crate) fn rows(&self) -> ChunksExact<'_, u8> {
+        self.pixels.chunks_exact(self.stride().max(1))
+    }
+}
+
+impl Buffer for CapturedFrame {
+    fn bytes(&self) -> usize {
+        self.pixels.capacity()
+    }
+}
+
+/// Something captured frames are written to. Sinks are created on the video
+/// output thread and never leave it.
+pub(crate) trait VideoSink {
+    /// Takes the frame presented at virtual frame `tick`.
+    fn push(&mut self, tick: i64, frame: &CapturedFrame) -> anyhow::Result<()>;
+
+    /// Completes the output after the last frame.
+    fn finish(self: Box<Self>) -> anyhow::Result<()>;
+}
+
+/// Something rendered audio, interleaved stereo `f32` at 48 kHz, is written
+/// to, living on the audio output thread.
+pub(crate) trait AudioSink {
+    fn push(&mut self, samples: &[u8]) -> anyhow::Result<()>;
+
+    fn finish(self: Box<Self>) -> anyhow::Result<()>;
+}
+
+/// Discards everything.
+pub(crate) struct Null;
+
+impl VideoSink for Null {
+    fn push(&mut self, _tick: i64, _frame: &CapturedFrame) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+impl AudioSink for Null {
+    fn push(&mut self, _samples: &[u8]) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Hands everything to several sinks. An extra one that fails is dropped
+/// with a warning so that the others keep recording, while the encoded
+/// output the recording is for ends it.
+pub(crate) struct Tee<S: ?Sized> {
+    sinks: Vec<(&'static str, Box<S>)>,
+    primary: Option<&'static str>,
+}
+
+impl<S: ?Sized> Tee<S> {
+    fn new() -> Self {
+        Self {
+            sinks: Vec::new(),
+            primary: None,
+        }
+    }
+
+    fn add(&mut self, name: &'static str, sink: anyhow::Result<Box<S>>) {
+        match sink {
+            Ok(sink) => self.sinks.push((name, sink)),
+            Err(e) => log::warn!("{name} output not created: {e}"),
+        }
+    }
+
+    fn add_primary(
+        &mut self,
+        name: &'static str,
+        sink: anyhow::Result<Box<S>>,
+    ) -> anyhow::Result<()> {
+        let sink = sink.map_err(|e| anyhow::anyhow!("{name} output not created: {e}"))?;
+        self.sinks.push((name, sink));
+        self.primary = Some(name);
+        Ok(())
+    }
+
+    fn each(&mut self, mut f: impl FnMut(&mut S) -> anyhow::Result<()>) -> anyhow::Result<()> {
+        let mut failed = Ok(());
+        self.sinks.retain_mut(|(name, sink)| match f(sink) {
+            Ok(()) => true,
+            Err(e) if self.primary == Some(*name) => {
+                failed = Err(anyhow::anyhow!("{name} output failed: {e}"));
+                false
+            }
+            Err(e) => {
+                log::warn!("{name} output failed, dropping it: {e}");
+                false
+            }
+        });
+        failed?;
+        if self.sinks.is_empty() {
+            anyhow::bail!("every output failed");
+        }
+        Ok(())
+    }
+
+    fn finish_all(self, f: impl Fn(Box<S>) -> anyhow::Result<()>) -> anyhow::Result<()> {
+        let mut result = Ok(());
+        for (name, sink) in self.sinks {
+            if let Err(e) = f(sink) {
+                log::warn!("{name} output not finished: {e}");
+                result = Err(e);
+            }
+        }
+        result
+    }
+}
+
+impl VideoSink for Tee<dyn VideoSink> {
+    fn push(&mut self, tick: i64, frame: &CapturedFrame) -> anyhow::Result<()> {
+        self.each(|sink| sink.push(tick, frame))
+    }
+
+    fn finish(self: Box<Self>) -> anyhow::Result<()> {
+        self.finish_all(|sink| sink.finish())
+    }
+}
+
+impl AudioSink for Tee<dyn AudioSink> {
+    fn push(&mut self, samples: &[u8]) -> anyhow::Result<()> {
+        self.each(|sink| sink.push(samples))
+    }
+
+    fn finish(self: Box<Self>) -> anyhow::Result<()> {
+        self.finish_all(|sink| sink.finish())
+    }
+}
+
+/// The video output of a continuous recording, shared by every swap chain
+/// of the session and finished only when the game exits.
+static CONTINUOUS: Mutex<Option<Arc<VideoQueue>>> = Mutex::new(None);
+
+/// Whether every swap chain records into the same output.
+fn continuous() -> bool {
+    env::video().is_some_and(|v| v.continuous)
+}
+
+/// The queue a new swap chain hands its images of the given size to: the
+/// session's output when recording continuously, or an output of its own
+/// that finishes once the swap chain drops it.
+pub(crate) fn video_queue(
+    width: usize,
+    height: usize,
+    converter: &Converter,
+) -> Option<Arc<VideoQueue>> {
+    if !continuous() {
+        return create_video(width, height, converter).map(Arc::new);
+    }
+    let mut current = CONTINUOUS.lock();
+    if current.is_none() {
+        *current = create_video(width, height, converter).map(Arc::new);
+    }
+    current.clone()
+}
+
+/// Every configured output of captured frames.
+fn video_sinks(captured: Size, transfer: Transfer) -> anyhow::Result<Tee<dyn VideoSink>> {
+    let mut tee = Tee::<dyn VideoSink>::new();
+    if let Some(video) = env::video() {
+        let sink = VideoOutput::new(video, captured, transfer);
+        tee.add_primary("Video", sink.map(|s| Box::new(s) as _))?;
+    }
+    if let Some(images) = env::images() {
+        tee.add("Image", Ok(Box::new(ImageSequence::new(images))));
+    }
+    for sink in env::sinks() {
+        match sink {
+            SinkConfig::RawVideo(path) => {
+                tee.add(
+                    "Raw video",
+                    RawVideo::create(path).map(|s| Box::new(s) as _),
+                );
+            }
+            SinkConfig::VideoPipe(target) => {
+                tee.add(
+                    "Video pipe",
+                    VideoPipe::open(target, true).map(|s| Box::new(s) as _),
+                );
+            }
+            SinkConfig::RawVideoPipe(target) => {
+                tee.add(
+                    "Raw video pipe",
+                    VideoPipe::open(target, false).map(|s| Box::new(s) as _),
+                );
+            }
+            SinkConfig::Null => tee.add("Null video", Ok(Box::new(Null))),
+            SinkConfig::RawAudio(_) | SinkConfig::AudioPipe(_) => {}
+        }
+    }
+    if tee.sinks.is_empty() {
+        anyhow::bail!("no video output could be created");
+    }
+    Ok(tee)
+}
+
+/// Every configured output of rendered audio.
+fn audio_sinks(start_tick: i64) -> anyhow::Result<Tee<dyn AudioSink>> {
+    let mut tee = Tee::<dyn AudioSink>::new();
+    if let Some(audio) = env::audio() {
+        let sink = AudioOutput::new(audio, start_tick);
+        tee.add_primary("Audio", sink.map(|s| Box::new(s) as _))?;
+    }
+    for sink in env::sinks() {
+        match sink {
+            SinkConfig::RawAudio(path) => {
+                tee.add(
+                    "Raw audio",
+                    RawAudio::create(path).map(|s| Box::new(s) as _),
+                );
+            }
+            SinkConfig::AudioPipe(target) => {
+                tee.add(
+                    "Audio pipe",
+                    AudioPipe::open(target, start_tick).map(|s| Box::new(s) as _),
+                );
+            }
+            SinkConfig::Null => tee.add("Null audio", Ok(Box::new(Null))),
+            SinkConfig::RawVideo(_) | SinkConfig::VideoPipe(_) | SinkConfig::RawVideoPipe(_) => {}
+        }
+    }
+    if tee.sinks.is_empty() {
+        anyhow::bail!("no audio output could be created");
+    }
+    Ok(tee)
+}
+
+/// Starts the thread feeding captured frames to the video sinks, if any is
+/// configured, for images of the given size converted by `converter`.
+fn create_video(width: usize, height: usize, converter: &Converter) -> Option<VideoQueue> {
+    let wanted = env::video().is_some()
+        || env::images().is_some()
+        || env::sinks().iter().any(SinkConfig::takes_video);
+    if !wanted || finalize::shutting_down() {
+        return None;
+    }
+    let active = Active::register();
+    let start_tick = timing::ticks();
+    let (depth, transfer) = (converter.depth(), converter.transfer());
+    let buffers = (0..10).map(|_| CapturedFrame {
+        width,
+        height,
+        depth,
+        transfer,
+        pixels: Vec::with_capacity(width * height * depth.bytes_per_pixel()),
+    });
+    let (queue, drain) = queue::queue("Video", env::backpressure(), start_tick, buffers);
+    std::thread::spawn(move || {
+        let _active = active;
+        let captured = Size::new(width as _, height as _);
+        match loop_video(captured, transfer, drain) {
+            Ok(_) => {
+                log::info!("Video output successfully completed");
+            }
+            Err(e) => {
+                log::warn!("Video output error: {}", e);
+                if muxer::merging() {
+                    muxer::video_failed();
+                }
+            }
+        }
+    });
+    Some(queue)
+}
+
+/// Starts the thread feeding rendered audio to the audio sinks, if any is
+/// configured.
+pub(crate) fn create_audio() -> Option<AudioQueue> {
+    let wanted = env::audio().is_some() || env::sinks().iter().any(SinkConfig::takes_audio);
+    if !wanted || finalize::shutting_down() {
+        return None;
+    }
+    let active = Active::register();
+    let start_tick = timing::ticks();
+    // Dropping audio would shift everything after it, so it always waits.
+    let buffers = (0..10).map(|_| Vec::new());
+    let (queue, drain) = queue::queue("Audio", Backpressure::Block, 0, buffers);
+    std::thread::spawn(move || {
+        let _active = active;
+        match loop_audio(start_tick, drain) {
+            Ok(_) => {
+                log::info!("Audio output successfully completed");
+            }
+            Err(e) => {
+                log::warn!("Audio output error: {}", e);
+            }
+        }
+    });
+    Some(queue)
+}
+
+fn loop_video(
+    captured: Size,
+    transfer: Transfer,
+    mut drain: Drain<CapturedFrame>,
+) -> anyhow::Result<()> {
+    let mut sinks = video_sinks(captured, transfer)?;
+    while let Some(buf) = drain.recv() {
+        sinks.push(buf.tick, &buf)?;
+        drain.release(buf);
+    }
+    drain.report();
+    Box::new(sinks).finish()
+}
+
+fn loop_audio(start_tick: i64, mut drain: Drain<Vec<u8>>) -> anyhow::Result<()> {
+    let mut sinks = audio_sinks(start_tick)?;
+    while let Some(buf) = drain.recv() {
+        sinks.push(&buf)?;
+        drain.release(buf);
+    }
+    drain.report();
+    Box::new(sinks).finish()
+}
+
+/// `path` numbered by how many outputs `counter` has counted before.
+pub(super) fn numbered_path(path: &Path, counter: &AtomicU32) -> anyhow::Result<PathBuf> {
+    let mut new_path = path.to_owned();
+    let mut stem = path
+        .file_stem()
+        .ok_or(anyhow::anyhow!("must have a file name"))?
+        .to_owned();
+    let c = counter.fetch_add(1, Ordering::Relaxed);
+    if c != 0 {
+        stem.push(c.to_string());
+    }
+    new_path.set_file_name(stem);
+    if let Some(ext) = path.extension() {
+        new_path.set_extension(ext);
+    }
+    Ok(new_path)
+}
@@ -1,14 +1,22 @@
 use std::{
-    cell::LazyCell,
-    collections::BTreeMap,
     fs::File,
     num::NonZero,
-    sync::atomic::{
-        AtomicU32,
-        Ordering,
-    },
+    path::Path,
+    sync::atomic::AtomicU32,
 };
 
+use recordin_common::{
+    color::{
+        Depth,
+        Transfer,
+    },
+    config::VideoConfig,
+    geometry::{
+        Layout,
+        Size,
+    },
+    suggest,
+};
 use scuffle_ffmpeg::{
     AVPixelFormat,
     codec::EncoderCodec,
@@ -17,168 +25,276 @@ use scuffle_ffmpeg::{
         Encoder,
         VideoEncoderSettings,
     },
+    ffi,
     frame::VideoFrame,
-    io::{
-        Output,
-        OutputOptions,
-    },
+    io::Output,
     rational::Rational,
-    scaler::VideoScaler,
 };
 
-use crate::env;
+use crate::{
+    env,
+    output::{
+        audio_codec,
+        container::Container,
+        muxer::{
+            self,
+            Muxer,
+            SharedOutput,
+        },
+        probe,
+        scaler::{
+            ColorFormat,
+            ColorScaler,
+        },
+        segment::Segments,
+        sink::{
+            self,
+            CapturedFrame,
+            VideoSink,
+        },
+    },
+};
 
 pub(crate) static SURFACE_COUNTER: AtomicU32 = AtomicU32::new(0);
 
-pub(crate) type EncDuplex = (kanal::Sender<Vec<[u8; 3]>>, kanal::Receiver<Vec<[u8; 3]>>);
+/// The encoded video, written as one file or a series of segments.
+pub(crate) struct VideoOutput {
+    video: &'static VideoConfig,
+    container: Container,
+    color: ColorFormat,
+    layout: Layout,
+    fps: f64,
+    merging: bool,
+    segments: Option<Segments>,
+    segment: Option<Segment>,
+    /// Scaler for the current capture size and depth, rebuilt when a
+    /// continuous output receives other frames.
+    input: Option<(Size, Depth, ColorScaler)>,
+}
 
-pub(crate) fn create_encoder(width: usize, height: usize) -> Option<EncDuplex> {
-    env::should_emit_video().then_some({})?;
-    let path = env::VIDEO_OUTPUT.as_ref()?.clone();
-    let (tx1, rx1) = kanal::bounded(30);
-    let (tx2, rx2) = kanal::bounded(30);
-    for _ in 0..10 {
-        tx2.send(Vec::with_capacity(width * height)).unwrap();
+impl VideoOutput {
+    pub(crate) fn new(
+        video: &'static VideoConfig,
+        captured: Size,
+        transfer: Transfer,
+    ) -> anyhow::Result<Self> {
+        log::trace!("Video encoder: {}", video.encoder);
+        log::info!("ffmpeg arguments:\n{:?}", video.options);
+        let container = Container::resolve(
+            &video.output,
+            video.container.as_deref(),
+            &video.container_options,
+            &video.encoder,
+            env::recoverable(),
+        )?;
+        let color = ColorFormat::resolve(video)?.for_transfer(transfer);
+        log::trace!("Video format: {color:?}");
+        let layout = Layout::new(captured, video.crop, video.scale, video.fit)
+            .ok_or(anyhow::anyhow!("captured frame {captured} is empty"))?;
+        log::trace!("Video layout: {layout:?}");
+        log::trace!(
+            "Video container: {} {:?}",
+            container.name,
+            container.options
+        );
+        Ok(Self {
+            video,
+            container,
+            color,
+            layout,
+            fps: env::FPS.get(),
+            merging: muxer::merging(),
+            segments: None,
+            segment: None,
+            input: None,
+        })
     }
-    std::thread::spawn(move || {
-        match loop_encode(width, height, rx1, tx2, move || {
-            let mut new_path = path.clone();
-            let mut stem = path
-                .file_stem()
-                .ok_or(anyhow::anyhow!("must have a file name"))?
-                .to_owned();
-            let c = SURFACE_COUNTER.fetch_add(1, Ordering::Relaxed);
-            if c != 0 {
-                stem.push(c.to_string());
-            }
-            new_path.set_file_name(stem);
-            if let Some(ext) = path.extension() {
-                new_path.set_extension(ext);
-            }
-            log::trace!("Video output: \n{:?}", path);
-            Ok(File::create(path)?)
-        }) {
-            Ok(_) => {
-                log::info!("Video encoder successfully completed");
-            }
-            Err(e) => {
-                log::warn!("Video encoder error: {}", e);
+
+    fn open(&self, path: &Path, first_tick: i64) -> anyhow::Result<Segment> {
+        let mut output = self.container.open(path, File::create(path)?)?;
+        let encoder = new_encoder(
+            &mut output,
+            self.video,
+            &self.color,
+            self.layout.output,
+            self.fps,
+        )?;
+        let audio = match env::audio() {
+            Some(audio) if self.merging => Some(audio_codec::new_encoder(&mut output, audio)?),
+            _ => None,
+        };
+        self.container.write_header(&mut output)?;
+        let muxer = match audio {
+            Some(audio) => Muxer::Shared(SharedOutput::publish(output, audio, first_tick)),
+            None => Muxer::Own(output),
+        };
+        Ok(Segment {
+            muxer,
+            encoder,
+            frames: 0,
+            start_tick: first_tick,
+            end_tick: first_tick,
+        })
+    }
+}
+
+impl VideoSink for VideoOutput {
+    fn push(&mut self, tick: i64, buf: &CapturedFrame) -> anyhow::Result<()> {
+        let video = self.video;
+        if self.segments.is_none() {
+            let path = sink::numbered_path(&video.output, &SURFACE_COUNTER)?;
+            self.segments = Some(Segments::new(path, video.segment, self.fps)?);
+        }
+        let segments = self.segments.as_mut().unwrap();
+        if let Some(full) = self
+            .segment
+            .take_if(|s| segments.is_full(s.frames, tick - s.start_tick, &mut s.muxer))
+        {
+            full.finish()?;
+        }
+        if self.segment.is_none() {
+            let path = segments.next(tick)?;
+            self.segment = Some(self.open(&path, tick)?);
+        }
+        let seg = self.segment.as_mut().unwrap();
+        let size = Size::new(buf.width as _, buf.height as _);
+        if self
+            .input
+            .as_ref()
+            .is_none_or(|(current, depth, _)| (*current, *depth) != (size, buf.depth))
+        {
+            let output = self.layout.output;
+            if self.input.is_some() {
+                log::debug!("Capture resized to {size}, fitting into {output}");
             }
+            let Some(layout) = Layout::new(size, video.crop, Some(output), video.fit) else {
+                log::debug!("Skipping empty capture {size}");
+                return Ok(());
+            };
+            let input = match buf.depth {
+                Depth::Eight => AVPixelFormat::Rgb24,
+                Depth::Sixteen => AVPixelFormat::from(ffi::AV_PIX_FMT_RGB48LE),
+            };
+            let scaler = ColorScaler::new(
+                layout,
+                input,
+                &self.color,
+                video.scale_algorithm,
+                seg.encoder.incoming_time_base(),
+            )?;
+            self.input = Some((size, buf.depth, scaler));
+        }
+        let (.., sc) = self.input.as_mut().unwrap();
+        // Frames are numbered by virtual time from the start of each file,
+        // like the samples of audio sharing it.
+        let frame = sc.process(&buf.pixels, buf.stride(), tick - seg.start_tick)?;
+        seg.end_tick = tick + 1;
+        seg.encode(frame)
+    }
+
+    fn finish(mut self: Box<Self>) -> anyhow::Result<()> {
+        match self.segment.take() {
+            Some(last) => last.finish(),
+            None => Ok(()),
         }
-    });
-    Some((tx1, rx2))
+    }
 }
 
-fn loop_encode(
-    width: usize,
-    height: usize,
-    rx: kanal::Receiver<Vec<[u8; 3]>>,
-    tx: kanal::Sender<Vec<[u8; 3]>>,
-    lazy_file: impl FnOnce() -> anyhow::Result<File> + 'static,
-) -> anyhow::Result<()> {
-    let fps = env::FPS.get();
-    let encode_codec_name = env::VIDEO_ENCODER
-        .get()
-        .ok_or(anyhow::anyhow!("Video encoder not set"))?;
-    log::trace!("Video encoder: {}", encode_codec_name);
-    let args = &env::VIDEO_ARGS.as_ref().unwrap_or_else(|| {
-        static EMPTY: BTreeMap<String, String> = BTreeMap::new();
-        &EMPTY
-    });
-    struct LazyGroup {
-        output: Output<File>,
-        encoder: Encoder,
-        frame: VideoFrame,
-        scaler: VideoScaler,
+impl Drop for VideoOutput {
+    fn drop(&mut self) {
+        // Left open by an error: end the file so that the audio stops at it
+        // and its trailer is written.
+        if let Some(Err(e)) = self.segment.take().map(Segment::finish) {
+            log::warn!("Failed to finish video output: {e}");
+        }
     }
-    let mut lazy_group = LazyCell::new(|| {
-        let writer = lazy_file()?;
-        let mut output = Output::seekable(
-            writer,
-            OutputOptions::builder().format_name("Matroska")?.build(),
-        )?;
-        let codec = EncoderCodec::by_name(&encode_codec_name)
-            .ok_or(anyhow::anyhow!("encoder {} not found", encode_codec_name))?;
-        let dict = Dictionary::try_from_iter(args.iter().map(|(k, v)| (k.as_str(), v.as_str())))?;
-        let approx_fps = num_rational::Ratio::approximate_float(fps).unwrap();
-        let approx_tbn = approx_fps.recip();
-        let video_settings = VideoEncoderSettings::builder()
-            .width(width as _)
-            .height(height as _)
-            .pixel_format(AVPixelFormat::Yuv420p)
-            .frame_rate(Rational::new(
-                *approx_fps.numer(),
-                NonZero::new(*approx_fps.denom()).unwrap(),
-            ))
-            .codec_specific_options(dict)
-            .build();
-        let encoder = Encoder::new(
-            codec,
-            &mut output,
-            Rational::new(
-                *approx_tbn.numer(),
-                NonZero::new(*approx_tbn.denom()).unwrap(),
-            ),
-            Rational::new(1, NonZero::new(1000).unwrap()),
-            video_settings,
-        )?;
-        output.write_header()?;
-        let frame = VideoFrame::builder()
-            .width(width as _)
-            .height(height as _)
-            .pix_fmt(AVPixelFormat::Rgb24)
-            .time_base(encoder.incoming_time_base())
-            .build()?;
-        let scaler = VideoScaler::new(
-            width as _,
-            height as _,
-            AVPixelFormat::Rgb24,
-            width as _,
-            height as _,
-            AVPixelFormat::Yuv420p,
-        )?;
-        let lazy = LazyGroup {
-            output,
-            encoder,
-            frame,
-            scaler,
-        };
-        anyhow::Ok(lazy)
-    });
-    let mut count = 0;
-    while let Ok(buf) = rx.recv() {
-        let LazyGroup {
-            output: out,
-            encoder: e,
-            frame: fr,
-            scaler: sc,
-        } = lazy_group.as_mut().map_err(|e| anyhow::anyhow!("{e}"))?;
-        let mut fr_data = fr.data_mut(0).unwrap();
-        for (h, row_in) in buf.chunks_exact(width).enumerate() {
-            let line = fr_data.get_row_mut(h).unwrap();
-            line.copy_from_slice(row_in.as_flattened());
+}
+
+/// One output file and the encoder writing into it.
+struct Segment {
+    muxer: Muxer,
+    encoder: Encoder,
+    frames: u64,
+    /// Virtual frame of the first frame.
+    start_tick: i64,
+    /// Virtual frame after the last one written.
+    end_tick: i64,
+}
+
+impl Segment {
+    fn encode(&mut self, frame: &VideoFrame) -> anyhow::Result<()> {
+        self.frames += 1;
+        self.encoder.send_frame(frame)?;
+        while let Some(packet) = self.encoder.receive_packet()? {
+            self.muxer.write(packet)?;
         }
-        tx.send(buf).ok();
-        fr.set_pts(Some(count));
-        count += 1;
-        let yuv = sc.process(fr)?;
-        e.send_frame(yuv)?;
-        while let Some(packet) = e.receive_packet()? {
-            out.write_interleaved_packet(packet)?;
+        if let Muxer::Shared(shared) = &self.muxer {
+            shared.video_written(self.end_tick);
         }
+        Ok(())
     }
-    if count > 0
-        && let Ok(LazyGroup {
-            output: out,
-            encoder: e,
-            ..
-        }) = lazy_group.as_mut()
-    {
-        e.send_eof()?;
-        while let Some(packet) = e.receive_packet()? {
-            out.write_interleaved_packet(packet)?;
+
+    fn finish(mut self) -> anyhow::Result<()> {
+        self.encoder.send_eof()?;
+        while let Some(packet) = self.encoder.receive_packet()? {
+            self.muxer.write(packet)?;
+        }
+        if let Muxer::Shared(shared) = &self.muxer {
+            shared.end_video(self.end_tick)?;
         }
-        out.write_trailer()?;
+        self.muxer.finish()
+    }
+}
+
+pub(super) fn new_encoder<T: Send + Sync>(
+    output: &mut Output<T>,
+    video: &VideoConfig,
+    color: &ColorFormat,
+    size: Size,
+    fps: f64,
+) -> anyhow::Result<Encoder> {
+    let codec = EncoderCodec::by_name(&video.encoder).ok_or_else(|| {
+        let names = probe::encoder_names(ffi::AVMEDIA_TYPE_VIDEO);
+        anyhow::anyhow!(
+            "encoder {} not found{}",
+            video.encoder,
+            suggest::did_you_mean(&video.encoder, names.iter().map(String::as_str))
+        )
+    })?;
+    let unknown = probe::unknown_options(&video.encoder, video.options.keys());
+    if !unknown.is_empty() {
+        anyhow::bail!(
+            "encoder {} has no option {}",
+            video.encoder,
+            unknown.join(", ")
+        );
     }
-    Ok(())
+    let options = color
+        .tags()
+        .into_iter()
+        .chain(video.options.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+    let dict = Dictionary::try_from_iter(options)?;
+    let approx_fps = num_rational::Ratio::approximate_float(fps).unwrap();
+    let approx_tbn = approx_fps.recip();
+    let video_settings = VideoEncoderSettings::builder()
+        .width(size.width as _)
+        .height(size.height as _)
+        .pixel_format(color.pixel_format)
+        .frame_rate(Rational::new(
+            *approx_fps.numer(),
+            NonZero::new(*approx_fps.denom()).unwrap(),
+        ))
+        .codec_specific_options(dict)
+        .build();
+    let encoder = Encoder::new(
+        codec,
+        output,
+        Rational::new(
+            *approx_tbn.numer(),
+            NonZero::new(*approx_tbn.denom()).unwrap(),
+        ),
+        Rational::new(1, NonZero::new(1000).unwrap()),
+        video_settings,
+    )?;
+    Ok(encoder)
 }